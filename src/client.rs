@@ -8,27 +8,47 @@ use std::{
 use clap::{builder::Str, error, Error};
 use log::info;
 use log4rs::encode::json;
-use redis::{Commands, ConnectionLike, Script};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use tokio::time::{timeout, Duration};
 
-use crate::server::start;
+use crate::protocol::{self, Endpoint, RESPONSE_TTL_SECS};
+use crate::question::{Question, QuestionBank};
+use crate::queue::result::RedisResultSink;
+use crate::queue::{self, JobQueue, ResultSink};
+use crate::server;
 
 #[derive(Debug)]
 pub enum ErrorCode {
-    RedisInitErr(redis::RedisError),
-    RedisConnectErr(redis::RedisError),
+    QueueErr(queue::ErrorCode),
+    JudgeErr(String),
     DirectoryNotFound(String),
     ProcessTimeout,
     ProcessNotFinished,
 }
 
-impl ErrorCode {}
+impl ErrorCode {
+    pub fn get_err_msg(&self) -> String {
+        match self {
+            ErrorCode::QueueErr(err) => err.get_err_msg(),
+            ErrorCode::JudgeErr(err) => err.to_string(),
+            ErrorCode::DirectoryNotFound(dir) => format!("Directory not found: {}", dir),
+            ErrorCode::ProcessTimeout => String::from("Timeout"),
+            ErrorCode::ProcessNotFinished => String::from("Not finished"),
+        }
+    }
+}
+
+impl From<queue::ErrorCode> for ErrorCode {
+    fn from(err: queue::ErrorCode) -> Self {
+        ErrorCode::QueueErr(err)
+    }
+}
 
 static REDIS_CONNECTION_STR: &'static str = "redis://127.0.0.1";
 
 fn read_files_to_json(
+    redis_prefix: &str,
     p: String,
     solc_version: String,
     question_no: String,
@@ -47,7 +67,7 @@ fn read_files_to_json(
     send_obj["pathWithContent"] = serde_json::Value::Array(json);
     send_obj["judgeJobId"] = json!(job_id);
     send_obj["solcVersion"] = json!(solc_version);
-    send_obj["jobKey"] = json!(format!("smc-open-foundry-judge:{}", question_no));
+    send_obj["jobKey"] = json!(protocol::job_key(redis_prefix, &question_no));
 
     Ok(send_obj.to_string())
 }
@@ -58,17 +78,22 @@ pub fn request(
     question_no: String,
     job_id: String,
     timeout: String,
-    connection_str: String
+    endpoint: &Endpoint,
 ) -> Result<(), ErrorCode> {
     let start_time = SystemTime::now();
-    let get_conn_res = get_redis_conn(&connection_str);
-    if get_conn_res.is_err() {
-        // println!("Redis connection error");
-        return Err(get_conn_res.err().unwrap());
-    }
-    let mut conn = get_conn_res.unwrap();
-
-    let s = read_files_to_json(p, solc_version, question_no.clone(), job_id.clone());
+    let mut queue =
+        endpoint
+            .backend
+            .open(&endpoint.redis_host, &endpoint.redis_prefix, "client")?;
+    let mut sink = RedisResultSink::new(&endpoint.redis_host)?;
+
+    let s = read_files_to_json(
+        &endpoint.redis_prefix,
+        p,
+        solc_version,
+        question_no.clone(),
+        job_id.clone(),
+    );
     if s.is_err() {
         let mut json = json!({});
         json["info"] = json!("Failed");
@@ -82,20 +107,23 @@ pub fn request(
 
     let rt = tokio::runtime::Runtime::new().unwrap();
 
-    let request_key = format!("smc-open-foundry-judge:{}:request", &question_no);
-    let response_key = format!("smc-open-foundry-judge:{}:response", question_no);
+    let request_key = protocol::request_key(&endpoint.redis_prefix, &job_id);
+    let response_key = protocol::response_key(&endpoint.redis_prefix, &job_id);
 
     let res = rt.block_on(process_with_timeout(
         &Duration::from_secs(timeout.parse::<u64>().unwrap()),
         s.unwrap().as_str(),
-        &mut conn,
+        queue.as_mut(),
+        &mut sink,
         &request_key,
         &response_key,
     ));
 
     let mut json = json!({});
     if res.is_err() {
-        let _ = conn.del::<&str, i32>(&request_key);
+        let _ = sink.abandon(&request_key);
+        // Nobody waits for the job anymore, no need for a worker to keep judging it
+        let _ = server::cancel_job(&mut sink, &endpoint.redis_prefix, &job_id);
         json["info"] = json!("Timeout");
         json["code"] = json!(-2);
         json["msg"] = json!("Timeout");
@@ -114,23 +142,23 @@ pub fn request(
 async fn process_with_timeout(
     duration: &Duration,
     value: &str,
-    conn: &mut redis::Connection,
+    queue: &mut dyn JobQueue,
+    sink: &mut dyn ResultSink,
     request_key: &str,
     response_key: &str,
 ) -> Result<String, ErrorCode> {
     let async_operation = async move {
-        let _ = conn.set::<&str, &str, i32>(request_key, "");
-        let _ = conn.lpush::<&str, &str, i32>("test", value);
+        let _ = sink.expect(request_key, RESPONSE_TTL_SECS);
+        let _ = queue.submit(value);
 
         for _ in 0..80 {
-            let res = write_response_if_request_exist(&request_key, &response_key, conn);
-            if res.is_err() {
-                // println!("Not finished yet");
-            } else {
-                let data = res.unwrap();
-                if data.len() > 0 {
-                    return Ok(data.to_string());
+            let res = sink.take(request_key, response_key);
+            match res {
+                Ok(Some(data)) if !data.is_empty() => return Ok(data),
+                Ok(_) => {
+                    // println!("Not finished yet");
                 }
+                Err(err) => println!("{}", err.get_err_msg()),
             }
             tokio::time::sleep(Duration::from_millis(100)).await;
         }
@@ -154,39 +182,6 @@ async fn interact_with_host_service(
     println!("AAAAA");
 }
 
-fn write_response_if_request_exist(
-    request_key: &str,
-    response_key: &str,
-    conn: &mut redis::Connection,
-) -> Result<String, ErrorCode> {
-    let script = Script::new(
-        r#"
-    if redis.call("EXISTS", KEYS[2]) == 1 then
-        local value = redis.call("GET", KEYS[2])
-        redis.call("DEL", KEYS[1])
-        redis.call("DEL", KEYS[2])
-        return value
-    else
-        return nil
-    end
-  "#,
-    );
-
-    let res = script
-        .key(request_key)
-        .key(response_key)
-        .invoke::<Option<String>>(conn);
-    if res.is_err() {
-        println!("{}", res.as_ref().unwrap_err());
-    }
-
-    let res = res.unwrap();
-    match res {
-        None => return Err(ErrorCode::ProcessNotFinished),
-        Some(s) => return Ok(s),
-    }
-}
-
 fn recursive_get_string(
     base_path: &PathBuf,
     relative_path: PathBuf,
@@ -218,17 +213,53 @@ fn recursive_get_string(
     Ok(())
 }
 
-fn get_redis_conn(connection_str: &str) -> Result<Box<redis::Connection>, ErrorCode> {
-    let redis_client_result = redis::Client::open(connection_str);
+/* judge_local
 
-    if redis_client_result.is_err() {
-        return Err(ErrorCode::RedisInitErr(redis_client_result.unwrap_err()));
-    }
+   @dev Judges the directory in this process through the in-memory queue, no Redis or
+        server needed, the output has the same shape as `request`
 
-    let conn = redis_client_result.unwrap().get_connection();
-    if conn.is_err() {
-        return Err(ErrorCode::RedisConnectErr(conn.err().unwrap()));
-    }
+*/
+pub fn judge_local(
+    p: String,
+    solc_version: String,
+    question_no: String,
+    job_id: String,
+    worker_dir: String,
+) -> Result<(), ErrorCode> {
+    let start_time = SystemTime::now();
+    let s = read_files_to_json("local", p, solc_version, question_no, job_id)?;
+
+    let res = server::judge_locally(&s, &worker_dir, None)
+        .map_err(|err| ErrorCode::JudgeErr(err.get_err_msg()))?;
+    let mut json: serde_json::Value = serde_json::from_str(&res).unwrap();
+
+    let cost_time = SystemTime::now().duration_since(start_time).unwrap();
+    json["costTime"] = json!(format!("{:.2}s", cost_time.as_secs_f64()));
+    print!("{:#}", json);
+    Ok(())
+}
 
-    Ok(Box::new(conn.unwrap()))
+/* judge_reference_solution
+
+   @dev Judges the reference solution of the question like a submission, with the tests
+        of the bank, and returns the output
+
+*/
+pub fn judge_reference_solution(
+    bank: &QuestionBank,
+    question: &Question,
+    solc_version: &str,
+    worker_dir: &str,
+) -> Result<serde_json::Value, ErrorCode> {
+    let s = read_files_to_json(
+        "local",
+        question.solution_dir().to_string_lossy().into_owned(),
+        solc_version.to_string(),
+        question.question_no.clone(),
+        format!("validate-{}", question.question_no),
+    )?;
+
+    let res = server::judge_locally(&s, worker_dir, Some(bank.clone()))
+        .map_err(|err| ErrorCode::JudgeErr(err.get_err_msg()))?;
+    Ok(serde_json::from_str(&res).unwrap())
 }
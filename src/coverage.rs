@@ -0,0 +1,291 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde_json::{json, Value};
+
+use crate::question::{CoverageTarget, CoverageTargets};
+use crate::rubric;
+
+// Only the reference implementation is measured, not the tests or the libraries
+const MEASURED_DIR: &str = "contracts/";
+
+/* FileCoverage
+
+   @dev Lines and branches of a file of the reference implementation the tests ran,
+        `uncovered_lines` are the lines with code that never ran
+
+*/
+#[derive(Debug, Default)]
+pub struct FileCoverage {
+    pub path: String,
+    pub lines_found: usize,
+    pub lines_hit: usize,
+    pub branches_found: usize,
+    pub branches_hit: usize,
+    pub uncovered_lines: Vec<usize>,
+}
+
+/* parse_lcov
+
+   @dev Files of the lcov report `forge coverage --report lcov` wrote, relative to
+        `base_path`, the directory of the job. Lines are counted from the `DA` records
+        and branches from the `BRDA` ones, a branch whose block never ran (`-`) counts
+        as missed
+
+*/
+pub fn parse_lcov(report: &str, base_path: &Path) -> Vec<FileCoverage> {
+    let workspace = format!("{}/", base_path.display());
+    let canonical = base_path
+        .canonicalize()
+        .map(|path| format!("{}/", path.display()))
+        .unwrap_or_default();
+
+    let mut files = vec![];
+    let mut current: Option<(FileCoverage, BTreeMap<usize, bool>)> = None;
+    for line in report.lines() {
+        let line = line.trim();
+        if let Some(path) = line.strip_prefix("SF:") {
+            let path = path
+                .strip_prefix(&workspace)
+                .or_else(|| {
+                    path.strip_prefix(&canonical)
+                        .filter(|_| !canonical.is_empty())
+                })
+                .unwrap_or(path);
+            let file = FileCoverage {
+                path: path.to_string(),
+                ..FileCoverage::default()
+            };
+            current = Some((file, BTreeMap::new()));
+        } else if line == "end_of_record" {
+            if let Some((mut file, lines)) = current.take() {
+                file.lines_found = lines.len();
+                file.lines_hit = lines.values().filter(|hit| **hit).count();
+                file.uncovered_lines = lines
+                    .into_iter()
+                    .filter(|(_, hit)| !hit)
+                    .map(|(line, _)| line)
+                    .collect();
+                files.push(file);
+            }
+        } else if let Some((file, lines)) = current.as_mut() {
+            if let Some(record) = line.strip_prefix("DA:") {
+                let mut fields = record.split(',');
+                let number = fields.next().and_then(|n| n.parse().ok());
+                let hits = fields.next().and_then(|n| n.parse::<u64>().ok());
+                if let (Some(number), Some(hits)) = (number, hits) {
+                    // A line may be listed once per statement on it
+                    *lines.entry(number).or_insert(false) |= hits > 0;
+                }
+            } else if let Some(record) = line.strip_prefix("BRDA:") {
+                let taken = record.rsplit(',').next().unwrap_or("-");
+                file.branches_found += 1;
+                if taken.parse::<u64>().is_ok_and(|taken| taken > 0) {
+                    file.branches_hit += 1;
+                }
+            }
+        }
+    }
+    files.retain(|file| file.path.starts_with(MEASURED_DIR));
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    files
+}
+
+// Nothing measured covers nothing, a report without the reference files earns no score
+fn percent(hit: usize, found: usize) -> f64 {
+    if found == 0 {
+        0.0
+    } else {
+        hit as f64 * 100.0 / found as f64
+    }
+}
+
+// The score earned by reaching `percent` of a target, in proportion up to the target
+fn earned(target: &CoverageTarget, percent: f64) -> f64 {
+    if target.target <= 0.0 {
+        target.score
+    } else {
+        target.score * (percent / target.target).min(1.0)
+    }
+}
+
+fn measure(target: Option<&CoverageTarget>, hit: usize, found: usize) -> (f64, f64, Value) {
+    let percent = percent(hit, found);
+    let mut result = json!({
+        "found": found,
+        "hit": hit,
+        "percent": (percent * 100.0).round() / 100.0,
+    });
+    let Some(target) = target else {
+        return (0.0, 0.0, result);
+    };
+    let earned = if found == 0 {
+        result["msg"] = json!("Nothing of the reference solution was measured");
+        0.0
+    } else {
+        earned(target, percent)
+    };
+    result["target"] = json!(target.target);
+    result["score"] = rubric::score_json(target.score);
+    result["earned"] = rubric::score_json(earned);
+    (target.score, earned, result)
+}
+
+/* score
+
+   @dev Scores the coverage of the files against the targets of the question. Returns
+        the total, the score earned and the `coverage` of the result: lines and branches
+        over every file, then per file with its uncovered lines
+
+*/
+pub fn score(targets: &CoverageTargets, files: &[FileCoverage]) -> (f64, f64, Value) {
+    let sum = |count: fn(&FileCoverage) -> usize| files.iter().map(count).sum::<usize>();
+    let (lines_total, lines_earned, lines) = measure(
+        targets.lines.as_ref(),
+        sum(|f| f.lines_hit),
+        sum(|f| f.lines_found),
+    );
+    let (branches_total, branches_earned, branches) = measure(
+        targets.branches.as_ref(),
+        sum(|f| f.branches_hit),
+        sum(|f| f.branches_found),
+    );
+
+    let files: Vec<Value> = files
+        .iter()
+        .map(|file| {
+            json!({
+                "path": &file.path,
+                "lines": { "found": file.lines_found, "hit": file.lines_hit },
+                "branches": { "found": file.branches_found, "hit": file.branches_hit },
+                "uncoveredLines": &file.uncovered_lines,
+            })
+        })
+        .collect();
+    let coverage = json!({
+        "lines": lines,
+        "branches": branches,
+        "files": files,
+    });
+    (
+        lines_total + branches_total,
+        lines_earned + branches_earned,
+        coverage,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn targets(lines: Option<f64>, branches: Option<f64>) -> CoverageTargets {
+        let target = |target| CoverageTarget {
+            target,
+            score: 10.0,
+        };
+        CoverageTargets {
+            lines: lines.map(target),
+            branches: branches.map(target),
+        }
+    }
+
+    fn file(lines_found: usize, lines_hit: usize, branches: (usize, usize)) -> FileCoverage {
+        FileCoverage {
+            path: String::from("contracts/Vault.sol"),
+            lines_found,
+            lines_hit,
+            branches_found: branches.0,
+            branches_hit: branches.1,
+            uncovered_lines: vec![],
+        }
+    }
+
+    #[test]
+    fn reports_are_read_per_file_of_the_reference() {
+        let base_path = Path::new("/work/00/q1");
+        let report = "TN:
+SF:/work/00/q1/contracts/Vault.sol
+FN:4,Vault.deposit
+DA:5,3
+DA:6,3
+DA:6,0
+DA:9,0
+BRDA:6,0,0,3
+BRDA:6,0,1,-
+BRDA:9,1,0,0
+BRF:3
+BRH:1
+LF:4
+LH:2
+end_of_record
+TN:
+SF:/work/00/q1/contracts/Empty.sol
+LF:0
+LH:0
+end_of_record
+SF:/work/00/q1/test/Vault.t.sol
+DA:3,1
+LF:1
+LH:1
+end_of_record
+SF:lib/forge-std/src/Test.sol
+DA:1,0
+end_of_record
+";
+        let files = parse_lcov(report, base_path);
+
+        let paths: Vec<&str> = files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(paths, vec!["contracts/Empty.sol", "contracts/Vault.sol"]);
+        let vault = &files[1];
+        // A line listed twice counts once, hit if any statement on it ran
+        assert_eq!((vault.lines_found, vault.lines_hit), (3, 2));
+        assert_eq!((vault.branches_found, vault.branches_hit), (3, 1));
+        assert_eq!(vault.uncovered_lines, vec![9]);
+        let empty = &files[0];
+        assert_eq!((empty.lines_found, empty.branches_found), (0, 0));
+    }
+
+    #[test]
+    fn files_missing_from_the_report_earn_nothing() {
+        let files = parse_lcov(
+            "TN:\nSF:test/A.t.sol\nDA:1,1\nend_of_record\n",
+            Path::new("/work"),
+        );
+        assert!(files.is_empty());
+
+        let (total, earned, coverage) = score(&targets(Some(80.0), Some(50.0)), &files);
+        assert_eq!((total, earned), (20.0, 0.0));
+        assert_eq!(coverage["lines"]["percent"], json!(0.0));
+        assert_eq!(
+            coverage["lines"]["msg"],
+            json!("Nothing of the reference solution was measured")
+        );
+    }
+
+    #[test]
+    fn files_without_lines_earn_nothing_on_lines() {
+        let (total, earned, coverage) = score(&targets(Some(80.0), None), &[file(0, 0, (0, 0))]);
+        assert_eq!((total, earned), (10.0, 0.0));
+        assert_eq!(coverage["lines"]["found"], json!(0));
+        assert!(coverage["branches"].get("target").is_none());
+    }
+
+    #[test]
+    fn targets_are_scored_in_proportion_up_to_the_target() {
+        let targets = targets(Some(80.0), Some(50.0));
+        // At the targets
+        let (total, earned, _) = score(&targets, &[file(10, 8, (4, 2))]);
+        assert_eq!((total, earned), (20.0, 20.0));
+        // Above them, the score stays full
+        let (_, earned, coverage) = score(&targets, &[file(10, 10, (4, 4))]);
+        assert_eq!(earned, 20.0);
+        assert_eq!(coverage["lines"]["percent"], json!(100.0));
+        // Below them, in proportion
+        let (_, earned, coverage) = score(&targets, &[file(10, 4, (4, 1))]);
+        assert_eq!(earned, 5.0 + 5.0);
+        assert_eq!(coverage["branches"]["earned"], json!(5));
+        // Over several files
+        let (_, earned, _) = score(&targets, &[file(10, 4, (2, 1)), file(10, 8, (2, 0))]);
+        assert_eq!(earned, 7.5 + 5.0);
+    }
+}
@@ -0,0 +1,177 @@
+use std::fs;
+use std::path::Path;
+
+use serde::Serialize;
+use serde_json::Value;
+
+/* Diagnostic
+
+   @dev An entry of the `errors` solc reports, which `forge build --json` passes through.
+        Lines and columns start at 1, columns count characters, `endLine`/`endColumn`
+        point right after the range. The location is missing for errors about no file
+
+*/
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Diagnostic {
+    pub severity: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub column: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end_line: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end_column: Option<usize>,
+    pub message: String,
+    pub formatted_message: String,
+}
+
+impl Diagnostic {
+    pub fn is_error(&self) -> bool {
+        self.severity == "error"
+    }
+
+    // Whatever forge printed when it never got to report the solc errors
+    pub fn from_output(message: &str) -> Self {
+        Diagnostic {
+            severity: "error".to_string(),
+            error_code: None,
+            path: None,
+            line: None,
+            column: None,
+            end_line: None,
+            end_column: None,
+            message: message.to_string(),
+            formatted_message: message.to_string(),
+        }
+    }
+}
+
+// Line and column of the byte `offset` of `source`
+fn position(source: &str, offset: usize) -> Option<(usize, usize)> {
+    let before = source.get(..offset)?;
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    Some((
+        before.matches('\n').count() + 1,
+        before[line_start..].chars().count() + 1,
+    ))
+}
+
+/* parse
+
+   @dev Diagnostics of the `forge build --json` output, `None` unless it is json. solc
+        reports files relative to the project root, they are given relative to
+        `base_path`, the directory of the job, as the submission named them, in the
+        formatted messages as well
+
+*/
+pub fn parse(stdout: &str, base_path: &Path) -> Option<Vec<Diagnostic>> {
+    let output: Value = serde_json::from_str(stdout).ok()?;
+    let errors = output.get("errors").and_then(Value::as_array);
+    let workspace = format!("{}/", base_path.display());
+
+    let mut diagnostics = vec![];
+    for error in errors.into_iter().flatten() {
+        let text = |key: &str| error[key].as_str().map(str::to_string);
+        let mut diagnostic = Diagnostic {
+            severity: text("severity").unwrap_or_else(|| "error".to_string()),
+            error_code: text("errorCode"),
+            path: None,
+            line: None,
+            column: None,
+            end_line: None,
+            end_column: None,
+            message: text("message").unwrap_or_default(),
+            formatted_message: text("formattedMessage")
+                .unwrap_or_default()
+                .replace(&workspace, ""),
+        };
+
+        let location = &error["sourceLocation"];
+        if let Some(file) = location["file"].as_str() {
+            let file = Path::new(file);
+            let relative = file
+                .strip_prefix(base_path)
+                .ok()
+                .or_else(|| file.strip_prefix(base_path.canonicalize().ok()?).ok())
+                .unwrap_or(file);
+            diagnostic.path = Some(relative.to_string_lossy().into_owned());
+
+            let source = fs::read_to_string(file).unwrap_or_default();
+            let offset = |key: &str| location[key].as_u64().map(|o| o as usize);
+            if let Some((line, column)) = offset("start").and_then(|o| position(&source, o)) {
+                diagnostic.line = Some(line);
+                diagnostic.column = Some(column);
+            }
+            if let Some((line, column)) = offset("end").and_then(|o| position(&source, o)) {
+                diagnostic.end_line = Some(line);
+                diagnostic.end_column = Some(column);
+            }
+        }
+        diagnostics.push(diagnostic);
+    }
+    Some(diagnostics)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::env;
+
+    #[test]
+    fn locations_are_relative_to_the_job() {
+        let base_path = env::temp_dir().join(format!("judger-diagnostics-{}", std::process::id()));
+        fs::create_dir_all(base_path.join("contracts")).unwrap();
+        let source = "contract A {\n    string s = \"é\"; uint x = y;\n}\n";
+        fs::write(base_path.join("contracts").join("A.sol"), source).unwrap();
+        let file = base_path.join("contracts").join("A.sol");
+        let start = source.find("y;").unwrap();
+
+        let stdout = json!({
+            "errors": [
+                {
+                    "severity": "error",
+                    "errorCode": "7576",
+                    "message": "Undeclared identifier.",
+                    "formattedMessage": format!("DeclarationError: Undeclared identifier.\n --> {}:2:30:", file.display()),
+                    "sourceLocation": { "file": file.display().to_string(), "start": start, "end": start + 1 }
+                },
+                { "severity": "warning", "message": "No SPDX license." },
+                { "message": "Stack too deep." }
+            ]
+        })
+        .to_string();
+        let diagnostics = parse(&stdout, &base_path).unwrap();
+        fs::remove_dir_all(&base_path).unwrap();
+
+        let error = &diagnostics[0];
+        assert!(error.is_error());
+        assert_eq!(error.error_code.as_deref(), Some("7576"));
+        assert_eq!(error.path.as_deref(), Some("contracts/A.sol"));
+        // Columns count characters, `é` is two bytes
+        assert_eq!((error.line, error.column), (Some(2), Some(30)));
+        assert_eq!((error.end_line, error.end_column), (Some(2), Some(31)));
+        assert_eq!(
+            error.formatted_message,
+            "DeclarationError: Undeclared identifier.\n --> contracts/A.sol:2:30:"
+        );
+
+        assert!(!diagnostics[1].is_error());
+        assert_eq!(diagnostics[1].path, None);
+        assert_eq!(diagnostics[1].line, None);
+        assert_eq!(diagnostics[2].severity, "error");
+    }
+
+    #[test]
+    fn output_without_json_gives_nothing() {
+        let base_path = Path::new("/nonexistent");
+        assert!(parse("Error: compiler not found", base_path).is_none());
+        assert!(parse("{}", base_path).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,207 @@
+use serde_json::{json, Value};
+
+use crate::question::GasBudget;
+use crate::rubric;
+
+// Share of the score earned between the target and the ceiling, unless set otherwise
+pub const DEFAULT_PARTIAL: f64 = 0.5;
+// What of the calls of a function is held against its budget
+pub const MEASURES: [&str; 4] = ["min", "mean", "median", "max"];
+
+/* tier
+
+   @dev The share of the score earned using `gas` and the tier reached: all of it at or
+        under `target`, `partial` at or under `ceiling`, nothing above
+
+*/
+pub fn tier(gas: u64, target: u64, ceiling: Option<u64>, partial: f64) -> (f64, &'static str) {
+    if gas <= target {
+        (1.0, "target")
+    } else if ceiling.is_some_and(|ceiling| gas <= ceiling) {
+        (partial, "ceiling")
+    } else {
+        (0.0, "over")
+    }
+}
+
+/* FunctionGas
+
+   @dev A function of the gas report, `contract` without its path and `stats` the
+        `calls`, `min`, `mean`, `median` and `max` forge measured
+
+*/
+pub struct FunctionGas {
+    contract: String,
+    signature: String,
+    stats: Value,
+}
+
+/* parse_report
+
+   @dev Functions of the json printed by `forge test --gas-report --json`, one entry
+        per contract. Functions are keyed by signature, or by name then signature on
+        releases grouping overloads
+
+*/
+pub fn parse_report(stdout: &str) -> Vec<FunctionGas> {
+    let report = serde_json::from_str::<Value>(stdout).ok().or_else(|| {
+        stdout
+            .lines()
+            .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+            .find(Value::is_array)
+    });
+
+    let mut functions = vec![];
+    for entry in report.iter().filter_map(Value::as_array).flatten() {
+        let contract = entry["contract"].as_str().unwrap_or("");
+        let contract = contract.rsplit(':').next().unwrap_or(contract);
+        for (key, value) in entry["functions"].as_object().into_iter().flatten() {
+            let overloads: Vec<(&String, &Value)> = if value.get("calls").is_some() {
+                vec![(key, value)]
+            } else {
+                value.as_object().into_iter().flatten().collect()
+            };
+            for (signature, stats) in overloads {
+                functions.push(FunctionGas {
+                    contract: contract.to_string(),
+                    signature: signature.to_string(),
+                    stats: stats.clone(),
+                });
+            }
+        }
+    }
+    functions
+}
+
+// `Vault.deposit` matches every overload of `deposit`, `Vault.deposit(uint256)` only that one
+fn find<'a>(report: &'a [FunctionGas], function: &str) -> Option<&'a FunctionGas> {
+    let (contract, name) = function.split_once('.')?;
+    report.iter().find(|f| {
+        f.contract == contract
+            && (f.signature == name
+                || (!name.contains('(') && f.signature.starts_with(&format!("{}(", name))))
+    })
+}
+
+/* score_budgets
+
+   @dev Scores the gas budgets of a question against the report, a function the tests
+        never called earns nothing. Returns the total, the score earned and every budget
+        with the gas measured
+
+*/
+pub fn score_budgets(budgets: &[GasBudget], report: &[FunctionGas]) -> (f64, f64, Value) {
+    let (mut total, mut earned) = (0.0, 0.0);
+    let mut results = vec![];
+    for budget in budgets {
+        let measured = find(report, &budget.function)
+            .and_then(|function| function.stats[budget.measure.as_str()].as_u64());
+        let (share, tier) = match measured {
+            Some(gas) => tier(gas, budget.target, budget.ceiling, budget.partial),
+            None => (0.0, "missing"),
+        };
+        total += budget.score;
+        earned += budget.score * share;
+        results.push(json!({
+            "function": &budget.function,
+            "measure": &budget.measure,
+            "measured": measured,
+            "target": budget.target,
+            "ceiling": budget.ceiling,
+            "tier": tier,
+            "score": rubric::score_json(budget.score),
+            "earned": rubric::score_json(budget.score * share),
+        }));
+    }
+    (total, earned, json!(results))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn budget(function: &str, target: u64, ceiling: Option<u64>, measure: &str) -> GasBudget {
+        GasBudget {
+            function: function.to_string(),
+            target,
+            ceiling,
+            score: 4.0,
+            partial: DEFAULT_PARTIAL,
+            measure: measure.to_string(),
+        }
+    }
+
+    fn report() -> Vec<FunctionGas> {
+        let stats = |min: u64, max: u64| json!({"calls": 2, "min": min, "mean": (min + max) / 2, "median": (min + max) / 2, "max": max});
+        let stdout = format!(
+            "Compiling 3 files\n{}\n",
+            json!([
+                {
+                    "contract": "contracts/Vault.sol:Vault",
+                    "functions": {
+                        "deposit(uint256)": stats(20000, 40000),
+                        "withdraw": {
+                            "withdraw(uint256)": stats(10000, 12000),
+                            "withdraw(uint256,address)": stats(15000, 15000)
+                        }
+                    }
+                },
+                { "contract": "contracts/Token.sol:Token", "functions": {} }
+            ])
+        );
+        parse_report(&stdout)
+    }
+
+    #[test]
+    fn tiers_are_inclusive() {
+        assert_eq!(tier(1000, 1000, Some(2000), 0.5), (1.0, "target"));
+        assert_eq!(tier(2000, 1000, Some(2000), 0.5), (0.5, "ceiling"));
+        assert_eq!(tier(2001, 1000, Some(2000), 0.5), (0.0, "over"));
+        assert_eq!(tier(1001, 1000, None, 0.5), (0.0, "over"));
+    }
+
+    #[test]
+    fn reports_are_read_with_or_without_grouped_overloads() {
+        let report = report();
+        let signatures: Vec<(&str, &str)> = report
+            .iter()
+            .map(|f| (f.contract.as_str(), f.signature.as_str()))
+            .collect();
+        assert_eq!(
+            signatures,
+            vec![
+                ("Vault", "deposit(uint256)"),
+                ("Vault", "withdraw(uint256)"),
+                ("Vault", "withdraw(uint256,address)"),
+            ]
+        );
+        assert!(parse_report("no json here").is_empty());
+    }
+
+    #[test]
+    fn budgets_score_the_measure_of_the_function() {
+        let budgets = [
+            budget("Vault.deposit", 30000, None, "mean"),
+            budget("Vault.deposit", 30000, Some(45000), "max"),
+            budget("Vault.withdraw(uint256,address)", 12000, Some(16000), "min"),
+            budget("Vault.withdraw", 12000, None, "max"),
+            budget("Token.transfer", 50000, None, "mean"),
+        ];
+        let (total, earned, results) = score_budgets(&budgets, &report());
+
+        assert_eq!((total, earned), (20.0, 4.0 + 2.0 + 2.0 + 4.0));
+        let tiers: Vec<&str> = results
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|result| result["tier"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            tiers,
+            vec!["target", "ceiling", "ceiling", "target", "missing"]
+        );
+        assert_eq!(results[4]["measured"], Value::Null);
+        assert_eq!(results[1]["measured"], json!(40000));
+        assert_eq!(results[2]["earned"], json!(2));
+    }
+}
@@ -0,0 +1,427 @@
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use axum::extract::{Path, Query, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use log::{error, info};
+use serde::Deserialize;
+use serde_json::json;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tokio_stream::{Stream, StreamExt};
+
+use crate::protocol::{grader_result_key, job_record_key, JOB_RECORD_TTL_SECS};
+use crate::queue::result::RedisResultSink;
+use crate::queue::{self, JobQueue, QueueBackend, ResultSink};
+use crate::server::{self, job_record};
+
+// Upper bound of `GET /jobs/{id}?wait=<secs>`, longer waits are cut to this
+const MAX_WAIT_SECS: u64 = 60;
+const POLL_INTERVAL_MILLIS: u64 = 200;
+
+#[derive(Debug)]
+pub enum ErrorCode {
+    BindFailure(String),
+    QueueErr(queue::ErrorCode),
+}
+
+impl ErrorCode {
+    pub fn get_err_msg(&self) -> String {
+        match self {
+            ErrorCode::BindFailure(err) => format!("HTTP bind failed: {}", err),
+            ErrorCode::QueueErr(err) => err.get_err_msg(),
+        }
+    }
+}
+
+impl From<queue::ErrorCode> for ErrorCode {
+    fn from(err: queue::ErrorCode) -> Self {
+        ErrorCode::QueueErr(err)
+    }
+}
+
+struct AppState {
+    redis_host: String,
+    redis_prefix: String,
+    grader_token: Option<String>,
+    queue: Mutex<Box<dyn JobQueue>>,
+    sink: Mutex<Box<dyn ResultSink>>,
+}
+
+type Reply = (StatusCode, Json<serde_json::Value>);
+
+fn reply_err(status: StatusCode, msg: &str) -> Reply {
+    let mut json = json!({});
+    json["info"] = json!("Failed");
+    json["msg"] = json!(msg);
+    (status, Json(json))
+}
+
+/* serve
+
+   @dev Front door for submitting jobs over HTTP, the jobs go through the same queue
+        and result sink as the ones pushed to Redis directly
+
+        POST /jobs               submit a JobMessage, `judgeJobId` is generated if omitted,
+                                 an id that already has a job is a 409
+        GET  /jobs/{id}          the status record of the job
+        GET  /jobs/{id}?wait=30  blocks until the job is done or the wait elapsed
+        GET  /jobs/{id}/events   server-sent progress events until the job is done
+        DELETE /jobs/{id}        cancels the job, queued or running
+        GET  /jobs/{id}/grader   the full result, hidden tests included, for
+                                 `Authorization: Bearer <grader token>` only
+
+*/
+pub fn serve(
+    addr: &str,
+    redis_host: &str,
+    redis_prefix: &str,
+    grader_token: Option<String>,
+    backend: &QueueBackend,
+) -> Result<(), ErrorCode> {
+    let state = Arc::new(AppState {
+        redis_host: redis_host.to_string(),
+        redis_prefix: redis_prefix.to_string(),
+        grader_token,
+        queue: Mutex::new(backend.open(redis_host, redis_prefix, "http")?),
+        sink: Mutex::new(Box::new(RedisResultSink::new(redis_host)?)),
+    });
+
+    let app = router(state);
+
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        let listener = tokio::net::TcpListener::bind(addr)
+            .await
+            .map_err(|err| ErrorCode::BindFailure(err.to_string()))?;
+        info!("HTTP server listening on {}", addr);
+        axum::serve(listener, app)
+            .await
+            .map_err(|err| ErrorCode::BindFailure(err.to_string()))
+    })
+}
+
+fn router(state: Arc<AppState>) -> Router {
+    Router::new()
+        .route("/jobs", post(submit_job))
+        .route("/jobs/:id", get(get_job).delete(cancel_job))
+        .route("/jobs/:id/events", get(job_events))
+        .route("/jobs/:id/grader", get(get_grader_result))
+        .with_state(state)
+}
+
+fn generate_job_id() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+    format!("http-{:x}", nanos)
+}
+
+async fn submit_job(
+    State(state): State<Arc<AppState>>,
+    Json(mut body): Json<serde_json::Value>,
+) -> Reply {
+    if !body.is_object() {
+        return reply_err(StatusCode::BAD_REQUEST, "Job should be a json object");
+    }
+    if body["judgeJobId"].as_str().unwrap_or("").is_empty() {
+        body["judgeJobId"] = json!(generate_job_id());
+    }
+    // Rejects the job here rather than letting a worker drop it
+    let job = match server::get_job_message(&body.to_string()) {
+        Ok(job) => job,
+        Err(err) => return reply_err(StatusCode::BAD_REQUEST, &err.get_err_msg()),
+    };
+    if let Err(err) = server::validate_submission(&job) {
+        return reply_err(StatusCode::BAD_REQUEST, &err.get_err_msg());
+    }
+
+    let record_key = job_record_key(&state.redis_prefix, &job.judge_job_id);
+    let record = job_record(&job.judge_job_id, "queued", None);
+
+    let res = tokio::task::block_in_place(|| -> Result<bool, queue::ErrorCode> {
+        // The record replaces the request marker, HTTP clients never take the response.
+        // Only a new one is stored, a reused id would mix two submissions up
+        let recorded =
+            state
+                .sink
+                .lock()
+                .unwrap()
+                .record_new(&record_key, &record, JOB_RECORD_TTL_SECS)?;
+        if recorded {
+            state.queue.lock().unwrap().submit(&body.to_string())?;
+        }
+        Ok(recorded)
+    });
+    match res {
+        Ok(true) => {}
+        Ok(false) => {
+            return reply_err(
+                StatusCode::CONFLICT,
+                &format!("Job {} already exists", job.judge_job_id),
+            )
+        }
+        Err(err) => {
+            error!(
+                "Submit job {} failed: {}",
+                job.judge_job_id,
+                err.get_err_msg()
+            );
+            return reply_err(StatusCode::SERVICE_UNAVAILABLE, &err.get_err_msg());
+        }
+    }
+
+    let mut json = json!({});
+    json["jobId"] = json!(&job.judge_job_id);
+    json["status"] = json!("queued");
+    (StatusCode::ACCEPTED, Json(json))
+}
+
+#[derive(Deserialize)]
+struct GetJobParams {
+    wait: Option<u64>,
+}
+
+async fn get_job(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Query(params): Query<GetJobParams>,
+) -> Reply {
+    let record_key = job_record_key(&state.redis_prefix, &id);
+    let wait = Duration::from_secs(params.wait.unwrap_or(0).min(MAX_WAIT_SECS));
+    let start_time = SystemTime::now();
+
+    loop {
+        let res = tokio::task::block_in_place(|| state.sink.lock().unwrap().lookup(&record_key));
+        let record = match res {
+            Ok(Some(record)) => serde_json::from_str::<serde_json::Value>(&record).unwrap(),
+            Ok(None) => return reply_err(StatusCode::NOT_FOUND, "Job not found"),
+            Err(err) => return reply_err(StatusCode::SERVICE_UNAVAILABLE, &err.get_err_msg()),
+        };
+
+        let elapsed = SystemTime::now().duration_since(start_time).unwrap();
+        let status = record["status"].as_str().unwrap_or("");
+        if server::is_final_status(status) || elapsed >= wait {
+            return (StatusCode::OK, Json(record));
+        }
+        tokio::time::sleep(Duration::from_millis(POLL_INTERVAL_MILLIS)).await;
+    }
+}
+
+// Without a grader token configured there is no grader view at all
+async fn get_grader_result(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Reply {
+    let Some(token) = &state.grader_token else {
+        return reply_err(StatusCode::NOT_FOUND, "Grader view disabled");
+    };
+    let authorization = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok());
+    if authorization != Some(format!("Bearer {}", token).as_str()) {
+        return reply_err(StatusCode::UNAUTHORIZED, "Grader token required");
+    }
+
+    let grader_key = grader_result_key(&state.redis_prefix, &id);
+    let res = tokio::task::block_in_place(|| state.sink.lock().unwrap().lookup(&grader_key));
+    match res {
+        Ok(Some(result)) => (
+            StatusCode::OK,
+            Json(serde_json::from_str(&result).unwrap_or(json!(result))),
+        ),
+        Ok(None) => reply_err(StatusCode::NOT_FOUND, "No result for the job"),
+        Err(err) => reply_err(StatusCode::SERVICE_UNAVAILABLE, &err.get_err_msg()),
+    }
+}
+
+async fn cancel_job(State(state): State<Arc<AppState>>, Path(id): Path<String>) -> Reply {
+    let record_key = job_record_key(&state.redis_prefix, &id);
+    let res = tokio::task::block_in_place(|| -> Result<Option<String>, queue::ErrorCode> {
+        let mut sink = state.sink.lock().unwrap();
+        let record = sink.lookup(&record_key)?;
+        if record.is_some() {
+            server::cancel_job(sink.as_mut(), &state.redis_prefix, &id)?;
+        }
+        Ok(record)
+    });
+    let record = match res {
+        Ok(Some(record)) => serde_json::from_str::<serde_json::Value>(&record).unwrap(),
+        Ok(None) => return reply_err(StatusCode::NOT_FOUND, "Job not found"),
+        Err(err) => return reply_err(StatusCode::SERVICE_UNAVAILABLE, &err.get_err_msg()),
+    };
+    // Cancelling a finished job changes nothing
+    if server::is_final_status(record["status"].as_str().unwrap_or("")) {
+        return (StatusCode::OK, Json(record));
+    }
+
+    let mut json = json!({});
+    json["jobId"] = json!(&id);
+    json["status"] = json!("cancelling");
+    (StatusCode::ACCEPTED, Json(json))
+}
+
+async fn job_events(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, Reply> {
+    let record_key = job_record_key(&state.redis_prefix, &id);
+    let res = tokio::task::block_in_place(|| state.sink.lock().unwrap().lookup(&record_key));
+    match res {
+        Ok(Some(_)) => {}
+        Ok(None) => return Err(reply_err(StatusCode::NOT_FOUND, "Job not found")),
+        Err(err) => {
+            return Err(reply_err(
+                StatusCode::SERVICE_UNAVAILABLE,
+                &err.get_err_msg(),
+            ))
+        }
+    }
+
+    let (tx, rx) = tokio::sync::mpsc::unbounded_channel::<String>();
+    let redis_host = state.redis_host.clone();
+    let redis_prefix = state.redis_prefix.clone();
+
+    // The subscription blocks, it stops soon after the client went away
+    thread::spawn(move || {
+        let res = server::follow_job(
+            &redis_host,
+            &redis_prefix,
+            &id,
+            |event| tx.send(event.to_string()).is_ok(),
+            || !tx.is_closed(),
+        );
+        if let Err(err) = res {
+            error!("Follow job {} failed: {}", id, err.get_err_msg());
+        }
+    });
+
+    let stream = UnboundedReceiverStream::new(rx).map(|event| Ok(Event::default().data(event)));
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queue::memory::MemoryQueue;
+    use axum::body::{to_bytes, Body};
+    use axum::http::Request;
+    use tower::ServiceExt;
+
+    fn memory_app(queue: &MemoryQueue) -> Router {
+        router(Arc::new(AppState {
+            redis_host: String::new(),
+            redis_prefix: String::from("test"),
+            grader_token: None,
+            queue: Mutex::new(Box::new(queue.for_worker("http"))),
+            sink: Mutex::new(Box::new(queue.result_sink())),
+        }))
+    }
+
+    async fn send(app: &Router, method: &str, uri: &str, body: Option<serde_json::Value>) -> Reply {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(body.map_or(Body::empty(), |body| Body::from(body.to_string())))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, Json(serde_json::from_slice(&bytes).unwrap()))
+    }
+
+    fn job(judge_job_id: &str) -> serde_json::Value {
+        json!({
+            "questionNo": "q1",
+            "solcVersion": "0.8.20",
+            "judgeJobId": judge_job_id,
+            "pathWithContent": [{ "path": "contracts/A.sol", "content": "contract A {}" }],
+        })
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn submitted_jobs_are_queued_and_recorded() {
+        let queue = MemoryQueue::new();
+        let app = memory_app(&queue);
+
+        let (status, Json(reply)) = send(&app, "POST", "/jobs", Some(job("job-1"))).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert_eq!(reply["jobId"], "job-1");
+        let delivery = queue.for_worker("test:00").fetch().unwrap().unwrap();
+        assert!(delivery.raw.contains("job-1"));
+
+        let (status, Json(record)) = send(&app, "GET", "/jobs/job-1", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(record["status"], "queued");
+
+        // Without an id one is generated
+        let mut anonymous = job("");
+        anonymous.as_object_mut().unwrap().remove("judgeJobId");
+        let (status, Json(reply)) = send(&app, "POST", "/jobs", Some(anonymous)).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert!(reply["jobId"].as_str().unwrap().starts_with("http-"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn reused_job_ids_are_refused() {
+        let queue = MemoryQueue::new();
+        let app = memory_app(&queue);
+
+        let (status, _) = send(&app, "POST", "/jobs", Some(job("job-1"))).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        let (status, _) = send(&app, "POST", "/jobs", Some(job("job-1"))).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        // Only the first submission was queued
+        let mut worker = queue.for_worker("test:00");
+        assert!(worker.fetch().unwrap().is_some());
+        assert!(worker.fetch().unwrap().is_none());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn unknown_jobs_are_not_found() {
+        let queue = MemoryQueue::new();
+        let app = memory_app(&queue);
+
+        let (status, _) = send(&app, "GET", "/jobs/missing", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = send(&app, "DELETE", "/jobs/missing", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = send(&app, "GET", "/jobs/missing/grader", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn invalid_jobs_are_bad_requests() {
+        let queue = MemoryQueue::new();
+        let app = memory_app(&queue);
+
+        let mut escaping = job("job-1");
+        escaping["pathWithContent"][0]["path"] = json!("../A.sol");
+        let (status, _) = send(&app, "POST", "/jobs", Some(escaping)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = send(&app, "POST", "/jobs", Some(json!([1, 2]))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn cancelling_flags_the_job() {
+        let queue = MemoryQueue::new();
+        let app = memory_app(&queue);
+        let (status, _) = send(&app, "POST", "/jobs", Some(job("job-1"))).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+
+        let (status, Json(reply)) = send(&app, "DELETE", "/jobs/job-1", None).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert_eq!(reply["status"], "cancelling");
+        let cancel_key = crate::protocol::cancel_key("test", "job-1");
+        assert!(queue.result_sink().is_cancelled(&cancel_key).unwrap());
+    }
+}
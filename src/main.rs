@@ -1,11 +1,31 @@
-use std::{borrow::Borrow, env, process::exit};
+use std::{
+    env,
+    path::{Path, PathBuf},
+    process::exit,
+    thread,
+};
 
 use chrono::{Local, TimeZone};
-use clap::{parser::ValueSource, Arg, ArgMatches, Command};
+use clap::{parser::ValueSource, Arg, ArgAction, ArgMatches, Command};
 use log::trace;
+use protocol::{DEFAULT_LIST_NAME, DEFAULT_REDIS_HOST, DEFAULT_REDIS_PREFIX, DEFAULT_STREAM_GROUP};
+use queue::QueueBackend;
 
 mod client;
+mod coverage;
+mod diagnostics;
+mod gas;
+mod http;
+mod mutation;
+mod protocol;
+mod question;
+mod queue;
+mod rubric;
+mod sandbox;
+mod scan;
 mod server;
+mod solidity;
+mod tiers;
 mod types;
 
 const COMMAND_NAME: &str = "test";
@@ -34,14 +54,14 @@ fn main() {
                     Arg::new("redis-host")
                         .short('r')
                         .long("redis-host")
-                        .default_value("redis://127.0.0.1/1")
+                        .default_value(DEFAULT_REDIS_HOST)
                         .help("Redis server(env: REDIS_HOST)"),
                 )
                 .arg(
                     Arg::new("redis-prefix")
                         .short('p')
                         .long("redis-prefix")
-                        .default_value("smc-open-solidity-judge")
+                        .default_value(DEFAULT_REDIS_PREFIX)
                         .help("Prefix of the redis operations(env: REDIS_PREFIX)"),
                 )
                 .arg(
@@ -57,8 +77,109 @@ fn main() {
                     Arg::new("redis-list-name")
                         .short('n')
                         .long("redis-list-name")
-                        .default_value("test")
-                        .help("Redis list name(env: REDIS_LIST_NAME)"),
+                        .default_value(DEFAULT_LIST_NAME)
+                        .help("Request list under the prefix the jobs are pushed to(env: REDIS_LIST_NAME)"),
+                )
+                .arg(
+                    Arg::new("server-id")
+                        .short('s')
+                        .long("server-id")
+                        .help("Unique id of this server, defaults to the hostname(env: SERVER_ID)"),
+                )
+                .arg(
+                    Arg::new("max-retries")
+                        .long("max-retries")
+                        .default_value("3")
+                        .help("Retries of a job failed for infrastructure reasons before it is dead-lettered(env: MAX_RETRIES)"),
+                )
+                .arg(
+                    Arg::new("retry-backoff")
+                        .long("retry-backoff")
+                        .default_value("5")
+                        .help("Delay in secs before the first retry, doubled on every attempt(env: RETRY_BACKOFF)"),
+                )
+                .arg(
+                    Arg::new("build-time-limit")
+                        .long("build-time-limit")
+                        .default_value("60")
+                        .help("Secs forge build may run before it is killed(env: BUILD_TIME_LIMIT)"),
+                )
+                .arg(
+                    Arg::new("test-time-limit")
+                        .long("test-time-limit")
+                        .default_value("120")
+                        .help("Secs forge test may run before it is killed(env: TEST_TIME_LIMIT)"),
+                )
+                .arg(
+                    Arg::new("max-time-limit")
+                        .long("max-time-limit")
+                        .default_value("600")
+                        .help("Upper bound of the time limits a job may ask for(env: MAX_TIME_LIMIT)"),
+                )
+                .arg(
+                    Arg::new("question-bank")
+                        .long("question-bank")
+                        .default_value("questions")
+                        .help("Directory of the official tests, one subdirectory per question number(env: QUESTION_BANK)"),
+                )
+                .arg(
+                    Arg::new("sandbox")
+                        .long("sandbox")
+                        .value_parser(["on", "off"])
+                        .default_value("off")
+                        .help("Run forge in mount, PID and network namespaces of its own(env: SANDBOX)"),
+                )
+                .arg(
+                    Arg::new("sandbox-solc-dir")
+                        .long("sandbox-solc-dir")
+                        .help("Solc installations mounted read-only in the sandbox, defaults to ~/.svm(env: SANDBOX_SOLC_DIR)"),
+                )
+                .arg(
+                    Arg::new("sandbox-memory")
+                        .long("sandbox-memory")
+                        .default_value("4096")
+                        .help("Address space limit of every sandboxed process in MiB(env: SANDBOX_MEMORY)"),
+                )
+                .arg(
+                    Arg::new("sandbox-processes")
+                        .long("sandbox-processes")
+                        .default_value("512")
+                        .help("Process limit of the sandboxed forge, counts every process of the user(env: SANDBOX_PROCESSES)"),
+                )
+                .arg(
+                    Arg::new("sandbox-file-size")
+                        .long("sandbox-file-size")
+                        .default_value("64")
+                        .help("Largest file a sandboxed process may write in MiB(env: SANDBOX_FILE_SIZE)"),
+                )
+                .arg(
+                    Arg::new("queue-backend")
+                        .short('q')
+                        .long("queue-backend")
+                        .value_parser(["list", "stream"])
+                        .default_value("list")
+                        .help("Queue the jobs are read from(env: QUEUE_BACKEND)"),
+                )
+                .arg(
+                    Arg::new("stream-group")
+                        .long("stream-group")
+                        .default_value(DEFAULT_STREAM_GROUP)
+                        .help("Consumer group of the stream backend(env: STREAM_GROUP)"),
+                )
+                .arg(
+                    Arg::new("claim-idle")
+                        .long("claim-idle")
+                        .help("Secs a stream entry may stay pending on a worker before others claim it, above twice the 30s workers renew their claims every, defaults to 120(env: CLAIM_IDLE)"),
+                )
+                .arg(
+                    Arg::new("http-addr")
+                        .long("http-addr")
+                        .help("Also serve the HTTP job API on this address, e.g. 0.0.0.0:8080(env: HTTP_ADDR)"),
+                )
+                .arg(
+                    Arg::new("grader-token")
+                        .long("grader-token")
+                        .help("Bearer token of GET /jobs/{id}/grader, the full results with hidden tests, disabled when unset(env: GRADER_TOKEN)"),
                 ),
         )
         .subcommand(
@@ -101,28 +222,311 @@ fn main() {
                     Arg::new("connection-str")
                         .short('c')
                         .long("connection-str")
-                        .default_value("redis://127.0.0.1")
-                        .help("Redis connection str"),
+                        .default_value(DEFAULT_REDIS_HOST)
+                        .help("Redis connection str (env: CONNECTION_STR)"),
+                )
+                .arg(
+                    Arg::new("redis-prefix")
+                        .short('p')
+                        .long("redis-prefix")
+                        .default_value(DEFAULT_REDIS_PREFIX)
+                        .help("Prefix of the redis operations (env: REDIS_PREFIX)"),
+                )
+                .arg(
+                    Arg::new("redis-list-name")
+                        .short('l')
+                        .long("redis-list-name")
+                        .default_value(DEFAULT_LIST_NAME)
+                        .help("Request list under the prefix the jobs are pushed to (env: REDIS_LIST_NAME)"),
+                )
+                .arg(
+                    Arg::new("queue-backend")
+                        .short('q')
+                        .long("queue-backend")
+                        .value_parser(["list", "stream"])
+                        .default_value("list")
+                        .help("Queue the jobs are pushed to, the one of the server (env: QUEUE_BACKEND)"),
+                )
+                .arg(
+                    Arg::new("stream-group")
+                        .long("stream-group")
+                        .default_value(DEFAULT_STREAM_GROUP)
+                        .help("Consumer group of the stream backend (env: STREAM_GROUP)"),
+                ),
+        )
+        .subcommand(
+            Command::new("local")
+                .about("Judge a directory in this process, without Redis or a server")
+                .arg(
+                    Arg::new("directory")
+                        .short('d')
+                        .long("directory")
+                        .default_value("usercode")
+                        .help("Root dir of the files (env: DIRECTORY)"),
+                )
+                .arg(
+                    Arg::new("question-no")
+                        .short('n')
+                        .long("question-no")
+                        .required(true)
+                        .help("Questino number of the input"),
+                )
+                .arg(
+                    Arg::new("solc-version")
+                        .short('v')
+                        .long("solc-version")
+                        .default_value("0.8.20")
+                        .help("Solc version selected (env: SOLC_VERSION)"),
+                )
+                .arg(
+                    Arg::new("job-id")
+                        .short('j')
+                        .long("job-id")
+                        .default_value("local")
+                        .help("Job id offered (env: JOB_ID)"),
+                )
+                .arg(
+                    Arg::new("worker-dir")
+                        .long("worker-dir")
+                        .default_value("tmp/worker")
+                        .help(
+                            "The default work directory of the foundry env(env: REDIS_WORKER_DIR)",
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("events")
+                .about("Follow the progress events of a job until it is done")
+                .arg(
+                    Arg::new("job-id")
+                        .short('j')
+                        .long("job-id")
+                        .required(true)
+                        .help("Job id to follow"),
+                )
+                .arg(
+                    Arg::new("redis-host")
+                        .short('r')
+                        .long("redis-host")
+                        .default_value(DEFAULT_REDIS_HOST)
+                        .help("Redis server(env: REDIS_HOST)"),
+                )
+                .arg(
+                    Arg::new("redis-prefix")
+                        .short('p')
+                        .long("redis-prefix")
+                        .default_value(DEFAULT_REDIS_PREFIX)
+                        .help("Prefix of the redis operations(env: REDIS_PREFIX)"),
+                ),
+        )
+        .subcommand(
+            Command::new("cancel")
+                .about("Cancel a queued or running job")
+                .arg(
+                    Arg::new("job-id")
+                        .short('j')
+                        .long("job-id")
+                        .required(true)
+                        .help("Job id to cancel"),
+                )
+                .arg(
+                    Arg::new("redis-host")
+                        .short('r')
+                        .long("redis-host")
+                        .default_value(DEFAULT_REDIS_HOST)
+                        .help("Redis server(env: REDIS_HOST)"),
+                )
+                .arg(
+                    Arg::new("redis-prefix")
+                        .short('p')
+                        .long("redis-prefix")
+                        .default_value(DEFAULT_REDIS_PREFIX)
+                        .help("Prefix of the redis operations(env: REDIS_PREFIX)"),
                 ),
         )
         .subcommand(Command::new("init").about("Initialize the cache files"))
+        .subcommand(
+            Command::new("dead-letter")
+                .about("Inspect and replay jobs that ran out of retries")
+                .subcommand_required(true)
+                .arg(
+                    Arg::new("redis-host")
+                        .short('r')
+                        .long("redis-host")
+                        .global(true)
+                        .default_value(DEFAULT_REDIS_HOST)
+                        .help("Redis server(env: REDIS_HOST)"),
+                )
+                .arg(
+                    Arg::new("redis-prefix")
+                        .short('p')
+                        .long("redis-prefix")
+                        .global(true)
+                        .default_value(DEFAULT_REDIS_PREFIX)
+                        .help("Prefix of the redis operations(env: REDIS_PREFIX)"),
+                )
+                .arg(
+                    Arg::new("queue-backend")
+                        .short('q')
+                        .long("queue-backend")
+                        .global(true)
+                        .value_parser(["list", "stream"])
+                        .default_value("list")
+                        .help("Queue replayed jobs are pushed to(env: QUEUE_BACKEND)"),
+                )
+                .arg(
+                    Arg::new("redis-list-name")
+                        .short('n')
+                        .long("redis-list-name")
+                        .global(true)
+                        .default_value(DEFAULT_LIST_NAME)
+                        .help("Request list of the list backend(env: REDIS_LIST_NAME)"),
+                )
+                .arg(
+                    Arg::new("stream-group")
+                        .long("stream-group")
+                        .global(true)
+                        .default_value(DEFAULT_STREAM_GROUP)
+                        .help("Consumer group of the stream backend(env: STREAM_GROUP)"),
+                )
+                .subcommand(Command::new("list").about("Print the dead-lettered jobs"))
+                .subcommand(
+                    Command::new("replay")
+                        .about("Move dead-lettered jobs back onto the request list")
+                        .arg(
+                            Arg::new("job-id")
+                                .short('j')
+                                .long("job-id")
+                                .help("Only replay this job, all jobs are replayed if omitted"),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("question")
+                .about("Manage the question bank the official tests come from")
+                .subcommand_required(true)
+                .arg(
+                    Arg::new("question-bank")
+                        .short('b')
+                        .long("question-bank")
+                        .global(true)
+                        .default_value("questions")
+                        .help("Directory of the question bank(env: QUESTION_BANK)"),
+                )
+                .subcommand(
+                    Command::new("add")
+                        .about("Add a question, or replace it with a new version")
+                        .arg(
+                            Arg::new("question-no")
+                                .short('n')
+                                .long("question-no")
+                                .required(true)
+                                .help("Question number"),
+                        )
+                        .arg(
+                            Arg::new("directory")
+                                .short('d')
+                                .long("directory")
+                                .required(true)
+                                .help("Reference solution in contracts/, official tests in test/, optional fixtures/ and question.json"),
+                        )
+                        .arg(
+                            Arg::new("solc")
+                                .long("solc")
+                                .help("Semver range of the allowed solc versions, e.g. \">=0.8.0, <0.9.0\""),
+                        )
+                        .arg(
+                            Arg::new("library")
+                                .long("library")
+                                .action(ArgAction::Append)
+                                .help("Import root the submissions may use, e.g. forge-std, repeatable"),
+                        )
+                        .arg(
+                            Arg::new("forbid")
+                                .long("forbid")
+                                .action(ArgAction::Append)
+                                .help("Construct forbidden on top of the default cheatcodes, e.g. assembly, selfdestruct, delegatecall or vm.prank, repeatable"),
+                        ),
+                )
+                .subcommand(Command::new("list").about("Print the questions of the bank"))
+                .subcommand(
+                    Command::new("show")
+                        .about("Print the settings and files of a question")
+                        .arg(
+                            Arg::new("question-no")
+                                .short('n')
+                                .long("question-no")
+                                .required(true)
+                                .help("Question number"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("validate")
+                        .about("Judge the reference solution, it has to get the full score")
+                        .arg(
+                            Arg::new("question-no")
+                                .short('n')
+                                .long("question-no")
+                                .required(true)
+                                .help("Question number"),
+                        )
+                        .arg(
+                            Arg::new("solc-version")
+                                .short('v')
+                                .long("solc-version")
+                                .default_value("0.8.20")
+                                .help("Solc version selected (env: SOLC_VERSION)"),
+                        )
+                        .arg(
+                            Arg::new("worker-dir")
+                                .long("worker-dir")
+                                .default_value("tmp/worker")
+                                .help("The default work directory of the foundry env(env: REDIS_WORKER_DIR)"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("remove")
+                        .about("Remove a question, its versions stay archived")
+                        .arg(
+                            Arg::new("question-no")
+                                .short('n')
+                                .long("question-no")
+                                .required(true)
+                                .help("Question number"),
+                        ),
+                ),
+        )
         .get_matches();
 
     match matches.subcommand() {
         Some(("client", sub_matches)) => client(sub_matches),
         Some(("server", sub_matches)) => server(sub_matches),
+        Some(("local", sub_matches)) => local(sub_matches),
+        Some(("events", sub_matches)) => events(sub_matches),
+        Some(("cancel", sub_matches)) => cancel(sub_matches),
         Some(("init", sub_matches)) => init(sub_matches),
+        Some(("dead-letter", sub_matches)) => dead_letter(sub_matches),
+        Some(("question", sub_matches)) => question(sub_matches),
         _ => unreachable!(),
     }
 }
 
+// Resolves an option with the priority cmd param > env > default
+fn get_config(matches: &ArgMatches, name: &str, env_name: &str) -> String {
+    if matches.value_source(name) != Some(ValueSource::CommandLine) {
+        if let Ok(value) = env::var(env_name) {
+            return value;
+        }
+    }
+    matches.get_one::<String>(name).unwrap().to_string()
+}
+
 fn client(matches: &ArgMatches) {
     let mut directory;
     let mut question_no;
     let mut solc_version;
     let mut job_id;
     let mut timeout;
-    let mut connection_str;
 
     directory = String::from("usercode"); // default value
     let dir_env = env::var("DIRECTORY");
@@ -185,17 +589,18 @@ fn client(matches: &ArgMatches) {
         timeout = matches.get_one::<String>("timeout").unwrap().to_string();
     }
 
-    connection_str = String::from("127.0.0.1:6379");
-    let connection_str_env = env::var("CONNECTION_STR");
-    if connection_str_env.is_ok() {
-        connection_str = connection_str_env.unwrap();
-    }
-    if matches.value_source("connection-str") == Some(ValueSource::CommandLine) {
-        connection_str = matches
-            .get_one::<String>("connection-str")
-            .unwrap()
-            .to_string();
-    }
+    // The same defaults as the server, so both end up on the same request list
+    let endpoint = protocol::Endpoint {
+        redis_host: get_config(matches, "connection-str", "CONNECTION_STR"),
+        redis_prefix: get_config(matches, "redis-prefix", "REDIS_PREFIX"),
+        backend: QueueBackend::parse(
+            &get_config(matches, "queue-backend", "QUEUE_BACKEND"),
+            &get_config(matches, "redis-list-name", "REDIS_LIST_NAME"),
+            &get_config(matches, "stream-group", "STREAM_GROUP"),
+            0,
+        )
+        .expect("Queue backend should be list or stream"),
+    };
 
     // print!("{}", dir);
     let res = client::request(
@@ -204,10 +609,10 @@ fn client(matches: &ArgMatches) {
         question_no,
         job_id,
         timeout,
-        connection_str,
+        &endpoint,
     );
-    if res.is_err() {
-        print!("{:?}", res.unwrap_err());
+    if let Err(err) = res {
+        print!("{}", err.get_err_msg());
     }
 }
 
@@ -218,6 +623,7 @@ fn server(matches: &ArgMatches) {
     let mut redis_prefix_holder: Option<String> = None;
     let mut redis_worker_dir_holder: Option<String> = None;
     let mut redis_list_name_holder: Option<String> = None;
+    let mut server_id_holder: Option<String> = None;
 
     if matches.value_source("thread-num").unwrap() == ValueSource::CommandLine {
         thread_num_holder = Some(matches.get_one::<String>("thread-num").unwrap().to_string());
@@ -250,6 +656,10 @@ fn server(matches: &ArgMatches) {
         );
     }
 
+    if matches.value_source("server-id") == Some(ValueSource::CommandLine) {
+        server_id_holder = Some(matches.get_one::<String>("server-id").unwrap().to_string());
+    }
+
     let _thread_num_res = env::var("THREAD_NUM");
     if _thread_num_res.is_ok() && thread_num_holder.is_none() {
         thread_num_holder = Some(_thread_num_res.unwrap());
@@ -275,6 +685,12 @@ fn server(matches: &ArgMatches) {
         redis_list_name_holder = Some(_redis_list_name_res.unwrap());
     }
 
+    if let Ok(server_id) = env::var("SERVER_ID") {
+        if server_id_holder.is_none() {
+            server_id_holder = Some(server_id);
+        }
+    }
+
     if thread_num_holder.is_none() {
         thread_num_holder = Some(matches.get_one::<String>("thread-num").unwrap().to_string());
     }
@@ -311,21 +727,308 @@ fn server(matches: &ArgMatches) {
     let redis_prefix = redis_prefix_holder.unwrap();
     let redis_worker_dir = redis_worker_dir_holder.unwrap();
     let redis_list_name = redis_list_name_holder.unwrap();
+    let server_id = server_id_holder.unwrap_or_else(server::default_server_id);
+    let retry_policy = server::RetryPolicy {
+        max_retries: get_config(matches, "max-retries", "MAX_RETRIES")
+            .parse::<u32>()
+            .unwrap(),
+        backoff_secs: get_config(matches, "retry-backoff", "RETRY_BACKOFF")
+            .parse::<u64>()
+            .unwrap(),
+    };
+    let time_limits = server::TimeLimits {
+        build_secs: get_config(matches, "build-time-limit", "BUILD_TIME_LIMIT")
+            .parse::<u64>()
+            .unwrap(),
+        test_secs: get_config(matches, "test-time-limit", "TEST_TIME_LIMIT")
+            .parse::<u64>()
+            .unwrap(),
+        max_secs: get_config(matches, "max-time-limit", "MAX_TIME_LIMIT")
+            .parse::<u64>()
+            .unwrap(),
+    };
+    let sandbox = sandbox::SandboxConfig {
+        enabled: get_config(matches, "sandbox", "SANDBOX") == "on",
+        solc_dir: matches
+            .get_one::<String>("sandbox-solc-dir")
+            .cloned()
+            .or_else(|| env::var("SANDBOX_SOLC_DIR").ok())
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from(env::var("HOME").unwrap_or_default()).join(".svm")),
+        memory_mb: get_config(matches, "sandbox-memory", "SANDBOX_MEMORY")
+            .parse::<u64>()
+            .unwrap(),
+        max_processes: get_config(matches, "sandbox-processes", "SANDBOX_PROCESSES")
+            .parse::<u64>()
+            .unwrap(),
+        file_size_mb: get_config(matches, "sandbox-file-size", "SANDBOX_FILE_SIZE")
+            .parse::<u64>()
+            .unwrap(),
+    };
+    // Workers renew the claim on the entry they judge, one idle for longer than a few
+    // renewals belongs to a dead worker
+    let claim_idle = matches
+        .get_one::<String>("claim-idle")
+        .cloned()
+        .or_else(|| env::var("CLAIM_IDLE").ok())
+        .map_or(server::CLAIM_RENEW_SECS * 4, |secs| {
+            secs.parse::<u64>().unwrap()
+        });
+    let backend = QueueBackend::parse(
+        &get_config(matches, "queue-backend", "QUEUE_BACKEND"),
+        &redis_list_name,
+        &get_config(matches, "stream-group", "STREAM_GROUP"),
+        claim_idle as usize,
+    )
+    .expect("Queue backend should be list or stream");
+    // A single late renewal must not hand a job still being judged to another worker
+    if matches!(backend, QueueBackend::Stream { .. }) && claim_idle <= server::CLAIM_RENEW_SECS * 2
+    {
+        panic!(
+            "Claim idle of {}s should be above twice the {}s claims are renewed every",
+            claim_idle,
+            server::CLAIM_RENEW_SECS
+        );
+    }
 
     print!(
-        "{} {} {} {} {}",
-        thread_num, redis_host, redis_prefix, redis_worker_dir, redis_list_name
+        "{} {} {} {} {} {}",
+        thread_num, redis_host, redis_prefix, redis_worker_dir, redis_list_name, server_id
     );
 
+    let http_addr = matches
+        .get_one::<String>("http-addr")
+        .cloned()
+        .or_else(|| env::var("HTTP_ADDR").ok());
+    if let Some(http_addr) = http_addr {
+        let redis_host = redis_host.clone();
+        let redis_prefix = redis_prefix.clone();
+        let grader_token = matches
+            .get_one::<String>("grader-token")
+            .cloned()
+            .or_else(|| env::var("GRADER_TOKEN").ok());
+        let backend = backend.clone();
+        thread::spawn(move || {
+            let res = http::serve(
+                &http_addr,
+                &redis_host,
+                &redis_prefix,
+                grader_token,
+                &backend,
+            );
+            if let Err(err) = res {
+                log::error!("{}", err.get_err_msg());
+            }
+        });
+    }
+
     server::start(
         thread_num.parse::<i32>().unwrap(),
         redis_host.as_str(),
         redis_prefix.as_str(),
         redis_worker_dir.as_str(),
-        redis_list_name.as_str(),
+        server_id.as_str(),
+        &server::WorkerSettings {
+            retry_policy,
+            time_limits,
+            sandbox,
+            question_bank: Some(question::QuestionBank::new(Path::new(&get_config(
+                matches,
+                "question-bank",
+                "QUESTION_BANK",
+            )))),
+        },
+        &backend,
     );
 }
 
+fn local(matches: &ArgMatches) {
+    let res = client::judge_local(
+        get_config(matches, "directory", "DIRECTORY"),
+        get_config(matches, "solc-version", "SOLC_VERSION"),
+        get_config(matches, "question-no", "QUESTION_NO"),
+        get_config(matches, "job-id", "JOB_ID"),
+        get_config(matches, "worker-dir", "REDIS_WORKER_DIR"),
+    );
+    if let Err(err) = res {
+        print!("{}", err.get_err_msg());
+    }
+}
+
+fn events(matches: &ArgMatches) {
+    let redis_host = get_config(matches, "redis-host", "REDIS_HOST");
+    let redis_prefix = get_config(matches, "redis-prefix", "REDIS_PREFIX");
+    let job_id = matches.get_one::<String>("job-id").unwrap();
+
+    let res = server::follow_job(
+        &redis_host,
+        &redis_prefix,
+        job_id,
+        |event| {
+            println!("{}", event);
+            true
+        },
+        || true,
+    );
+    match res {
+        Ok(true) => {}
+        Ok(false) => println!("Job {} not found", job_id),
+        Err(err) => println!("{}", err.get_err_msg()),
+    }
+}
+
+fn cancel(matches: &ArgMatches) {
+    let redis_host = get_config(matches, "redis-host", "REDIS_HOST");
+    let redis_prefix = get_config(matches, "redis-prefix", "REDIS_PREFIX");
+    let job_id = matches.get_one::<String>("job-id").unwrap();
+
+    let res = queue::result::RedisResultSink::new(&redis_host)
+        .and_then(|mut sink| server::cancel_job(&mut sink, &redis_prefix, job_id));
+    match res {
+        Ok(()) => println!("Job {} cancelled", job_id),
+        Err(err) => println!("{}", err.get_err_msg()),
+    }
+}
+
 fn init(matches: &ArgMatches) {
     let _ = server::init_cache_file();
 }
+
+fn dead_letter(matches: &ArgMatches) {
+    let redis_host = get_config(matches, "redis-host", "REDIS_HOST");
+    let redis_prefix = get_config(matches, "redis-prefix", "REDIS_PREFIX");
+    let backend = QueueBackend::parse(
+        &get_config(matches, "queue-backend", "QUEUE_BACKEND"),
+        &get_config(matches, "redis-list-name", "REDIS_LIST_NAME"),
+        &get_config(matches, "stream-group", "STREAM_GROUP"),
+        0,
+    )
+    .expect("Queue backend should be list or stream");
+
+    match matches.subcommand() {
+        Some(("list", _)) => {
+            match server::list_dead_letters(&redis_host, &redis_prefix, &backend) {
+                Ok(entries) => {
+                    for entry in entries {
+                        println!("{}", entry);
+                    }
+                }
+                Err(err) => println!("{:?}", err),
+            }
+        }
+        Some(("replay", sub_matches)) => {
+            let job_id = sub_matches.get_one::<String>("job-id").map(|s| s.as_str());
+            match server::replay_dead_letters(&redis_host, &redis_prefix, &backend, job_id) {
+                Ok(replayed) => println!("Replayed {} jobs", replayed),
+                Err(err) => println!("{:?}", err),
+            }
+        }
+        _ => unreachable!(),
+    }
+}
+
+fn question(matches: &ArgMatches) {
+    let bank = question::QuestionBank::new(Path::new(&get_config(
+        matches,
+        "question-bank",
+        "QUESTION_BANK",
+    )));
+
+    let res = match matches.subcommand() {
+        Some(("add", sub_matches)) => question_add(&bank, sub_matches),
+        Some(("list", _)) => bank.list().map(|questions| {
+            for q in questions {
+                println!(
+                    "{}\tv{}\tsolc {}\t{} files",
+                    q.question_no,
+                    q.meta.version,
+                    if q.meta.solc.is_empty() {
+                        "*"
+                    } else {
+                        &q.meta.solc
+                    },
+                    q.files().len()
+                );
+            }
+        }),
+        Some(("show", sub_matches)) => bank
+            .find(sub_matches.get_one::<String>("question-no").unwrap())
+            .map(|q| {
+                println!("{}", serde_json::to_string_pretty(&q.meta).unwrap());
+                for file in q.files() {
+                    println!("{}", file);
+                }
+            }),
+        Some(("validate", sub_matches)) => question_validate(&bank, sub_matches),
+        Some(("remove", sub_matches)) => {
+            let question_no = sub_matches.get_one::<String>("question-no").unwrap();
+            bank.remove(question_no)
+                .map(|_| println!("Question {} removed", question_no))
+        }
+        _ => unreachable!(),
+    };
+    if let Err(err) = res {
+        println!("{}", err.get_err_msg());
+        exit(1);
+    }
+}
+
+fn question_add(
+    bank: &question::QuestionBank,
+    matches: &ArgMatches,
+) -> Result<(), question::ErrorCode> {
+    let question_no = matches.get_one::<String>("question-no").unwrap();
+    let directory = Path::new(matches.get_one::<String>("directory").unwrap());
+
+    // The options win over the question.json shipped with the question
+    let mut meta = match std::fs::read_to_string(directory.join("question.json")) {
+        Ok(raw) => serde_json::from_str::<question::QuestionMeta>(&raw)
+            .map_err(|err| question::ErrorCode::InvalidQuestion(err.to_string()))?,
+        Err(_) => question::QuestionMeta::default(),
+    };
+    if let Some(solc) = matches.get_one::<String>("solc") {
+        meta.solc = solc.to_string();
+    }
+    if let Some(libraries) = matches.get_many::<String>("library") {
+        meta.libraries = Some(libraries.cloned().collect());
+    }
+    if let Some(forbid) = matches.get_many::<String>("forbid") {
+        let mut forbidden = meta.forbidden();
+        forbidden.extend(forbid.cloned());
+        meta.forbidden = Some(forbidden);
+    }
+
+    let q = bank.add(question_no, directory, meta)?;
+    println!("Question {} added as v{}", q.question_no, q.meta.version);
+    Ok(())
+}
+
+fn question_validate(
+    bank: &question::QuestionBank,
+    matches: &ArgMatches,
+) -> Result<(), question::ErrorCode> {
+    let q = bank.find(matches.get_one::<String>("question-no").unwrap())?;
+    let output = client::judge_reference_solution(
+        bank,
+        &q,
+        &get_config(matches, "solc-version", "SOLC_VERSION"),
+        &get_config(matches, "worker-dir", "REDIS_WORKER_DIR"),
+    )
+    .map_err(|err| question::ErrorCode::InvalidQuestion(err.get_err_msg()))?;
+
+    let total_score = output["total_score"].as_f64().unwrap_or(0.0);
+    let get_score = output["get_score"].as_f64().unwrap_or(0.0);
+    // Bonus tests may take the reference solution above the total
+    if output["code"] != 0 || total_score == 0.0 || get_score < total_score {
+        println!("{:#}", output);
+        return Err(question::ErrorCode::InvalidQuestion(format!(
+            "the reference solution of {} v{} scores {}/{}",
+            q.question_no, q.meta.version, get_score, total_score
+        )));
+    }
+    println!(
+        "Question {} v{} is valid, the reference solution scores {}/{}",
+        q.question_no, q.meta.version, get_score, total_score
+    );
+    Ok(())
+}
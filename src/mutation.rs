@@ -0,0 +1,589 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::question::MutationSettings;
+use crate::rubric;
+use crate::scan;
+
+// Mutated operators, the longer ones first so `<=` is never read as `<`
+const OPERATORS: [&str; 32] = [
+    ">>>=", "<<=", ">>=", ">>>", "**=", "&&", "||", "==", "!=", "<=", ">=", "++", "--", "+=", "-=",
+    "*=", "/=", "%=", "&=", "|=", "^=", "<<", ">>", "**", "=>", "->", "<", ">", "+", "-", "*", "/",
+];
+const SWAPS: [(&str, &str); 12] = [
+    ("+", "-"),
+    ("-", "+"),
+    ("*", "/"),
+    ("/", "*"),
+    ("<", "<="),
+    ("<=", "<"),
+    (">", ">="),
+    (">=", ">"),
+    ("==", "!="),
+    ("!=", "=="),
+    ("&&", "||"),
+    ("||", "&&"),
+];
+
+/* Mutant
+
+   @dev A change to a file of the reference solution: the bytes `start..end` of `file`
+        replaced by `replacement`. `operator` is what was changed:
+
+        operator-swap    an arithmetic, comparison or logical operator swapped
+        require-removal  a `require(...)` statement removed
+        condition-flip   the condition of an `if` negated
+
+*/
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Mutant {
+    pub id: usize,
+    pub file: String,
+    pub line: usize,
+    pub operator: String,
+    pub original: String,
+    pub replacement: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Mutant {
+    pub fn apply(&self, source: &str) -> Option<String> {
+        let before = source.get(..self.start)?;
+        let after = source.get(self.end..)?;
+        Some(format!("{}{}{}", before, self.replacement, after))
+    }
+}
+
+fn line_of(source: &str, offset: usize) -> usize {
+    source[..offset].matches('\n').count() + 1
+}
+
+// Offset right after the parenthesis closing the one at `open`
+fn closing_paren(masked: &[u8], open: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (i, byte) in masked.iter().enumerate().skip(open) {
+        match byte {
+            b'(' => depth += 1,
+            b')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i + 1);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+fn is_ident(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_' || b == b'$'
+}
+
+// Whether `text` ends with the keyword `word` rather than an identifier ending in it
+fn ends_with_keyword(text: &str, word: &str) -> bool {
+    text.strip_suffix(word)
+        .is_some_and(|rest| !rest.bytes().last().is_some_and(is_ident))
+}
+
+// Offsets of the keyword `word` followed by `(`, skipping longer identifiers containing it
+fn keyword_calls(masked: &str, word: &str) -> Vec<(usize, usize)> {
+    let bytes = masked.as_bytes();
+    let mut found = vec![];
+    for (start, _) in masked.match_indices(word) {
+        let end = start + word.len();
+        if (start > 0 && is_ident(bytes[start - 1])) || bytes.get(end).is_some_and(|b| is_ident(*b))
+        {
+            continue;
+        }
+        let open = end + masked[end..].len() - masked[end..].trim_start().len();
+        if bytes.get(open) == Some(&b'(') {
+            found.push((start, open));
+        }
+    }
+    found
+}
+
+/* mutate
+
+   @dev Every mutant of a Solidity source, comments, strings and pragmas left alone.
+        Arithmetic operators are only swapped where they are binary, a unary minus
+        stays
+
+*/
+pub fn mutate(file: &str, source: &str) -> Vec<Mutant> {
+    let masked = scan::strip_comments_and_strings(source);
+    let bytes = masked.as_bytes();
+    let mut mutants = vec![];
+    let mut push = |operator: &str, start: usize, end: usize, replacement: String| {
+        mutants.push(Mutant {
+            id: 0,
+            file: file.to_string(),
+            line: line_of(source, start),
+            operator: operator.to_string(),
+            original: source[start..end].to_string(),
+            replacement,
+            start,
+            end,
+        });
+    };
+
+    let mut i = 0;
+    while i < bytes.len() {
+        let Some(op) = OPERATORS
+            .iter()
+            .find(|op| bytes[i..].starts_with(op.as_bytes()))
+        else {
+            i += 1;
+            continue;
+        };
+        let line_start = masked[..i].rfind('\n').map_or(0, |n| n + 1);
+        let in_pragma = masked[line_start..].trim_start().starts_with("pragma");
+        // An operand ends right before a binary operator, `return` is no operand
+        let before = masked[..i].trim_end();
+        let binary = before
+            .bytes()
+            .last()
+            .is_some_and(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b')' || b == b']')
+            && !ends_with_keyword(before, "return");
+        let arithmetic = ["+", "-", "*", "/"].contains(op);
+        if let Some((_, to)) = SWAPS.iter().find(|(from, _)| from == op) {
+            if !in_pragma && (binary || !arithmetic) {
+                push("operator-swap", i, i + op.len(), to.to_string());
+            }
+        }
+        i += op.len();
+    }
+
+    for (start, open) in keyword_calls(&masked, "require") {
+        let Some(close) = closing_paren(bytes, open) else {
+            continue;
+        };
+        let rest = &masked[close..];
+        if rest.trim_start().starts_with(';') {
+            let end = close + rest.len() - rest.trim_start().len() + 1;
+            // Lines are kept so the other lines of the mutant do not move
+            let newlines = "\n".repeat(source[start..end].matches('\n').count());
+            push("require-removal", start, end, newlines);
+        }
+    }
+
+    for (_, open) in keyword_calls(&masked, "if") {
+        let Some(close) = closing_paren(bytes, open) else {
+            continue;
+        };
+        let condition = &source[open + 1..close - 1];
+        push("condition-flip", open, close, format!("(!({}))", condition));
+    }
+
+    mutants.sort_by_key(|mutant| mutant.start);
+    mutants
+}
+
+fn collect_sources(dir: &Path, base: &Path, sources: &mut Vec<(String, String)>) {
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .collect();
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            collect_sources(&path, base, sources);
+        } else if path.extension().is_some_and(|ext| ext == "sol") {
+            if let (Ok(relative), Ok(source)) = (path.strip_prefix(base), fs::read_to_string(&path))
+            {
+                sources.push((relative.to_string_lossy().into_owned(), source));
+            }
+        }
+    }
+}
+
+/* generate
+
+   @dev The mutants of the `contracts/` of `solution`, numbered from 1. Beyond `max`
+        an evenly spread selection over the files is kept
+
+*/
+pub fn generate(solution: &Path, max: usize) -> Vec<Mutant> {
+    let mut sources = vec![];
+    collect_sources(&solution.join("contracts"), solution, &mut sources);
+    let mut mutants: Vec<Mutant> = sources
+        .iter()
+        .flat_map(|(file, source)| mutate(file, source))
+        .collect();
+    if mutants.len() > max {
+        let count = mutants.len();
+        mutants = (0..max).map(|i| mutants[i * count / max].clone()).collect();
+    }
+    for (i, mutant) in mutants.iter_mut().enumerate() {
+        mutant.id = i + 1;
+    }
+    mutants
+}
+
+/* Outcome
+
+   @dev What the tests of a student did to a mutant. `Killed` names the first test that
+        passed against the reference solution and failed against the mutant. A mutant
+        that does not compile is `Stillborn` and not counted, one the tests never finish
+        on or crash forge on counts as killed. `Failed` is a mutant the worker could not
+        run at all
+
+*/
+#[derive(Debug, Clone)]
+pub enum Outcome {
+    Killed(String),
+    Survived,
+    Stillborn,
+    TimedOut,
+    Cancelled,
+    Failed(String),
+}
+
+impl Outcome {
+    pub fn status(&self) -> &'static str {
+        match self {
+            Outcome::Killed(_) => "killed",
+            Outcome::Survived => "survived",
+            Outcome::Stillborn => "stillborn",
+            Outcome::TimedOut => "timeout",
+            Outcome::Cancelled => "cancelled",
+            Outcome::Failed(_) => "failed",
+        }
+    }
+}
+
+/* score
+
+   @dev Scores the outcomes of the mutants against the target of the question. Returns
+        the total, the score earned and the `mutation` of the result. Stillborn mutants
+        are not counted, and nothing was measured when no mutant compiled, which earns
+        nothing
+
+*/
+pub fn score(
+    settings: &MutationSettings,
+    mutants: &[Mutant],
+    outcomes: &HashMap<usize, Outcome>,
+    baseline_failures: &[String],
+) -> (f64, f64, Value) {
+    let counted = outcomes
+        .values()
+        .filter(|outcome| !matches!(outcome, Outcome::Stillborn))
+        .count();
+    let killed = outcomes
+        .values()
+        .filter(|outcome| matches!(outcome, Outcome::Killed(_) | Outcome::TimedOut))
+        .count();
+    let percent = if counted == 0 {
+        0.0
+    } else {
+        killed as f64 * 100.0 / counted as f64
+    };
+    let earned = if counted == 0 {
+        0.0
+    } else if settings.target <= 0.0 {
+        settings.score
+    } else {
+        settings.score * (percent / settings.target).min(1.0)
+    };
+    let mutants: Vec<Value> = mutants
+        .iter()
+        .map(|mutant| {
+            let outcome = &outcomes[&mutant.id];
+            let killed_by = match outcome {
+                Outcome::Killed(test) => Some(test),
+                _ => None,
+            };
+            json!({
+                "id": mutant.id,
+                "file": &mutant.file,
+                "line": mutant.line,
+                "operator": &mutant.operator,
+                "original": &mutant.original,
+                "replacement": &mutant.replacement,
+                "status": outcome.status(),
+                "killedBy": killed_by,
+            })
+        })
+        .collect();
+    let mut mutation = json!({
+        "total": counted,
+        "killed": killed,
+        "percent": (percent * 100.0).round() / 100.0,
+        "target": settings.target,
+        "score": rubric::score_json(settings.score),
+        "earned": rubric::score_json(earned),
+        "baselineFailures": baseline_failures,
+        "mutants": mutants,
+    });
+    if counted == 0 {
+        mutation["msg"] = json!("No mutant compiled, there was nothing to kill");
+    }
+    (settings.score, earned, mutation)
+}
+
+/* MutantTask
+
+   @dev A mutant of a job to run with its tests, in a copy of the job directory
+        `workspace`. `baseline_failures` are the tests already failing against the
+        reference solution, they kill nothing
+
+*/
+#[derive(Debug)]
+pub struct MutantTask {
+    pub judge_job_id: String,
+    pub workspace: PathBuf,
+    pub solc_version: String,
+    pub limit: Duration,
+    pub mutant: Mutant,
+    pub baseline_failures: Arc<Vec<String>>,
+    // The fuzz settings of the question, mutants get the inputs the reference got
+    pub fuzz_env: Vec<(&'static str, String)>,
+    pub results: Sender<(usize, Outcome)>,
+}
+
+/* MutantPool
+
+   @dev Mutants waiting to run, shared by the workers of a server. Idle workers take
+        them before new jobs, and so does the worker waiting for the mutants of its job,
+        so a job never waits on a pool every worker is blocked in
+
+*/
+#[derive(Debug, Clone, Default)]
+pub struct MutantPool {
+    tasks: Arc<Mutex<VecDeque<MutantTask>>>,
+}
+
+impl MutantPool {
+    pub fn push(&self, tasks: impl IntoIterator<Item = MutantTask>) {
+        if let Ok(mut queue) = self.tasks.lock() {
+            queue.extend(tasks);
+        }
+    }
+
+    pub fn take(&self) -> Option<MutantTask> {
+        self.tasks.lock().ok()?.pop_front()
+    }
+
+    // Drops the mutants of a cancelled job nobody took yet
+    pub fn drop_job(&self, judge_job_id: &str) {
+        if let Ok(mut queue) = self.tasks.lock() {
+            queue.retain(|task| task.judge_job_id != judge_job_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::sync::mpsc;
+
+    const VAULT: &str = "pragma solidity >=0.8.0;
+
+contract Vault {
+    // a + b in a comment
+    string note = \"a - b\";
+
+    function withdraw(uint a, uint b) public pure returns (int) {
+        require(a >= b, \"low\");
+        if (a == b) {
+            return -1;
+        }
+        return int(a - b);
+    }
+}
+";
+
+    fn of<'a>(mutants: &'a [Mutant], operator: &str) -> Vec<&'a Mutant> {
+        mutants
+            .iter()
+            .filter(|mutant| mutant.operator == operator)
+            .collect()
+    }
+
+    #[test]
+    fn operators_are_swapped_in_code_only() {
+        let mutants = mutate("contracts/Vault.sol", VAULT);
+        let swaps: Vec<(&str, &str)> = of(&mutants, "operator-swap")
+            .iter()
+            .map(|mutant| (mutant.original.as_str(), mutant.replacement.as_str()))
+            .collect();
+        // Not the pragma, the comment, the string or the unary minus
+        assert_eq!(swaps, vec![(">=", ">"), ("==", "!="), ("-", "+")]);
+
+        let swap = of(&mutants, "operator-swap")[2];
+        let mutated = swap.apply(VAULT).unwrap();
+        assert!(mutated.contains("return int(a + b);"));
+        assert_eq!(swap.line, 12);
+    }
+
+    #[test]
+    fn requires_are_removed_keeping_the_lines() {
+        let source = "contract A {\n    function f(uint a) public {\n        require(\n            a > 0,\n            \"zero\"\n        );\n    }\n}\n";
+        let mutants = mutate("contracts/A.sol", source);
+        let removals = of(&mutants, "require-removal");
+        assert_eq!(removals.len(), 1);
+        assert_eq!(removals[0].line, 3);
+
+        let mutated = removals[0].apply(source).unwrap();
+        assert!(!mutated.contains("require"));
+        assert_eq!(mutated.lines().count(), source.lines().count());
+        // `requireOwner(...)` is not a require
+        assert!(of(
+            &mutate("contracts/A.sol", "requireOwner(x);"),
+            "require-removal"
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn conditions_are_flipped() {
+        let mutants = mutate("contracts/Vault.sol", VAULT);
+        let flips = of(&mutants, "condition-flip");
+        assert_eq!(flips.len(), 1);
+        assert_eq!(flips[0].original, "(a == b)");
+        assert_eq!(flips[0].replacement, "(!(a == b))");
+        assert!(flips[0].apply(VAULT).unwrap().contains("if (!(a == b)) {"));
+    }
+
+    #[test]
+    fn generate_keeps_at_most_max_the_same_every_time() {
+        let solution = env::temp_dir().join(format!("judger-mutation-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&solution);
+        fs::create_dir_all(solution.join("contracts").join("lib")).unwrap();
+        fs::create_dir_all(solution.join("test")).unwrap();
+        fs::write(solution.join("contracts").join("Vault.sol"), VAULT).unwrap();
+        fs::write(
+            solution.join("contracts").join("lib").join("Math.sol"),
+            "contract Math { function f(uint a) public pure returns (uint) { return (a * 2 + a - 1) / 3; } }",
+        )
+        .unwrap();
+        fs::write(
+            solution.join("test").join("Vault.t.sol"),
+            "contract T { function t() public { require(1 < 2); } }",
+        )
+        .unwrap();
+
+        let all = generate(&solution, 100);
+        assert!(all
+            .iter()
+            .all(|mutant| mutant.file.starts_with("contracts/")));
+        assert_eq!(
+            all.iter().map(|mutant| mutant.id).collect::<Vec<_>>(),
+            (1..=all.len()).collect::<Vec<_>>()
+        );
+
+        let some = generate(&solution, 3);
+        let again = generate(&solution, 3);
+        assert_eq!(some.len(), 3);
+        let picked = |mutants: &[Mutant]| -> Vec<(usize, String, usize)> {
+            mutants
+                .iter()
+                .map(|mutant| (mutant.id, mutant.file.clone(), mutant.start))
+                .collect()
+        };
+        assert_eq!(picked(&some), picked(&again));
+        // Spread over both files rather than the first three of one
+        assert!(some
+            .iter()
+            .any(|mutant| mutant.file == "contracts/Vault.sol"));
+        assert!(some
+            .iter()
+            .any(|mutant| mutant.file == "contracts/lib/Math.sol"));
+
+        let _ = fs::remove_dir_all(&solution);
+    }
+
+    // Mutants numbered 1 to `count`
+    fn numbered(count: usize) -> Vec<Mutant> {
+        (1..=count)
+            .map(|id| Mutant {
+                id,
+                ..mutate("contracts/A.sol", "uint x = a + b;")[0].clone()
+            })
+            .collect()
+    }
+
+    fn task(judge_job_id: &str, id: usize, results: &Sender<(usize, Outcome)>) -> MutantTask {
+        MutantTask {
+            judge_job_id: judge_job_id.to_string(),
+            workspace: PathBuf::from("/tmp"),
+            solc_version: String::from("0.8.20"),
+            limit: Duration::from_secs(1),
+            mutant: numbered(id).pop().unwrap(),
+            baseline_failures: Arc::new(vec![]),
+            fuzz_env: vec![],
+            results: results.clone(),
+        }
+    }
+
+    #[test]
+    fn pool_hands_out_tasks_in_order_and_drops_cancelled_jobs() {
+        let (sender, _results) = mpsc::channel();
+        let pool = MutantPool::default();
+        let shared = pool.clone();
+        pool.push([task("a", 1, &sender), task("b", 1, &sender)]);
+        shared.push([task("a", 2, &sender)]);
+
+        let first = pool.take().unwrap();
+        assert_eq!((first.judge_job_id.as_str(), first.mutant.id), ("a", 1));
+        shared.drop_job("a");
+        let next = pool.take().unwrap();
+        assert_eq!((next.judge_job_id.as_str(), next.mutant.id), ("b", 1));
+        assert!(pool.take().is_none());
+    }
+
+    fn settings(target: f64) -> MutationSettings {
+        MutationSettings {
+            score: 10.0,
+            target,
+            max: 50,
+        }
+    }
+
+    #[test]
+    fn score_counts_killed_and_timed_out_over_compiled_mutants() {
+        let mutants = numbered(4);
+        let outcomes = HashMap::from([
+            (1, Outcome::Killed(String::from("test_withdraw"))),
+            (2, Outcome::Survived),
+            (3, Outcome::Stillborn),
+            (4, Outcome::TimedOut),
+        ]);
+
+        let (total, earned, mutation) = score(&settings(100.0), &mutants, &outcomes, &[]);
+        assert_eq!(total, 10.0);
+        assert!((earned - 20.0 / 3.0).abs() < 1e-9);
+        assert_eq!(mutation["total"], 3);
+        assert_eq!(mutation["killed"], 2);
+        assert_eq!(mutation["percent"], 66.67);
+        assert_eq!(mutation["mutants"][0]["killedBy"], "test_withdraw");
+        assert_eq!(mutation["mutants"][2]["status"], "stillborn");
+
+        // Reaching the target earns the whole score
+        let (_, earned, _) = score(&settings(60.0), &mutants, &outcomes, &[]);
+        assert_eq!(earned, 10.0);
+    }
+
+    #[test]
+    fn score_earns_nothing_when_no_mutant_compiled() {
+        let mutants = numbered(2);
+        let outcomes = HashMap::from([(1, Outcome::Stillborn), (2, Outcome::Stillborn)]);
+        let (_, earned, mutation) = score(&settings(0.0), &mutants, &outcomes, &[]);
+        assert_eq!(earned, 0.0);
+        assert_eq!(mutation["total"], 0);
+        assert!(mutation["msg"].is_string());
+    }
+}
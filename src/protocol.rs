@@ -0,0 +1,69 @@
+/* protocol
+
+   @dev The Redis layout shared by the client, the HTTP front door and the workers.
+        Every key derives from the same `--redis-prefix`, so a client and a server
+        started with the defaults talk to each other
+
+*/
+use crate::queue::QueueBackend;
+
+pub const DEFAULT_REDIS_HOST: &str = "redis://127.0.0.1/1";
+pub const DEFAULT_REDIS_PREFIX: &str = "smc-open-solidity-judge";
+pub const DEFAULT_LIST_NAME: &str = "requests";
+pub const DEFAULT_STREAM_GROUP: &str = "judger";
+
+// How long a request marker waits for its job, and a response for its submitter
+pub const RESPONSE_TTL_SECS: usize = 60 * 60;
+
+// How long the status record of a job can be looked up after its last update
+pub const JOB_RECORD_TTL_SECS: usize = 24 * 60 * 60;
+
+/* Endpoint
+
+   @dev Where jobs are submitted to: the Redis server, the key prefix and the queue
+        under that prefix, the request list or the stream the server reads
+
+*/
+#[derive(Debug, Clone)]
+pub struct Endpoint {
+    pub redis_host: String,
+    pub redis_prefix: String,
+    pub backend: QueueBackend,
+}
+
+// The list jobs are pushed onto and the list backend reads from
+pub fn requests_key(redis_prefix: &str, list_name: &str) -> String {
+    format!("{}:{}", redis_prefix, list_name)
+}
+
+// `jobKey` of a job message, identifies the question the job answers
+pub fn job_key(redis_prefix: &str, question_no: &str) -> String {
+    format!("{}:{}", redis_prefix, question_no)
+}
+
+// Set by the submitter while it waits for the response of the job
+pub fn request_key(redis_prefix: &str, judge_job_id: &str) -> String {
+    format!("{}:request:{}", redis_prefix, judge_job_id)
+}
+
+pub fn response_key(redis_prefix: &str, judge_job_id: &str) -> String {
+    format!("{}:response:{}", redis_prefix, judge_job_id)
+}
+
+// Set by whoever cancels the job, workers drop or kill the job while it exists
+pub fn cancel_key(redis_prefix: &str, judge_job_id: &str) -> String {
+    format!("{}:cancel:{}", redis_prefix, judge_job_id)
+}
+
+pub fn job_record_key(redis_prefix: &str, judge_job_id: &str) -> String {
+    format!("{}:job:{}", redis_prefix, judge_job_id)
+}
+
+// The full result of a job, hidden tests included, kept next to its status record
+pub fn grader_result_key(redis_prefix: &str, judge_job_id: &str) -> String {
+    format!("{}:grader:{}", redis_prefix, judge_job_id)
+}
+
+pub fn job_events_channel(redis_prefix: &str, judge_job_id: &str) -> String {
+    format!("{}:events:{}", redis_prefix, judge_job_id)
+}
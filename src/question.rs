@@ -0,0 +1,787 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+use semver::{Version, VersionReq};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::gas;
+use crate::mutation::{self, Mutant};
+use crate::scan::{self, DEFAULT_FORBIDDEN};
+use crate::solidity;
+
+// Directories of a question copied over the submission, `test/` replaces the student's
+const OVERLAY_DIRS: [&str; 2] = ["test", "fixtures"];
+const META_FILE: &str = "question.json";
+// Mutants of the reference solution, generated for questions with `mutation`
+const MUTANTS_FILE: &str = "mutants.json";
+// Fuzz seed of the questions that pick none, and of `judger local`
+pub const DEFAULT_FUZZ_SEED: &str = "0x2a";
+const UINT256_MAX: &str =
+    "115792089237316195423570985008687907853269984665640564039457584007913129639935";
+// The reference solution, laid out like a submission
+const SOLUTION_DIR: &str = "solution";
+// Replaced versions of the questions, `{bank}/.archive/{questionNo}/v{version}/`
+const ARCHIVE_DIR: &str = ".archive";
+
+#[derive(Debug)]
+pub enum ErrorCode {
+    UnknownQuestion(String),
+    BankReadFailure(String),
+    BankWriteFailure(String),
+    OverlayFailure(String),
+    InvalidQuestion(String),
+    SolcNotAllowed(String),
+    LibraryNotAllowed(String),
+}
+
+impl ErrorCode {
+    pub fn get_err_msg(&self) -> String {
+        match self {
+            ErrorCode::UnknownQuestion(question_no) => {
+                format!("Unknown question {}", question_no)
+            }
+            ErrorCode::BankReadFailure(err) => format!("Read question bank failed: {}", err),
+            ErrorCode::BankWriteFailure(err) => format!("Write question bank failed: {}", err),
+            ErrorCode::OverlayFailure(err) => format!("Overlay question failed: {}", err),
+            ErrorCode::InvalidQuestion(err) => format!("Invalid question: {}", err),
+            ErrorCode::SolcNotAllowed(err) => format!("Solc version not allowed: {}", err),
+            ErrorCode::LibraryNotAllowed(err) => format!("Library not allowed: {}", err),
+        }
+    }
+}
+
+fn default_version() -> u32 {
+    1
+}
+
+/* QuestionMeta
+
+   @dev `question.json` of a question.
+
+        version    bumped every time the question is replaced, reported with the results
+        solc       semver range the `solcVersion` of a job has to match, e.g. ">=0.8.0, <0.9.0"
+        libraries  import roots the submission may use besides its own files, e.g.
+                   "forge-std" or "@openzeppelin", any when omitted
+        scores     overrides the score annotations of the official tests, keyed by
+                   test function name
+        forbidden  cheatcodes (`vm.ffi`) and constructs (`assembly`, `selfdestruct`,
+                   `delegatecall`) the submission may not use, `scan::DEFAULT_FORBIDDEN`
+                   when omitted
+        gas        gas budgets of contract functions, scored from the gas report
+        coverage   makes it a question on writing tests: the student's `test/` runs
+                   against the reference solution and is scored by the line and branch
+                   coverage it reaches
+        mutation   makes it a question on writing tests as well, scored by the share of
+                   mutants of the reference solution the student's tests kill
+        fuzz       `runs`, `seed` and `max_test_rejects` of the fuzz tests
+        invariant  `runs`, `depth` and `fail_on_revert` of the invariant tests
+
+*/
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct QuestionMeta {
+    #[serde(default = "default_version")]
+    pub version: u32,
+
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub solc: String,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub libraries: Option<Vec<String>>,
+
+    #[serde(default)]
+    pub scores: HashMap<String, f64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub forbidden: Option<Vec<String>>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub gas: Vec<GasBudget>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub coverage: Option<CoverageTargets>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mutation: Option<MutationSettings>,
+
+    #[serde(default)]
+    pub fuzz: FuzzSettings,
+
+    #[serde(default)]
+    pub invariant: InvariantSettings,
+}
+
+fn default_partial() -> f64 {
+    gas::DEFAULT_PARTIAL
+}
+
+fn default_measure() -> String {
+    String::from("mean")
+}
+
+/* GasBudget
+
+   @dev A gas budget of a contract function, held against the gas report of the tests.
+
+        function  `Contract.function`, or `Contract.function(uint256)` for one overload
+        target    the whole `score` at or under it
+        ceiling   `partial` of the score at or under it, nothing above
+        measure   `min`, `mean`, `median` or `max` of the calls, `mean` by default
+
+*/
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GasBudget {
+    pub function: String,
+    pub target: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ceiling: Option<u64>,
+    pub score: f64,
+    #[serde(default = "default_partial")]
+    pub partial: f64,
+    #[serde(default = "default_measure")]
+    pub measure: String,
+}
+
+impl GasBudget {
+    fn check(&self) -> Result<(), String> {
+        if !self.function.contains('.') {
+            return Err(format!("{}: expected Contract.function", self.function));
+        }
+        if self.ceiling.is_some_and(|ceiling| ceiling < self.target) {
+            return Err(format!("{}: ceiling below target", self.function));
+        }
+        if !(0.0..=1.0).contains(&self.partial) || self.score < 0.0 {
+            return Err(format!(
+                "{}: partial has to be within 0 and 1, score positive",
+                self.function
+            ));
+        }
+        if !gas::MEASURES.contains(&self.measure.as_str()) {
+            return Err(format!(
+                "{}: measure is none of {}",
+                self.function,
+                gas::MEASURES.join(", ")
+            ));
+        }
+        Ok(())
+    }
+}
+
+/* CoverageTargets
+
+   @dev What the tests of a student have to cover of the reference solution, in percent.
+        Short of a target, its score is earned in proportion to the coverage reached
+
+*/
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CoverageTargets {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lines: Option<CoverageTarget>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branches: Option<CoverageTarget>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CoverageTarget {
+    pub target: f64,
+    pub score: f64,
+}
+
+impl CoverageTargets {
+    fn check(&self) -> Result<(), String> {
+        if self.lines.is_none() && self.branches.is_none() {
+            return Err(String::from(
+                "coverage: expected a lines or branches target",
+            ));
+        }
+        for (name, target) in [("lines", &self.lines), ("branches", &self.branches)] {
+            let Some(target) = target else {
+                continue;
+            };
+            if !(0.0..=100.0).contains(&target.target) || target.score < 0.0 {
+                return Err(format!(
+                    "coverage: {} target has to be within 0 and 100, score positive",
+                    name
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn default_killed_target() -> f64 {
+    100.0
+}
+
+fn default_max_mutants() -> usize {
+    50
+}
+
+/* MutationSettings
+
+   @dev How the mutants of a question are scored: the whole `score` once `target`
+        percent of them are killed, in proportion below. At most `max` mutants are run
+
+*/
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MutationSettings {
+    pub score: f64,
+    #[serde(default = "default_killed_target")]
+    pub target: f64,
+    #[serde(default = "default_max_mutants")]
+    pub max: usize,
+}
+
+impl MutationSettings {
+    fn check(&self) -> Result<(), String> {
+        if !(0.0..=100.0).contains(&self.target) || self.score < 0.0 || self.max == 0 {
+            return Err(String::from(
+                "mutation: target has to be within 0 and 100, score positive, max above 0",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/* FuzzSettings
+
+   @dev The `[fuzz]` settings of `foundry.toml` a question sets, the ones of the
+        working directory apply to those it leaves out. The seed is always set, to
+        `DEFAULT_FUZZ_SEED` unless the question picks one, so a submission gets the
+        same inputs on every run and every worker
+
+*/
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FuzzSettings {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub runs: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_test_rejects: Option<u32>,
+}
+
+// The `[invariant]` settings of `foundry.toml` a question sets
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct InvariantSettings {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub runs: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub depth: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fail_on_revert: Option<bool>,
+}
+
+impl FuzzSettings {
+    pub fn seed(&self) -> &str {
+        self.seed.as_deref().unwrap_or(DEFAULT_FUZZ_SEED)
+    }
+
+    fn check(&self) -> Result<(), String> {
+        let seed = self.seed();
+        let valid = match seed.strip_prefix("0x") {
+            Some(hex) => {
+                (1..=64).contains(&hex.len()) && hex.chars().all(|c| c.is_ascii_hexdigit())
+            }
+            // Same length decimals compare as strings, longer ones are out of range anyway
+            None => {
+                (1..=78).contains(&seed.len())
+                    && seed.chars().all(|c| c.is_ascii_digit())
+                    && (seed.len() < 78 || seed <= UINT256_MAX)
+            }
+        };
+        if !valid {
+            return Err(format!("fuzz: seed {} is not a uint256", seed));
+        }
+        Ok(())
+    }
+}
+
+/* fuzz_env
+
+   @dev The environment passing the fuzz and invariant settings of a question to forge,
+        it wins over `foundry.toml`. Without a question only the default seed is set
+
+*/
+pub fn fuzz_env(question: Option<&Question>) -> Vec<(&'static str, String)> {
+    let Some(question) = question else {
+        return vec![("FOUNDRY_FUZZ_SEED", DEFAULT_FUZZ_SEED.to_string())];
+    };
+    let (fuzz, invariant) = (&question.meta.fuzz, &question.meta.invariant);
+    let mut env = vec![("FOUNDRY_FUZZ_SEED", fuzz.seed().to_string())];
+    let settings = [
+        ("FOUNDRY_FUZZ_RUNS", fuzz.runs.map(|v| v.to_string())),
+        (
+            "FOUNDRY_FUZZ_MAX_TEST_REJECTS",
+            fuzz.max_test_rejects.map(|v| v.to_string()),
+        ),
+        (
+            "FOUNDRY_INVARIANT_RUNS",
+            invariant.runs.map(|v| v.to_string()),
+        ),
+        (
+            "FOUNDRY_INVARIANT_DEPTH",
+            invariant.depth.map(|v| v.to_string()),
+        ),
+        (
+            "FOUNDRY_INVARIANT_FAIL_ON_REVERT",
+            invariant.fail_on_revert.map(|v| v.to_string()),
+        ),
+    ];
+    for (name, value) in settings {
+        if let Some(value) = value {
+            env.push((name, value));
+        }
+    }
+    env
+}
+
+impl QuestionMeta {
+    pub fn forbidden(&self) -> Vec<String> {
+        match &self.forbidden {
+            Some(forbidden) => forbidden.clone(),
+            None => DEFAULT_FORBIDDEN
+                .iter()
+                .map(|rule| rule.to_string())
+                .collect(),
+        }
+    }
+}
+
+/* Question
+
+   @dev A question of the bank, `{bank}/{questionNo}/` holds the official `test/`, the
+        `fixtures/` the tests rely on, the reference `solution/` and `question.json`
+
+*/
+#[derive(Debug, Clone)]
+pub struct Question {
+    pub question_no: String,
+    pub dir: PathBuf,
+    pub meta: QuestionMeta,
+}
+
+/* QuestionBank
+
+   @dev The official questions on the server. Students only submit `contracts/`, the
+        tests and their scores always come from here
+
+*/
+#[derive(Debug, Clone)]
+pub struct QuestionBank {
+    root: PathBuf,
+}
+
+fn read_meta(dir: &Path, question_no: &str) -> Result<QuestionMeta, ErrorCode> {
+    match fs::read_to_string(dir.join(META_FILE)) {
+        Ok(raw) => serde_json::from_str(&raw)
+            .map_err(|err| ErrorCode::BankReadFailure(format!("{}: {}", question_no, err))),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(QuestionMeta {
+            version: default_version(),
+            ..QuestionMeta::default()
+        }),
+        Err(err) => Err(ErrorCode::BankReadFailure(err.to_string())),
+    }
+}
+
+fn write_meta(dir: &Path, meta: &QuestionMeta) -> Result<(), ErrorCode> {
+    let raw = serde_json::to_string_pretty(meta).unwrap();
+    fs::write(dir.join(META_FILE), raw).map_err(|err| ErrorCode::BankWriteFailure(err.to_string()))
+}
+
+impl QuestionBank {
+    pub fn new(root: &Path) -> Self {
+        QuestionBank {
+            root: root.to_path_buf(),
+        }
+    }
+
+    pub fn find(&self, question_no: &str) -> Result<Question, ErrorCode> {
+        let dir = self.root.join(question_no);
+        if question_no.starts_with('.') || !dir.is_dir() {
+            return Err(ErrorCode::UnknownQuestion(question_no.to_string()));
+        }
+        Ok(Question {
+            question_no: question_no.to_string(),
+            meta: read_meta(&dir, question_no)?,
+            dir,
+        })
+    }
+
+    pub fn list(&self) -> Result<Vec<Question>, ErrorCode> {
+        let entries = match fs::read_dir(&self.root) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => return Err(ErrorCode::BankReadFailure(err.to_string())),
+        };
+
+        let mut questions = vec![];
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !name.starts_with('.') && entry.path().is_dir() {
+                questions.push(self.find(&name)?);
+            }
+        }
+        questions.sort_by(|a, b| a.question_no.cmp(&b.question_no));
+        Ok(questions)
+    }
+
+    // The highest version the question ever had, archived ones included
+    fn latest_version(&self, question_no: &str) -> u32 {
+        let current = self.find(question_no).map_or(0, |q| q.meta.version);
+        let archived = fs::read_dir(self.root.join(ARCHIVE_DIR).join(question_no))
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter_map(|entry| entry.file_name().to_str()?.strip_prefix('v')?.parse().ok())
+            .max()
+            .unwrap_or(0);
+        current.max(archived)
+    }
+
+    fn archive(&self, question: &Question) -> Result<(), ErrorCode> {
+        let target = self
+            .root
+            .join(ARCHIVE_DIR)
+            .join(&question.question_no)
+            .join(format!("v{}", question.meta.version));
+        let _ = fs::remove_dir_all(&target);
+        fs::create_dir_all(target.parent().unwrap())
+            .and_then(|_| fs::rename(&question.dir, &target))
+            .map_err(|err| ErrorCode::BankWriteFailure(err.to_string()))
+    }
+
+    /* add
+
+       @dev Adds the question laid out in `src` like a submission: the reference solution
+            in `contracts/`, the official tests in `test/` and optionally `fixtures/`. A
+            question already in the bank is archived and replaced by the next version
+
+    */
+    pub fn add(
+        &self,
+        question_no: &str,
+        src: &Path,
+        mut meta: QuestionMeta,
+    ) -> Result<Question, ErrorCode> {
+        let valid_name = !question_no.is_empty()
+            && !question_no.starts_with('.')
+            && !question_no.contains(['/', '\\']);
+        if !valid_name {
+            return Err(ErrorCode::InvalidQuestion(format!(
+                "{}: not a question number",
+                question_no
+            )));
+        }
+        for dir in ["contracts", "test"] {
+            if !src.join(dir).is_dir() {
+                return Err(ErrorCode::InvalidQuestion(format!(
+                    "{} has no {}/",
+                    src.display(),
+                    dir
+                )));
+            }
+        }
+        if !meta.solc.is_empty() {
+            VersionReq::parse(&meta.solc).map_err(|err| {
+                ErrorCode::InvalidQuestion(format!("solc {}: {}", meta.solc, err))
+            })?;
+        }
+        // Annotation mistakes belong to the author, not to every student judged against them
+        solidity::collect_tests(src)
+            .map_err(|err| ErrorCode::InvalidQuestion(err.get_err_msg()))?;
+        for budget in &meta.gas {
+            budget.check().map_err(ErrorCode::InvalidQuestion)?;
+        }
+        if let Some(coverage) = &meta.coverage {
+            coverage.check().map_err(ErrorCode::InvalidQuestion)?;
+        }
+        meta.fuzz.check().map_err(ErrorCode::InvalidQuestion)?;
+        if let Some(mutation) = &meta.mutation {
+            mutation.check().map_err(ErrorCode::InvalidQuestion)?;
+            if mutation::generate(src, mutation.max).is_empty() {
+                return Err(ErrorCode::InvalidQuestion(String::from(
+                    "mutation: the contracts have nothing to mutate",
+                )));
+            }
+        }
+        if let Some(forbidden) = &meta.forbidden {
+            scan::check_rules(forbidden)
+                .map_err(|err| ErrorCode::InvalidQuestion(err.get_err_msg()))?;
+        }
+
+        meta.version = self.latest_version(question_no) + 1;
+        if let Ok(current) = self.find(question_no) {
+            self.archive(&current)?;
+        }
+
+        let dir = self.root.join(question_no);
+        let copy = |from: &Path, to: &Path| {
+            copy_dir(from, to).map_err(|err| ErrorCode::BankWriteFailure(err.to_string()))
+        };
+        copy(
+            &src.join("contracts"),
+            &dir.join(SOLUTION_DIR).join("contracts"),
+        )?;
+        // Students write the tests of such questions, the official ones are the reference
+        if meta.coverage.is_some() || meta.mutation.is_some() {
+            copy(&src.join("test"), &dir.join(SOLUTION_DIR).join("test"))?;
+        }
+        for overlay_dir in OVERLAY_DIRS {
+            if src.join(overlay_dir).is_dir() {
+                copy(&src.join(overlay_dir), &dir.join(overlay_dir))?;
+            }
+        }
+        write_meta(&dir, &meta)?;
+        let question = self.find(question_no)?;
+        // Generated once here rather than by the first job
+        question.mutants();
+        Ok(question)
+    }
+
+    // Removes the question, its earlier versions stay in the archive
+    pub fn remove(&self, question_no: &str) -> Result<(), ErrorCode> {
+        let question = self.find(question_no)?;
+        self.archive(&question)
+    }
+}
+
+fn copy_dir(src: &Path, dst: &Path) -> io::Result<()> {
+    fs::create_dir_all(dst)?;
+    for entry in fs::read_dir(src)? {
+        let entry = entry?;
+        let target = dst.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+fn list_files(dir: &Path, base: &Path, files: &mut Vec<String>) {
+    for entry in fs::read_dir(dir).into_iter().flatten().flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            list_files(&path, base, files);
+        } else if let Ok(relative) = path.strip_prefix(base) {
+            files.push(relative.to_string_lossy().into_owned());
+        }
+    }
+}
+
+impl Question {
+    // The reference solution, a directory that can be judged like a submission
+    pub fn solution_dir(&self) -> PathBuf {
+        self.dir.join(SOLUTION_DIR)
+    }
+
+    // Every file of the question, relative to its directory
+    pub fn files(&self) -> Vec<String> {
+        let mut files = vec![];
+        list_files(&self.dir, &self.dir, &mut files);
+        files.sort();
+        files
+    }
+
+    // Students submit tests, judged against the reference solution
+    pub fn grades_tests(&self) -> bool {
+        self.meta.coverage.is_some() || self.meta.mutation.is_some()
+    }
+
+    /* mutants
+
+       @dev The mutants of the reference solution, cached in `mutants.json` of the
+            question. Every version of a question has its own directory, so the cache
+            never outlives the solution it was generated from. None without `mutation`
+
+    */
+    pub fn mutants(&self) -> Vec<Mutant> {
+        let Some(settings) = &self.meta.mutation else {
+            return vec![];
+        };
+        let cache = self.dir.join(MUTANTS_FILE);
+        let cached = fs::read_to_string(&cache)
+            .ok()
+            .and_then(|raw| serde_json::from_str(&raw).ok());
+        if let Some(mutants) = cached {
+            return mutants;
+        }
+        let mutants = mutation::generate(&self.solution_dir(), settings.max);
+        let _ = fs::write(&cache, serde_json::to_string(&mutants).unwrap());
+        mutants
+    }
+
+    /* check_submission
+
+       @dev Refuses a job the question does not allow: a solc version outside `solc` or
+            an import outside `libraries`. Relative imports stay within the submission
+
+    */
+    pub fn check_submission<'a>(
+        &self,
+        solc_version: &str,
+        sources: impl Iterator<Item = &'a str>,
+    ) -> Result<(), ErrorCode> {
+        if !self.meta.solc.is_empty() {
+            let allowed = VersionReq::parse(&self.meta.solc)
+                .map_err(|err| ErrorCode::InvalidQuestion(err.to_string()))?;
+            let matches = Version::parse(solc_version).is_ok_and(|v| allowed.matches(&v));
+            if !matches {
+                return Err(ErrorCode::SolcNotAllowed(format!(
+                    "{} does not match {}",
+                    solc_version, self.meta.solc
+                )));
+            }
+        }
+
+        let Some(libraries) = &self.meta.libraries else {
+            return Ok(());
+        };
+        let import_re = Regex::new(r#"import\s+(?:[^"';]*\s+from\s+)?["']([^"']+)["']"#).unwrap();
+        for source in sources {
+            for (_, [import]) in import_re.captures_iter(source).map(|c| c.extract()) {
+                if import.starts_with("./") || import.starts_with("../") {
+                    continue;
+                }
+                let root = import.split('/').next().unwrap_or(import);
+                if !libraries.iter().any(|library| library == root) {
+                    return Err(ErrorCode::LibraryNotAllowed(format!(
+                        "{}, allowed are {}",
+                        import,
+                        libraries.join(", ")
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /* overlay
+
+       @dev Replaces `test/` and `fixtures/` of the workspace with the ones of the
+            question, whatever the submission or an earlier job left there. Questions
+            grading tests keep the student's `test/` and replace `contracts/` with the
+            reference solution instead
+
+    */
+    pub fn overlay(&self, workspace: &Path) -> Result<(), ErrorCode> {
+        let replace = |source: &Path, target: &Path| {
+            let _ = fs::remove_dir_all(target);
+            if source.is_dir() {
+                copy_dir(source, target)
+                    .map_err(|err| ErrorCode::OverlayFailure(err.to_string()))?;
+            }
+            Ok(())
+        };
+        for dir in OVERLAY_DIRS {
+            if !(self.grades_tests() && dir == "test") {
+                replace(&self.dir.join(dir), &workspace.join(dir))?;
+            }
+        }
+        if self.grades_tests() {
+            replace(
+                &self.solution_dir().join("contracts"),
+                &workspace.join("contracts"),
+            )?;
+        }
+        Ok(())
+    }
+
+    // Applies `scores` to the questions collected from the tests into `output`
+    pub fn apply_scores(&self, output: &mut serde_json::Value) {
+        output["questionVersion"] = json!(self.meta.version);
+        let Some(questions) = output
+            .get_mut("questions")
+            .and_then(serde_json::Value::as_array_mut)
+        else {
+            return;
+        };
+        for q in questions {
+            let score = q["Func"]
+                .as_str()
+                .and_then(|func| self.meta.scores.get(func));
+            if let Some(score) = score {
+                q["Score"] = json!(score.to_string());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seeded(seed: &str) -> FuzzSettings {
+        FuzzSettings {
+            seed: Some(seed.to_string()),
+            ..FuzzSettings::default()
+        }
+    }
+
+    fn question(meta: QuestionMeta) -> Question {
+        Question {
+            question_no: String::from("q1"),
+            dir: PathBuf::from("q1"),
+            meta,
+        }
+    }
+
+    #[test]
+    fn fuzz_seeds_have_to_be_uint256() {
+        assert!(FuzzSettings::default().check().is_ok());
+        for seed in [
+            "0",
+            "12345",
+            "0xdeadBEEF",
+            &format!("0x{}", "f".repeat(64)),
+            UINT256_MAX,
+        ] {
+            assert!(seeded(seed).check().is_ok(), "{}", seed);
+        }
+        let too_large = format!("{}6", &UINT256_MAX[..77]);
+        let too_long = format!("0x{}", "f".repeat(65));
+        for seed in ["", "0x", "0xzz", "-1", "1.5", "seed", &too_large, &too_long] {
+            assert!(seeded(seed).check().is_err(), "{}", seed);
+        }
+    }
+
+    #[test]
+    fn fuzz_env_sets_the_default_seed() {
+        let env = vec![("FOUNDRY_FUZZ_SEED", String::from("0x2a"))];
+        assert_eq!(fuzz_env(None), env);
+        assert_eq!(fuzz_env(Some(&question(QuestionMeta::default()))), env);
+    }
+
+    #[test]
+    fn fuzz_env_passes_the_settings_of_the_question() {
+        let meta = QuestionMeta {
+            fuzz: FuzzSettings {
+                runs: Some(512),
+                seed: Some(String::from("7")),
+                max_test_rejects: Some(1000),
+            },
+            invariant: InvariantSettings {
+                runs: Some(64),
+                depth: None,
+                fail_on_revert: Some(true),
+            },
+            ..QuestionMeta::default()
+        };
+        let env = fuzz_env(Some(&question(meta)));
+        assert_eq!(
+            env,
+            vec![
+                ("FOUNDRY_FUZZ_SEED", String::from("7")),
+                ("FOUNDRY_FUZZ_RUNS", String::from("512")),
+                ("FOUNDRY_FUZZ_MAX_TEST_REJECTS", String::from("1000")),
+                ("FOUNDRY_INVARIANT_RUNS", String::from("64")),
+                ("FOUNDRY_INVARIANT_FAIL_ON_REVERT", String::from("true")),
+            ]
+        );
+    }
+}
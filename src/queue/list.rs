@@ -0,0 +1,199 @@
+use redis::{Commands, Connection, Direction, Value};
+
+use super::{
+    connect, dead_letter_key, reconnect_loop, requeue_due_retries, retry_key, Delivery, ErrorCode,
+    JobQueue,
+};
+use crate::protocol::requests_key;
+
+pub fn workers_key(redis_prefix: &str) -> String {
+    format!("{}:workers", redis_prefix)
+}
+
+pub fn processing_list_key(redis_prefix: &str, worker_id: &str) -> String {
+    format!("{}:processing:{}", redis_prefix, worker_id)
+}
+
+pub fn heartbeat_key(redis_prefix: &str, server_id: &str) -> String {
+    format!("{}:heartbeat:{}", redis_prefix, server_id)
+}
+
+/* RedisListQueue
+
+   @dev Workers move jobs atomically from the request list `{prefix}:{list_name}` into
+        their own processing list `{prefix}:processing:{worker_id}` and remove them from
+        there on ack. Jobs are pushed on the left and taken from the right, first come
+        first served, while retries, replays and orphans go back on the right to be
+        taken next
+
+*/
+pub struct RedisListQueue {
+    client: redis::Client,
+    conn: Connection,
+    redis_prefix: String,
+    requests_key: String,
+    processing_key: String,
+}
+
+impl RedisListQueue {
+    pub fn new(
+        redis_host: &str,
+        redis_prefix: &str,
+        list_name: &str,
+        worker_id: &str,
+    ) -> Result<Self, ErrorCode> {
+        let client = redis::Client::open(redis_host)?;
+        let conn = connect(&client)?;
+
+        Ok(RedisListQueue {
+            client,
+            conn,
+            redis_prefix: redis_prefix.to_string(),
+            requests_key: requests_key(redis_prefix, list_name),
+            processing_key: processing_list_key(redis_prefix, worker_id),
+        })
+    }
+}
+
+impl JobQueue for RedisListQueue {
+    fn submit(&mut self, raw: &str) -> Result<(), ErrorCode> {
+        self.conn
+            .lpush::<&str, &str, i32>(&self.requests_key, raw)?;
+        Ok(())
+    }
+
+    fn fetch(&mut self) -> Result<Option<Delivery>, ErrorCode> {
+        let x: Value = self.conn.blmove(
+            &self.requests_key,
+            &self.processing_key,
+            Direction::Right,
+            Direction::Left,
+            1.0,
+        )?;
+        match x {
+            Value::Data(bytes) => {
+                let raw = String::from_utf8_lossy(&bytes).into_owned();
+                Ok(Some(Delivery {
+                    id: raw.clone(),
+                    raw,
+                }))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn ack(&mut self, delivery: &Delivery) -> Result<(), ErrorCode> {
+        self.conn
+            .lrem::<&str, &str, i32>(&self.processing_key, 1, &delivery.id)?;
+        Ok(())
+    }
+
+    fn retry_at(&mut self, delivery: &Delivery, raw: &str, due: i64) -> Result<(), ErrorCode> {
+        redis::pipe()
+            .atomic()
+            .lrem(&self.processing_key, 1, &delivery.id)
+            .zadd(retry_key(&self.redis_prefix), raw, due)
+            .query::<()>(&mut self.conn)?;
+        Ok(())
+    }
+
+    fn requeue_due(&mut self, now: i64) -> Result<usize, ErrorCode> {
+        requeue_due_retries(
+            &mut self.conn,
+            &self.redis_prefix,
+            &self.requests_key,
+            false,
+            now,
+        )
+    }
+
+    fn dead_letter(&mut self, delivery: &Delivery, entry: &str) -> Result<(), ErrorCode> {
+        redis::pipe()
+            .atomic()
+            .lrem(&self.processing_key, 1, &delivery.id)
+            .lpush(dead_letter_key(&self.redis_prefix), entry)
+            .query::<()>(&mut self.conn)?;
+        Ok(())
+    }
+
+    fn dead_letters(&mut self) -> Result<Vec<String>, ErrorCode> {
+        Ok(self
+            .conn
+            .lrange(dead_letter_key(&self.redis_prefix), 0, -1)?)
+    }
+
+    fn replay(&mut self, entry: &str, raw: &str) -> Result<(), ErrorCode> {
+        redis::pipe()
+            .atomic()
+            .lrem(dead_letter_key(&self.redis_prefix), 1, entry)
+            .rpush(&self.requests_key, raw)
+            .query::<()>(&mut self.conn)?;
+        Ok(())
+    }
+
+    fn held(&mut self) -> Result<Vec<Delivery>, ErrorCode> {
+        let held: Vec<String> = self.conn.lrange(&self.processing_key, 0, -1)?;
+        Ok(held
+            .into_iter()
+            .map(|raw| Delivery {
+                id: raw.clone(),
+                raw,
+            })
+            .collect())
+    }
+
+    fn reconnect(&mut self) {
+        self.conn = reconnect_loop(&self.client);
+    }
+}
+
+// Records the worker so its processing list is swept once its server stops beating
+pub fn register_worker(
+    conn: &mut Connection,
+    redis_prefix: &str,
+    worker_id: &str,
+) -> Result<(), ErrorCode> {
+    conn.sadd::<String, &str, i32>(workers_key(redis_prefix), worker_id)?;
+    Ok(())
+}
+
+/* requeue_orphaned_jobs
+
+   @dev Moves the in-flight jobs of dead servers back onto the request list. This
+        server's own processing lists are always swept, whatever is left in them was
+        abandoned by a previous run
+
+*/
+pub fn requeue_orphaned_jobs(
+    conn: &mut Connection,
+    redis_prefix: &str,
+    requests_key: &str,
+    server_id: &str,
+) -> Result<usize, ErrorCode> {
+    let workers: Vec<String> = conn.smembers(workers_key(redis_prefix))?;
+    let mut requeued = 0;
+
+    for worker in workers {
+        let owner = worker.rsplit_once(':').map_or(worker.as_str(), |(s, _)| s);
+        if owner != server_id && conn.exists(heartbeat_key(redis_prefix, owner))? {
+            continue;
+        }
+
+        // The oldest job of the worker ends up at the right, the next to be taken
+        let processing_key = processing_list_key(redis_prefix, &worker);
+        while conn
+            .lmove::<&str, &str, Option<String>>(
+                &processing_key,
+                requests_key,
+                Direction::Left,
+                Direction::Right,
+            )?
+            .is_some()
+        {
+            requeued += 1;
+        }
+        conn.srem::<String, &str, i32>(workers_key(redis_prefix), &worker)?;
+    }
+
+    Ok(requeued)
+}
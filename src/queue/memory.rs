@@ -0,0 +1,361 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::Duration;
+
+use super::{Delivery, ErrorCode, JobQueue, ResultSink};
+
+#[derive(Default)]
+struct MemoryState {
+    requests: VecDeque<String>,
+    held: HashMap<String, Vec<String>>,
+    retries: Vec<(i64, String)>,
+    dead: Vec<String>,
+    expected: HashSet<String>,
+    responses: HashMap<String, String>,
+    records: HashMap<String, String>,
+    cancelled: HashSet<String>,
+}
+
+#[derive(Default)]
+struct Shared {
+    state: Mutex<MemoryState>,
+    submitted: Condvar,
+}
+
+fn lock(shared: &Shared) -> MutexGuard<'_, MemoryState> {
+    // A worker panicking mid-job must not take the whole queue down with it
+    shared.state.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/* MemoryQueue
+
+   @dev Process-local queue for tests and single-process use. Clones share the same
+        queue, each clone given its own worker id holds its own deliveries. Jobs are
+        handed out first come first served, retries and replays go to the front, in the
+        order of the Redis list backend
+
+*/
+#[derive(Clone, Default)]
+pub struct MemoryQueue {
+    shared: Arc<Shared>,
+    worker_id: String,
+}
+
+impl MemoryQueue {
+    pub fn new() -> Self {
+        MemoryQueue::default()
+    }
+
+    pub fn for_worker(&self, worker_id: &str) -> Self {
+        MemoryQueue {
+            shared: self.shared.clone(),
+            worker_id: worker_id.to_string(),
+        }
+    }
+
+    pub fn result_sink(&self) -> MemoryResultSink {
+        MemoryResultSink {
+            shared: self.shared.clone(),
+        }
+    }
+
+    fn release(&self, state: &mut MemoryState, delivery: &Delivery) {
+        if let Some(held) = state.held.get_mut(&self.worker_id) {
+            if let Some(index) = held.iter().position(|raw| raw == &delivery.id) {
+                held.remove(index);
+            }
+        }
+    }
+}
+
+impl JobQueue for MemoryQueue {
+    fn submit(&mut self, raw: &str) -> Result<(), ErrorCode> {
+        lock(&self.shared).requests.push_back(raw.to_string());
+        self.shared.submitted.notify_one();
+        Ok(())
+    }
+
+    fn fetch(&mut self) -> Result<Option<Delivery>, ErrorCode> {
+        let state = lock(&self.shared);
+        let (mut state, _) = self
+            .shared
+            .submitted
+            .wait_timeout_while(state, Duration::from_secs(1), |state| {
+                state.requests.is_empty()
+            })
+            .unwrap_or_else(|err| err.into_inner());
+
+        Ok(state.requests.pop_front().map(|raw| {
+            state
+                .held
+                .entry(self.worker_id.clone())
+                .or_default()
+                .push(raw.clone());
+            Delivery {
+                id: raw.clone(),
+                raw,
+            }
+        }))
+    }
+
+    fn ack(&mut self, delivery: &Delivery) -> Result<(), ErrorCode> {
+        let mut state = lock(&self.shared);
+        self.release(&mut state, delivery);
+        Ok(())
+    }
+
+    fn retry_at(&mut self, delivery: &Delivery, raw: &str, due: i64) -> Result<(), ErrorCode> {
+        let mut state = lock(&self.shared);
+        self.release(&mut state, delivery);
+        state.retries.push((due, raw.to_string()));
+        Ok(())
+    }
+
+    fn requeue_due(&mut self, now: i64) -> Result<usize, ErrorCode> {
+        let mut state = lock(&self.shared);
+        let (mut due, waiting): (Vec<_>, Vec<_>) =
+            state.retries.drain(..).partition(|(due, _)| *due <= now);
+        state.retries = waiting;
+        let requeued = due.len();
+        due.sort_by_key(|(due, _)| *due);
+        for (_, raw) in due {
+            state.requests.push_front(raw);
+        }
+        if requeued > 0 {
+            self.shared.submitted.notify_all();
+        }
+        Ok(requeued)
+    }
+
+    fn dead_letter(&mut self, delivery: &Delivery, entry: &str) -> Result<(), ErrorCode> {
+        let mut state = lock(&self.shared);
+        self.release(&mut state, delivery);
+        state.dead.insert(0, entry.to_string());
+        Ok(())
+    }
+
+    fn dead_letters(&mut self) -> Result<Vec<String>, ErrorCode> {
+        Ok(lock(&self.shared).dead.clone())
+    }
+
+    fn replay(&mut self, entry: &str, raw: &str) -> Result<(), ErrorCode> {
+        let mut state = lock(&self.shared);
+        if let Some(index) = state.dead.iter().position(|dead| dead == entry) {
+            state.dead.remove(index);
+        }
+        state.requests.push_front(raw.to_string());
+        self.shared.submitted.notify_one();
+        Ok(())
+    }
+
+    fn held(&mut self) -> Result<Vec<Delivery>, ErrorCode> {
+        let state = lock(&self.shared);
+        Ok(state
+            .held
+            .get(&self.worker_id)
+            .map(|held| {
+                held.iter()
+                    .map(|raw| Delivery {
+                        id: raw.clone(),
+                        raw: raw.clone(),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+}
+
+#[derive(Clone)]
+pub struct MemoryResultSink {
+    shared: Arc<Shared>,
+}
+
+impl ResultSink for MemoryResultSink {
+    fn expect(&mut self, request_key: &str, _ttl_secs: usize) -> Result<(), ErrorCode> {
+        lock(&self.shared).expected.insert(request_key.to_string());
+        Ok(())
+    }
+
+    fn is_expected(&mut self, request_key: &str) -> Result<bool, ErrorCode> {
+        Ok(lock(&self.shared).expected.contains(request_key))
+    }
+
+    fn publish(
+        &mut self,
+        request_key: &str,
+        response_key: &str,
+        response: &str,
+        _ttl_secs: usize,
+    ) -> Result<(), ErrorCode> {
+        let mut state = lock(&self.shared);
+        state.expected.remove(request_key);
+        state
+            .responses
+            .insert(response_key.to_string(), response.to_string());
+        Ok(())
+    }
+
+    fn take(&mut self, request_key: &str, response_key: &str) -> Result<Option<String>, ErrorCode> {
+        let mut state = lock(&self.shared);
+        let response = state.responses.remove(response_key);
+        if response.is_some() {
+            state.expected.remove(request_key);
+        }
+        Ok(response)
+    }
+
+    fn abandon(&mut self, request_key: &str) -> Result<(), ErrorCode> {
+        lock(&self.shared).expected.remove(request_key);
+        Ok(())
+    }
+
+    // Records live as long as the queue, the ttl only matters across processes
+    fn record(
+        &mut self,
+        record_key: &str,
+        record: &str,
+        _ttl_secs: usize,
+    ) -> Result<(), ErrorCode> {
+        lock(&self.shared)
+            .records
+            .insert(record_key.to_string(), record.to_string());
+        Ok(())
+    }
+
+    fn record_new(
+        &mut self,
+        record_key: &str,
+        record: &str,
+        _ttl_secs: usize,
+    ) -> Result<bool, ErrorCode> {
+        let mut shared = lock(&self.shared);
+        if shared.records.contains_key(record_key) {
+            return Ok(false);
+        }
+        shared
+            .records
+            .insert(record_key.to_string(), record.to_string());
+        Ok(true)
+    }
+
+    fn lookup(&mut self, record_key: &str) -> Result<Option<String>, ErrorCode> {
+        Ok(lock(&self.shared).records.get(record_key).cloned())
+    }
+
+    fn cancel(&mut self, cancel_key: &str, _ttl_secs: usize) -> Result<(), ErrorCode> {
+        lock(&self.shared).cancelled.insert(cancel_key.to_string());
+        Ok(())
+    }
+
+    fn is_cancelled(&mut self, cancel_key: &str) -> Result<bool, ErrorCode> {
+        Ok(lock(&self.shared).cancelled.contains(cancel_key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jobs_are_fetched_in_order_and_held_until_acked() {
+        let queue = MemoryQueue::new();
+        let mut worker = queue.for_worker("server:00");
+        worker.submit("first").unwrap();
+        worker.submit("second").unwrap();
+
+        let first = worker.fetch().unwrap().unwrap();
+        assert_eq!(first.raw, "first");
+        let second = worker.fetch().unwrap().unwrap();
+        assert_eq!(second.raw, "second");
+        assert_eq!(worker.held().unwrap().len(), 2);
+
+        worker.ack(&first).unwrap();
+        let held: Vec<String> = worker.held().unwrap().into_iter().map(|d| d.raw).collect();
+        assert_eq!(held, vec!["second"]);
+    }
+
+    #[test]
+    fn workers_hold_their_own_deliveries() {
+        let queue = MemoryQueue::new();
+        let mut first = queue.for_worker("server:00");
+        let mut second = queue.for_worker("server:01");
+        first.submit("job").unwrap();
+
+        let delivery = second.fetch().unwrap().unwrap();
+        assert!(first.held().unwrap().is_empty());
+        assert_eq!(second.held().unwrap()[0].raw, delivery.raw);
+        assert!(first.fetch().unwrap().is_none());
+    }
+
+    #[test]
+    fn retries_wait_for_their_due_time() {
+        let queue = MemoryQueue::new();
+        let mut worker = queue.for_worker("server:00");
+        worker.submit("job").unwrap();
+        let delivery = worker.fetch().unwrap().unwrap();
+
+        worker.retry_at(&delivery, "job, attempt 2", 100).unwrap();
+        assert!(worker.held().unwrap().is_empty());
+        assert_eq!(worker.requeue_due(99).unwrap(), 0);
+        assert_eq!(worker.requeue_due(100).unwrap(), 1);
+        assert_eq!(worker.fetch().unwrap().unwrap().raw, "job, attempt 2");
+    }
+
+    #[test]
+    fn dead_letters_are_replayed_onto_the_queue() {
+        let queue = MemoryQueue::new();
+        let mut worker = queue.for_worker("server:00");
+        worker.submit("job").unwrap();
+        let delivery = worker.fetch().unwrap().unwrap();
+
+        worker.dead_letter(&delivery, "dead job").unwrap();
+        assert!(worker.held().unwrap().is_empty());
+        assert_eq!(worker.dead_letters().unwrap(), vec!["dead job"]);
+
+        worker.replay("dead job", "job").unwrap();
+        assert!(worker.dead_letters().unwrap().is_empty());
+        assert_eq!(worker.fetch().unwrap().unwrap().raw, "job");
+    }
+
+    #[test]
+    fn retries_and_replays_go_before_newer_jobs() {
+        let queue = MemoryQueue::new();
+        let mut worker = queue.for_worker("server:00");
+        for job in ["first", "second", "third", "fourth"] {
+            worker.submit(job).unwrap();
+        }
+        let first = worker.fetch().unwrap().unwrap();
+        let second = worker.fetch().unwrap().unwrap();
+
+        worker.retry_at(&first, "first, attempt 2", 100).unwrap();
+        worker.dead_letter(&second, "dead second").unwrap();
+        worker.requeue_due(100).unwrap();
+        worker.replay("dead second", "second, replayed").unwrap();
+
+        let order: Vec<String> = (0..4)
+            .map(|_| worker.fetch().unwrap().unwrap().raw)
+            .collect();
+        assert_eq!(
+            order,
+            vec!["second, replayed", "first, attempt 2", "third", "fourth"]
+        );
+    }
+
+    #[test]
+    fn responses_reach_the_submitter_once() {
+        let queue = MemoryQueue::new();
+        let mut submitter = queue.result_sink();
+        let mut worker = queue.result_sink();
+        submitter.expect("request", 60).unwrap();
+        assert!(worker.is_expected("request").unwrap());
+        assert_eq!(submitter.take("request", "response").unwrap(), None);
+
+        worker.publish("request", "response", "result", 60).unwrap();
+        assert!(!worker.is_expected("request").unwrap());
+        assert_eq!(
+            submitter.take("request", "response").unwrap().as_deref(),
+            Some("result")
+        );
+        assert_eq!(submitter.take("request", "response").unwrap(), None);
+    }
+}
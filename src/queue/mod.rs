@@ -0,0 +1,271 @@
+use std::{thread, time::Duration};
+
+use log::error;
+use redis::{Connection, RedisError, Script};
+
+use crate::protocol;
+
+pub mod list;
+pub mod memory;
+pub mod result;
+pub mod stream;
+
+#[derive(Debug)]
+pub enum ErrorCode {
+    ConnectionDropped(String),
+    QueueErr(String),
+}
+
+impl ErrorCode {
+    pub fn get_err_msg(&self) -> String {
+        match self {
+            ErrorCode::ConnectionDropped(err) => format!("Queue connection dropped: {}", err),
+            ErrorCode::QueueErr(err) => format!("Queue err: {}", err),
+        }
+    }
+
+    pub fn is_connection_dropped(&self) -> bool {
+        matches!(self, ErrorCode::ConnectionDropped(_))
+    }
+}
+
+impl From<RedisError> for ErrorCode {
+    fn from(err: RedisError) -> Self {
+        if err.is_connection_dropped() || err.is_io_error() || err.is_connection_refusal() {
+            ErrorCode::ConnectionDropped(err.to_string())
+        } else {
+            ErrorCode::QueueErr(err.to_string())
+        }
+    }
+}
+
+/* Delivery
+
+   @dev A job handed to a worker, `id` is what acknowledges it: the raw job itself for
+        the list backends, the entry id for the stream backend
+
+*/
+#[derive(Debug, Clone)]
+pub struct Delivery {
+    pub id: String,
+    pub raw: String,
+}
+
+/* JobQueue
+
+   @dev Where jobs come from. A fetched job is held by the worker until it is
+        acknowledged, so a crash in the middle of a forge run never loses it
+
+*/
+pub trait JobQueue: Send {
+    // Appends a new job to the queue
+    fn submit(&mut self, raw: &str) -> Result<(), ErrorCode>;
+
+    // Waits up to a second for the next job
+    fn fetch(&mut self) -> Result<Option<Delivery>, ErrorCode>;
+
+    fn ack(&mut self, delivery: &Delivery) -> Result<(), ErrorCode>;
+
+    // Acknowledges the delivery and parks `raw` until the unix timestamp `due`
+    fn retry_at(&mut self, delivery: &Delivery, raw: &str, due: i64) -> Result<(), ErrorCode>;
+
+    // Submits the parked jobs whose due time has passed
+    fn requeue_due(&mut self, now: i64) -> Result<usize, ErrorCode>;
+
+    // Acknowledges the delivery and records `entry` in the dead letters
+    fn dead_letter(&mut self, delivery: &Delivery, entry: &str) -> Result<(), ErrorCode>;
+
+    fn dead_letters(&mut self) -> Result<Vec<String>, ErrorCode>;
+
+    // Removes `entry` from the dead letters and submits `raw` in one step
+    fn replay(&mut self, entry: &str, raw: &str) -> Result<(), ErrorCode>;
+
+    // Jobs fetched by this worker which are not acknowledged yet
+    fn held(&mut self) -> Result<Vec<Delivery>, ErrorCode>;
+
+    // Tells the other workers the delivery is still being judged
+    fn renew(&mut self, _delivery: &Delivery) -> Result<(), ErrorCode> {
+        Ok(())
+    }
+
+    // Blocks until the transport is usable again
+    fn reconnect(&mut self) {}
+}
+
+/* ResultSink
+
+   @dev Where results go. Every job has its own request marker and response key, the
+        submitter marks the request as awaited, the worker clears the marker and stores
+        the response, which the submitter then takes. Both expire after `ttl_secs` so
+        abandoned requests do not pile up
+
+*/
+pub trait ResultSink: Send {
+    fn expect(&mut self, request_key: &str, ttl_secs: usize) -> Result<(), ErrorCode>;
+
+    fn is_expected(&mut self, request_key: &str) -> Result<bool, ErrorCode>;
+
+    fn publish(
+        &mut self,
+        request_key: &str,
+        response_key: &str,
+        response: &str,
+        ttl_secs: usize,
+    ) -> Result<(), ErrorCode>;
+
+    // Returns the response once it arrived and clears the request
+    fn take(&mut self, request_key: &str, response_key: &str) -> Result<Option<String>, ErrorCode>;
+
+    fn abandon(&mut self, request_key: &str) -> Result<(), ErrorCode>;
+
+    // Stores the status record of a job, it expires after `ttl_secs`
+    fn record(&mut self, record_key: &str, record: &str, ttl_secs: usize) -> Result<(), ErrorCode>;
+
+    // Stores the first status record of a job, false when the job already has one
+    fn record_new(
+        &mut self,
+        record_key: &str,
+        record: &str,
+        ttl_secs: usize,
+    ) -> Result<bool, ErrorCode>;
+
+    fn lookup(&mut self, record_key: &str) -> Result<Option<String>, ErrorCode>;
+
+    // Flags the job as cancelled, a queued job may wait for hours so the flag has a ttl
+    fn cancel(&mut self, cancel_key: &str, ttl_secs: usize) -> Result<(), ErrorCode>;
+
+    fn is_cancelled(&mut self, cancel_key: &str) -> Result<bool, ErrorCode>;
+
+    // Publishes a progress event, nobody may be listening
+    fn notify(&mut self, _channel: &str, _event: &str) -> Result<(), ErrorCode> {
+        Ok(())
+    }
+
+    fn reconnect(&mut self) {}
+}
+
+/* QueueBackend
+
+   @dev How the workers receive jobs: `List` moves them from `{prefix}:{list_name}` into
+        a per-worker processing list, `Stream` reads `{prefix}:stream` through a consumer
+        group so pending entries can be inspected and reclaimed
+
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueueBackend {
+    List { list_name: String },
+    Stream { group: String, claim_idle_ms: usize },
+}
+
+impl QueueBackend {
+    pub fn parse(
+        name: &str,
+        list_name: &str,
+        group: &str,
+        claim_idle_secs: usize,
+    ) -> Option<QueueBackend> {
+        match name {
+            "list" => Some(QueueBackend::List {
+                list_name: list_name.to_string(),
+            }),
+            "stream" => Some(QueueBackend::Stream {
+                group: group.to_string(),
+                claim_idle_ms: claim_idle_secs * 1000,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn open(
+        &self,
+        redis_host: &str,
+        redis_prefix: &str,
+        worker_id: &str,
+    ) -> Result<Box<dyn JobQueue>, ErrorCode> {
+        match self {
+            QueueBackend::List { list_name } => Ok(Box::new(list::RedisListQueue::new(
+                redis_host,
+                redis_prefix,
+                list_name,
+                worker_id,
+            )?)),
+            QueueBackend::Stream {
+                group,
+                claim_idle_ms,
+            } => Ok(Box::new(stream::RedisStreamQueue::new(
+                redis_host,
+                redis_prefix,
+                worker_id,
+                group,
+                *claim_idle_ms,
+            )?)),
+        }
+    }
+}
+
+impl QueueBackend {
+    // The key jobs are submitted to, a list or a stream depending on the backend
+    pub fn requests_key(&self, redis_prefix: &str) -> String {
+        match self {
+            QueueBackend::List { list_name } => protocol::requests_key(redis_prefix, list_name),
+            QueueBackend::Stream { .. } => stream::stream_key(redis_prefix),
+        }
+    }
+}
+
+pub(crate) fn retry_key(redis_prefix: &str) -> String {
+    format!("{}:retry", redis_prefix)
+}
+
+pub(crate) fn dead_letter_key(redis_prefix: &str) -> String {
+    format!("{}:dead", redis_prefix)
+}
+
+fn connect(client: &redis::Client) -> Result<Connection, ErrorCode> {
+    client.get_connection().map_err(ErrorCode::from)
+}
+
+fn reconnect_loop(client: &redis::Client) -> Connection {
+    let mut delay = 1;
+    loop {
+        match connect(client) {
+            Ok(conn) => return conn,
+            Err(err) => error!("{}, reconnecting in {}s", err.get_err_msg(), delay),
+        }
+        thread::sleep(Duration::from_secs(delay));
+        delay = (delay * 2).min(30);
+    }
+}
+
+// Moves the retries whose backoff has elapsed back onto the request list or stream
+fn requeue_due_retries(
+    conn: &mut Connection,
+    redis_prefix: &str,
+    requests_key: &str,
+    is_stream: bool,
+    now: i64,
+) -> Result<usize, ErrorCode> {
+    let script = Script::new(
+        "
+    local due = redis.call('zrangebyscore', KEYS[1], '-inf', ARGV[1], 'LIMIT', 0, 10)
+    for _, job in ipairs(due) do
+        redis.call('zrem', KEYS[1], job)
+        if ARGV[2] == 'stream' then
+            redis.call('xadd', KEYS[2], '*', ARGV[3], job)
+        else
+            redis.call('rpush', KEYS[2], job)
+        end
+    end
+    return #due
+  ",
+    );
+
+    let requeued = script
+        .key(retry_key(redis_prefix))
+        .key(requests_key)
+        .arg(now)
+        .arg(if is_stream { "stream" } else { "list" })
+        .arg(stream::JOB_FIELD)
+        .invoke::<usize>(conn)?;
+    Ok(requeued)
+}
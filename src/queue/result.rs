@@ -0,0 +1,165 @@
+use std::time::Duration;
+
+use redis::{Commands, Connection, Script};
+
+use super::{connect, reconnect_loop, ErrorCode, ResultSink};
+
+// How often a quiet subscription asks whether it is still wanted
+const FOLLOW_POLL_SECS: u64 = 5;
+
+/* RedisResultSink
+
+   @dev The request key is a plain marker set by the submitter, the response is a plain
+        string key, both with an expiry
+
+*/
+pub struct RedisResultSink {
+    client: redis::Client,
+    conn: Connection,
+}
+
+impl RedisResultSink {
+    pub fn new(redis_host: &str) -> Result<Self, ErrorCode> {
+        let client = redis::Client::open(redis_host)?;
+        let conn = connect(&client)?;
+        Ok(RedisResultSink { client, conn })
+    }
+
+    /* follow
+
+       @dev Subscribes to `channel` on a connection of its own and calls `on_message` with
+            `None` once the subscription is active, then with every message published on
+            the channel, until it returns false. Whatever the caller looks up in the first
+            call can therefore not miss a message. While no message comes, `is_wanted` is
+            asked every few seconds and the subscription ends once it returns false
+
+    */
+    pub fn follow(
+        &mut self,
+        channel: &str,
+        mut on_message: impl FnMut(&mut Self, Option<&str>) -> bool,
+        mut is_wanted: impl FnMut() -> bool,
+    ) -> Result<(), ErrorCode> {
+        let mut conn = connect(&self.client)?;
+        let mut pubsub = conn.as_pubsub();
+        pubsub.set_read_timeout(Some(Duration::from_secs(FOLLOW_POLL_SECS)))?;
+        pubsub.subscribe(channel)?;
+        if !on_message(self, None) {
+            return Ok(());
+        }
+
+        loop {
+            let message = match pubsub.get_message() {
+                Ok(message) => message,
+                Err(err) if err.is_timeout() => {
+                    if !is_wanted() {
+                        return Ok(());
+                    }
+                    continue;
+                }
+                Err(err) => return Err(err.into()),
+            };
+            let payload: String = message.get_payload()?;
+            if !on_message(self, Some(&payload)) {
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl ResultSink for RedisResultSink {
+    fn expect(&mut self, request_key: &str, ttl_secs: usize) -> Result<(), ErrorCode> {
+        self.conn
+            .set_ex::<&str, &str, ()>(request_key, "", ttl_secs)?;
+        Ok(())
+    }
+
+    fn is_expected(&mut self, request_key: &str) -> Result<bool, ErrorCode> {
+        Ok(self.conn.exists(request_key)?)
+    }
+
+    fn publish(
+        &mut self,
+        request_key: &str,
+        response_key: &str,
+        response: &str,
+        ttl_secs: usize,
+    ) -> Result<(), ErrorCode> {
+        redis::pipe()
+            .atomic()
+            .del(request_key)
+            .set_ex(response_key, response, ttl_secs)
+            .query::<()>(&mut self.conn)?;
+        Ok(())
+    }
+
+    fn take(&mut self, request_key: &str, response_key: &str) -> Result<Option<String>, ErrorCode> {
+        let script = Script::new(
+            r#"
+    if redis.call("EXISTS", KEYS[2]) == 1 then
+        local value = redis.call("GET", KEYS[2])
+        redis.call("DEL", KEYS[1])
+        redis.call("DEL", KEYS[2])
+        return value
+    else
+        return nil
+    end
+  "#,
+        );
+
+        Ok(script
+            .key(request_key)
+            .key(response_key)
+            .invoke::<Option<String>>(&mut self.conn)?)
+    }
+
+    fn abandon(&mut self, request_key: &str) -> Result<(), ErrorCode> {
+        self.conn.del::<&str, i32>(request_key)?;
+        Ok(())
+    }
+
+    fn record(&mut self, record_key: &str, record: &str, ttl_secs: usize) -> Result<(), ErrorCode> {
+        self.conn
+            .set_ex::<&str, &str, ()>(record_key, record, ttl_secs)?;
+        Ok(())
+    }
+
+    fn record_new(
+        &mut self,
+        record_key: &str,
+        record: &str,
+        ttl_secs: usize,
+    ) -> Result<bool, ErrorCode> {
+        let stored: Option<String> = redis::cmd("SET")
+            .arg(record_key)
+            .arg(record)
+            .arg("NX")
+            .arg("EX")
+            .arg(ttl_secs)
+            .query(&mut self.conn)?;
+        Ok(stored.is_some())
+    }
+
+    fn lookup(&mut self, record_key: &str) -> Result<Option<String>, ErrorCode> {
+        Ok(self.conn.get(record_key)?)
+    }
+
+    fn cancel(&mut self, cancel_key: &str, ttl_secs: usize) -> Result<(), ErrorCode> {
+        self.conn
+            .set_ex::<&str, &str, ()>(cancel_key, "1", ttl_secs)?;
+        Ok(())
+    }
+
+    fn is_cancelled(&mut self, cancel_key: &str) -> Result<bool, ErrorCode> {
+        Ok(self.conn.exists(cancel_key)?)
+    }
+
+    fn notify(&mut self, channel: &str, event: &str) -> Result<(), ErrorCode> {
+        self.conn.publish::<&str, &str, i32>(channel, event)?;
+        Ok(())
+    }
+
+    fn reconnect(&mut self) {
+        self.conn = reconnect_loop(&self.client);
+    }
+}
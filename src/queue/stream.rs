@@ -0,0 +1,257 @@
+use std::collections::HashMap;
+
+use redis::streams::{StreamId, StreamReadOptions, StreamReadReply};
+use redis::{Commands, Connection, RedisResult, Value};
+
+use super::{
+    connect, dead_letter_key, reconnect_loop, requeue_due_retries, retry_key, Delivery, ErrorCode,
+    JobQueue,
+};
+
+// Field of the stream entry holding the job json
+pub const JOB_FIELD: &str = "job";
+
+pub fn stream_key(redis_prefix: &str) -> String {
+    format!("{}:stream", redis_prefix)
+}
+
+pub fn ensure_group(conn: &mut Connection, key: &str, group: &str) -> RedisResult<()> {
+    // Start from the beginning so jobs added before the group existed are not skipped
+    match conn.xgroup_create_mkstream::<&str, &str, &str, ()>(key, group, "0") {
+        Err(err) if err.code() == Some("BUSYGROUP") => Ok(()),
+        res => res,
+    }
+}
+
+fn to_delivery(entry: &StreamId) -> Delivery {
+    let raw = entry
+        .map
+        .get(JOB_FIELD)
+        .and_then(|v| redis::from_redis_value::<String>(v).ok())
+        .unwrap_or_default();
+    Delivery {
+        id: entry.id.clone(),
+        raw,
+    }
+}
+
+// Blocks up to `block_ms` for a new entry that was never delivered to the group
+pub fn read_new(
+    conn: &mut Connection,
+    key: &str,
+    group: &str,
+    consumer: &str,
+    block_ms: usize,
+) -> RedisResult<Option<Delivery>> {
+    let opts = StreamReadOptions::default()
+        .group(group, consumer)
+        .count(1)
+        .block(block_ms);
+    let reply: Option<StreamReadReply> = conn.xread_options(&[key], &[">"], &opts)?;
+    Ok(reply
+        .and_then(|reply| reply.keys.into_iter().next())
+        .and_then(|stream| stream.ids.first().map(to_delivery)))
+}
+
+// Entries delivered to this consumer but never acknowledged, e.g. before a restart
+pub fn read_pending(
+    conn: &mut Connection,
+    key: &str,
+    group: &str,
+    consumer: &str,
+) -> RedisResult<Vec<Delivery>> {
+    let opts = StreamReadOptions::default()
+        .group(group, consumer)
+        .count(100);
+    let reply: Option<StreamReadReply> = conn.xread_options(&[key], &["0"], &opts)?;
+    Ok(reply
+        .and_then(|reply| reply.keys.into_iter().next())
+        .map(|stream| stream.ids.iter().map(to_delivery).collect())
+        .unwrap_or_default())
+}
+
+/* autoclaim
+
+   @dev Takes over one entry that has been pending on another consumer for longer than
+        `min_idle_ms`, which means the worker holding it has died
+
+*/
+pub fn autoclaim(
+    conn: &mut Connection,
+    key: &str,
+    group: &str,
+    consumer: &str,
+    min_idle_ms: usize,
+) -> RedisResult<Option<Delivery>> {
+    let reply: Value = redis::cmd("XAUTOCLAIM")
+        .arg(key)
+        .arg(group)
+        .arg(consumer)
+        .arg(min_idle_ms)
+        .arg("0-0")
+        .arg("COUNT")
+        .arg(1)
+        .query(conn)?;
+
+    // Reply is [next-start-id, [[id, [field, value, ...]], ...], [deleted-ids]]
+    let entries = match reply {
+        Value::Bulk(parts) => parts.into_iter().nth(1),
+        _ => None,
+    };
+    let entry = match entries {
+        Some(Value::Bulk(entries)) => entries.into_iter().next(),
+        _ => None,
+    };
+    match entry {
+        Some(Value::Bulk(entry)) if entry.len() == 2 => {
+            let id: String = redis::from_redis_value(&entry[0])?;
+            let map: HashMap<String, Value> =
+                redis::from_redis_value(&entry[1]).unwrap_or_default();
+            Ok(Some(to_delivery(&StreamId { id, map })))
+        }
+        _ => Ok(None),
+    }
+}
+
+/* RedisStreamQueue
+
+   @dev Reads `{prefix}:stream` through a consumer group, the worker id is the consumer
+        name. Acknowledged entries stay in the stream as a replayable history
+
+*/
+pub struct RedisStreamQueue {
+    client: redis::Client,
+    conn: Connection,
+    redis_prefix: String,
+    key: String,
+    group: String,
+    consumer: String,
+    claim_idle_ms: usize,
+}
+
+impl RedisStreamQueue {
+    pub fn new(
+        redis_host: &str,
+        redis_prefix: &str,
+        worker_id: &str,
+        group: &str,
+        claim_idle_ms: usize,
+    ) -> Result<Self, ErrorCode> {
+        let client = redis::Client::open(redis_host)?;
+        let mut conn = connect(&client)?;
+        let key = stream_key(redis_prefix);
+        ensure_group(&mut conn, &key, group)?;
+
+        Ok(RedisStreamQueue {
+            client,
+            conn,
+            redis_prefix: redis_prefix.to_string(),
+            key,
+            group: group.to_string(),
+            consumer: worker_id.to_string(),
+            claim_idle_ms,
+        })
+    }
+}
+
+impl JobQueue for RedisStreamQueue {
+    fn submit(&mut self, raw: &str) -> Result<(), ErrorCode> {
+        self.conn
+            .xadd::<&str, &str, &str, &str, String>(&self.key, "*", &[(JOB_FIELD, raw)])?;
+        Ok(())
+    }
+
+    fn fetch(&mut self) -> Result<Option<Delivery>, ErrorCode> {
+        // Own pending entries first, they were left by a previous run of this worker
+        let pending = read_pending(&mut self.conn, &self.key, &self.group, &self.consumer)?;
+        if let Some(delivery) = pending.into_iter().next() {
+            return Ok(Some(delivery));
+        }
+        let claimed = autoclaim(
+            &mut self.conn,
+            &self.key,
+            &self.group,
+            &self.consumer,
+            self.claim_idle_ms,
+        )?;
+        if claimed.is_some() {
+            return Ok(claimed);
+        }
+        Ok(read_new(
+            &mut self.conn,
+            &self.key,
+            &self.group,
+            &self.consumer,
+            1000,
+        )?)
+    }
+
+    fn ack(&mut self, delivery: &Delivery) -> Result<(), ErrorCode> {
+        self.conn
+            .xack::<&str, &str, &str, i32>(&self.key, &self.group, &[&delivery.id])?;
+        Ok(())
+    }
+
+    fn retry_at(&mut self, delivery: &Delivery, raw: &str, due: i64) -> Result<(), ErrorCode> {
+        redis::pipe()
+            .atomic()
+            .xack(&self.key, &self.group, &[&delivery.id])
+            .zadd(retry_key(&self.redis_prefix), raw, due)
+            .query::<()>(&mut self.conn)?;
+        Ok(())
+    }
+
+    fn requeue_due(&mut self, now: i64) -> Result<usize, ErrorCode> {
+        requeue_due_retries(&mut self.conn, &self.redis_prefix, &self.key, true, now)
+    }
+
+    fn dead_letter(&mut self, delivery: &Delivery, entry: &str) -> Result<(), ErrorCode> {
+        redis::pipe()
+            .atomic()
+            .xack(&self.key, &self.group, &[&delivery.id])
+            .lpush(dead_letter_key(&self.redis_prefix), entry)
+            .query::<()>(&mut self.conn)?;
+        Ok(())
+    }
+
+    fn dead_letters(&mut self) -> Result<Vec<String>, ErrorCode> {
+        Ok(self
+            .conn
+            .lrange(dead_letter_key(&self.redis_prefix), 0, -1)?)
+    }
+
+    fn replay(&mut self, entry: &str, raw: &str) -> Result<(), ErrorCode> {
+        redis::pipe()
+            .atomic()
+            .lrem(dead_letter_key(&self.redis_prefix), 1, entry)
+            .xadd(&self.key, "*", &[(JOB_FIELD, raw)])
+            .query::<()>(&mut self.conn)?;
+        Ok(())
+    }
+
+    fn held(&mut self) -> Result<Vec<Delivery>, ErrorCode> {
+        Ok(read_pending(
+            &mut self.conn,
+            &self.key,
+            &self.group,
+            &self.consumer,
+        )?)
+    }
+
+    fn renew(&mut self, delivery: &Delivery) -> Result<(), ErrorCode> {
+        // Claiming our own entry resets its idle time, JUSTID leaves the delivery count alone
+        redis::cmd("XCLAIM")
+            .arg(&self.key)
+            .arg(&self.group)
+            .arg(&self.consumer)
+            .arg(0)
+            .arg(&delivery.id)
+            .arg("JUSTID")
+            .query::<()>(&mut self.conn)?;
+        Ok(())
+    }
+
+    fn reconnect(&mut self) {
+        self.conn = reconnect_loop(&self.client);
+    }
+}
@@ -0,0 +1,392 @@
+use std::collections::HashMap;
+
+use serde_json::{json, Value};
+
+use crate::gas;
+
+/* Rubric
+
+   @dev How the tests of a question add up, read from their annotations:
+
+        Score     points of the test, decimals allowed, counted in the total
+        Group     tests of a group are all-or-nothing, their scores only count once
+                  every test of the group passed
+        Bonus     points on top of the total, earned when the test passes
+        Penalty   points taken off when the test fails
+        Requires  tests or groups, separated by spaces or commas, that have to pass
+                  before this test counts at all, e.g. `testDeploy` or `setup`. A test
+                  is looked up in the contract of the test first, `VaultTest.testDeploy`
+                  or `test/Vault.t.sol:VaultTest.testDeploy` name one of another
+        GasTarget the score only counts whole if the test used at most this gas,
+        GasCeiling  `GasPartial` of it (half by default) at most this gas, nothing above
+        GasPartial
+
+        The score earned never drops below 0 but may exceed the total through bonus
+        tests
+
+*/
+struct Item {
+    file: String,
+    contract: String,
+    func: String,
+    signature: String,
+    group: Option<String>,
+    score: f64,
+    bonus: f64,
+    penalty: f64,
+    requires: Vec<String>,
+    passed: bool,
+    gas: Option<u64>,
+    gas_target: Option<u64>,
+    gas_ceiling: Option<u64>,
+    gas_partial: f64,
+}
+
+impl Item {
+    fn is_named(&self, name: &str) -> bool {
+        names_test(
+            name,
+            &self.file,
+            &self.contract,
+            &self.func,
+            &self.signature,
+        )
+    }
+
+    fn same_suite(&self, other: &Item) -> bool {
+        self.file == other.file && self.contract == other.contract
+    }
+
+    // Share of the score the gas used earns, with the tier reached when there is a target
+    fn gas_share(&self) -> (f64, Option<&'static str>) {
+        match (self.gas_target, self.gas) {
+            (None, _) => (1.0, None),
+            (Some(target), Some(gas)) => {
+                let (share, tier) = gas::tier(gas, target, self.gas_ceiling, self.gas_partial);
+                (share, Some(tier))
+            }
+            (Some(_), None) => (0.0, Some("missing")),
+        }
+    }
+}
+
+// A number of an annotation, annotations are collected as strings
+fn number(value: &Value) -> f64 {
+    match value {
+        Value::String(raw) => raw.trim().parse().unwrap_or(0.0),
+        value => value.as_f64().unwrap_or(0.0),
+    }
+}
+
+// Whole scores stay integers in the results, the others are rounded to 4 decimals
+pub fn score_json(score: f64) -> Value {
+    let score = (score * 10_000.0).round() / 10_000.0;
+    if score.fract() == 0.0 && score.abs() < 1e15 {
+        json!(score as i64)
+    } else {
+        json!(score)
+    }
+}
+
+// Whether a name of `Requires` is the test: its function name or signature, bare or
+// qualified by `Contract.` or `path:Contract.`
+pub fn names_test(name: &str, file: &str, contract: &str, func: &str, signature: &str) -> bool {
+    [func, signature].iter().any(|short| {
+        name == *short
+            || name.strip_suffix(short).is_some_and(|suite| {
+                suite == format!("{}.", contract) || suite == format!("{}:{}.", file, contract)
+            })
+    })
+}
+
+pub fn requirements(raw: &str) -> Vec<String> {
+    raw.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+fn item(q: &Value) -> Item {
+    let text = |key: &str| q[key].as_str().unwrap_or("").to_string();
+    Item {
+        file: text("File"),
+        contract: text("Contract"),
+        func: text("Func"),
+        signature: text("Signature"),
+        group: q["Group"].as_str().map(str::to_string),
+        score: number(&q["Score"]),
+        bonus: number(&q["Bonus"]),
+        penalty: number(&q["Penalty"]),
+        requires: requirements(q["Requires"].as_str().unwrap_or("")),
+        passed: q["Passed"].as_bool().unwrap_or(false),
+        gas: q["Gas"].as_u64(),
+        gas_target: q["GasTarget"].as_str().and_then(|raw| raw.parse().ok()),
+        gas_ceiling: q["GasCeiling"].as_str().and_then(|raw| raw.parse().ok()),
+        gas_partial: q["GasPartial"]
+            .as_str()
+            .and_then(|raw| raw.parse().ok())
+            .unwrap_or(gas::DEFAULT_PARTIAL),
+    }
+}
+
+/* apply
+
+   @dev Scores the judged `questions` in place, each gets its numeric `Score`, whether
+        it `Counted` and what it `Earned`. Returns the total, the score earned and the
+        breakdown per group, tests without a group listed last under a `null` name
+
+*/
+pub fn apply(questions: &mut [Value]) -> (f64, f64, Value) {
+    let items: Vec<Item> = questions.iter().map(item).collect();
+
+    let mut group_passed: HashMap<&str, bool> = HashMap::new();
+    let mut groups: Vec<&str> = vec![];
+    for item in &items {
+        if let Some(group) = &item.group {
+            if !group_passed.contains_key(group.as_str()) {
+                groups.push(group);
+            }
+            *group_passed.entry(group).or_insert(true) &= item.passed;
+        }
+    }
+    // Prerequisites look at the plain outcome of the tests they name, so cycles cannot hang.
+    // Tests sharing a name in several contracts are told apart by the contract requiring them
+    let required_passed = |item: &Item, name: &str| {
+        let all_passed = |named: Vec<&Item>| !named.is_empty() && named.iter().all(|t| t.passed);
+        let suite: Vec<&Item> = items
+            .iter()
+            .filter(|other| other.same_suite(item) && other.is_named(name))
+            .collect();
+        if !suite.is_empty() {
+            return all_passed(suite);
+        }
+        if let Some(passed) = group_passed.get(name) {
+            return *passed;
+        }
+        all_passed(items.iter().filter(|other| other.is_named(name)).collect())
+    };
+    let counted: Vec<bool> = items
+        .iter()
+        .map(|item| item.requires.iter().all(|name| required_passed(item, name)))
+        .collect();
+    let mut group_complete: HashMap<&str, bool> = HashMap::new();
+    for (item, counted) in items.iter().zip(&counted) {
+        if let Some(group) = &item.group {
+            *group_complete.entry(group).or_insert(true) &= item.passed && *counted;
+        }
+    }
+
+    let mut total = 0.0;
+    let mut credited = vec![0.0; items.len()];
+    let (mut bonus, mut penalty) = (0.0, 0.0);
+    for (i, (item, counted)) in items.iter().zip(&counted).enumerate() {
+        let complete = item
+            .group
+            .as_ref()
+            .is_none_or(|group| group_complete[group.as_str()]);
+        let (gas_share, gas_tier) = item.gas_share();
+        total += item.score;
+        if *counted && item.passed && complete {
+            credited[i] = item.score * gas_share;
+        }
+        let item_bonus = if *counted && item.passed {
+            item.bonus
+        } else {
+            0.0
+        };
+        let item_penalty = if *counted && !item.passed {
+            item.penalty
+        } else {
+            0.0
+        };
+        bonus += item_bonus;
+        penalty += item_penalty;
+
+        let q = &mut questions[i];
+        q["Score"] = score_json(item.score);
+        q["Counted"] = json!(counted);
+        q["Earned"] = score_json(credited[i] + item_bonus - item_penalty);
+        if let Some(tier) = gas_tier {
+            q["GasTier"] = json!(tier);
+        }
+    }
+
+    let mut breakdown = vec![];
+    let ungrouped = items.iter().any(|item| item.group.is_none());
+    for name in groups
+        .into_iter()
+        .map(Some)
+        .chain(ungrouped.then_some(None))
+    {
+        let members: Vec<usize> = (0..items.len())
+            .filter(|&i| items[i].group.as_deref() == name)
+            .collect();
+        breakdown.push(json!({
+            "name": name,
+            "tests": members.iter().map(|&i| &items[i].func).collect::<Vec<_>>(),
+            "total": score_json(members.iter().map(|&i| items[i].score).sum()),
+            "earned": score_json(members.iter().map(|&i| credited[i]).sum()),
+            "passed": members.iter().all(|&i| items[i].passed && counted[i]),
+        }));
+    }
+
+    let earned = (credited.iter().sum::<f64>() + bonus - penalty).max(0.0);
+    let rubric = json!({
+        "groups": breakdown,
+        "bonus": score_json(bonus),
+        "penalty": score_json(penalty),
+    });
+    (total, earned, rubric)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn question(contract: &str, func: &str, passed: bool, tags: Value) -> Value {
+        let mut q = json!({
+            "Func": func,
+            "File": "test/Vault.t.sol",
+            "Contract": contract,
+            "Signature": format!("{}()", func),
+            "Passed": passed,
+        });
+        for (key, value) in tags.as_object().unwrap() {
+            q[key] = value.clone();
+        }
+        q
+    }
+
+    #[test]
+    fn scores_are_whole_or_rounded() {
+        assert_eq!(score_json(10.0), json!(10));
+        assert_eq!(score_json(2.5), json!(2.5));
+        assert_eq!(score_json(1.0 / 3.0), json!(0.3333));
+        assert_eq!(score_json(0.99999), json!(1));
+    }
+
+    #[test]
+    fn groups_bonus_and_penalty_add_up() {
+        let mut questions = vec![
+            question(
+                "T",
+                "testDeploy",
+                true,
+                json!({"Score": "2", "Group": "deploy"}),
+            ),
+            question(
+                "T",
+                "testInit",
+                false,
+                json!({"Score": "2", "Group": "deploy"}),
+            ),
+            question("T", "testA", true, json!({"Score": "3.5"})),
+            question("T", "testBonus", true, json!({"Bonus": "2"})),
+            question("T", "testSafe", false, json!({"Penalty": "1"})),
+        ];
+        let (total, earned, rubric) = apply(&mut questions);
+
+        assert_eq!(total, 7.5);
+        assert_eq!(earned, 3.5 + 2.0 - 1.0);
+        // A group only counts once all of its tests passed
+        assert_eq!(questions[0]["Earned"], json!(0));
+        assert_eq!(questions[2]["Earned"], json!(3.5));
+        assert_eq!(questions[4]["Earned"], json!(-1));
+        assert_eq!(
+            rubric["groups"],
+            json!([
+                {"name": "deploy", "tests": ["testDeploy", "testInit"], "total": 4, "earned": 0, "passed": false},
+                {"name": null, "tests": ["testA", "testBonus", "testSafe"], "total": 3.5, "earned": 3.5, "passed": false},
+            ])
+        );
+        assert_eq!(
+            (rubric["bonus"].clone(), rubric["penalty"].clone()),
+            (json!(2), json!(1))
+        );
+    }
+
+    #[test]
+    fn penalties_never_take_the_score_below_zero() {
+        let mut questions = vec![question(
+            "T",
+            "testA",
+            false,
+            json!({"Score": "1", "Penalty": "5"}),
+        )];
+        let (total, earned, _) = apply(&mut questions);
+        assert_eq!((total, earned), (1.0, 0.0));
+    }
+
+    #[test]
+    fn requirements_are_looked_up_in_the_contract_first() {
+        let requires = |name: &str| json!({"Score": "1", "Requires": name});
+        let mut questions = vec![
+            question("DepositTest", "testSetup", true, json!({})),
+            question("DepositTest", "testDeposit", true, requires("testSetup")),
+            question("WithdrawTest", "testSetup", false, json!({})),
+            question("WithdrawTest", "testWithdraw", true, requires("testSetup")),
+            question(
+                "WithdrawTest",
+                "testQualified",
+                true,
+                requires("DepositTest.testSetup"),
+            ),
+            question("WithdrawTest", "testGroup", true, requires("setup")),
+            question("WithdrawTest", "testMissing", true, requires("testNothing")),
+            question(
+                "Other",
+                "testAnywhere",
+                true,
+                requires("testDeposit(), setup"),
+            ),
+        ];
+        questions[0]["Group"] = json!("setup");
+        let (_, _, _) = apply(&mut questions);
+
+        let counted: Vec<bool> = questions
+            .iter()
+            .map(|q| q["Counted"].as_bool().unwrap())
+            .collect();
+        assert_eq!(
+            counted,
+            vec![true, true, true, false, true, true, false, true]
+        );
+    }
+
+    #[test]
+    fn qualified_names_match_one_test() {
+        let names = |name: &str| {
+            names_test(
+                name,
+                "test/Vault.t.sol",
+                "VaultTest",
+                "testA",
+                "testA(uint256)",
+            )
+        };
+        assert!(names("testA"));
+        assert!(names("testA(uint256)"));
+        assert!(names("VaultTest.testA"));
+        assert!(names("test/Vault.t.sol:VaultTest.testA(uint256)"));
+        assert!(!names("OtherTest.testA"));
+        assert!(!names("test/Other.t.sol:VaultTest.testA"));
+        assert!(!names("estA"));
+    }
+
+    #[test]
+    fn gas_targets_scale_the_score() {
+        let gas = |used: u64| json!({"Score": "10", "Gas": used, "GasTarget": "1000", "GasCeiling": "2000"});
+        let mut questions = vec![
+            question("T", "testCheap", true, gas(900)),
+            question("T", "testMid", true, gas(1500)),
+            question("T", "testOver", true, gas(3000)),
+        ];
+        let (total, earned, _) = apply(&mut questions);
+        assert_eq!((total, earned), (30.0, 15.0));
+        let tiers: Vec<&str> = questions
+            .iter()
+            .map(|q| q["GasTier"].as_str().unwrap())
+            .collect();
+        assert_eq!(tiers, vec!["target", "ceiling", "over"]);
+    }
+}
@@ -0,0 +1,371 @@
+use std::ffi::CString;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::{env, fs};
+
+// System directories forge and solc need to run, mounted read-only when they exist
+const SYSTEM_PATHS: [&str; 8] = [
+    "/usr", "/bin", "/sbin", "/lib", "/lib32", "/lib64", "/etc", "/dev",
+];
+
+// Project files forge reads from the working directory, mounted read-only
+const PROJECT_PATHS: [&str; 4] = ["lib", "out", "foundry.toml", "remappings.txt"];
+
+// The uid and gid forge runs as inside the sandbox, it has no capabilities there
+const SANDBOX_ID: u32 = 1000;
+
+#[derive(Debug)]
+pub enum ErrorCode {
+    SetupFailure(String),
+}
+
+impl ErrorCode {
+    pub fn get_err_msg(&self) -> String {
+        match self {
+            ErrorCode::SetupFailure(err) => format!("Sandbox setup failed: {}", err),
+        }
+    }
+}
+
+/* SandboxConfig
+
+   @dev Confines the forge runs of a worker. Forge only sees the system directories,
+        `lib/`, the shared artifacts and the solc cache read-only, and the directory of
+        the job writable, in mount, PID and network namespaces of its own
+
+*/
+#[derive(Debug, Clone, Default)]
+pub struct SandboxConfig {
+    pub enabled: bool,
+    pub solc_dir: PathBuf,
+    pub memory_mb: u64,
+    pub max_processes: u64,
+    pub file_size_mb: u64,
+}
+
+struct Bind {
+    src: CString,
+    dst: CString,
+    is_dir: bool,
+    writable: bool,
+    // Flags of the source mount a read-only remount inside a user namespace must keep
+    locked_flags: libc::c_ulong,
+}
+
+/* Plan
+
+   @dev Everything the child needs to enter the sandbox, computed before the fork since
+        the child may not allocate between fork and exec
+
+*/
+struct Plan {
+    root: CString,
+    cwd: CString,
+    proc_dir: CString,
+    dirs: Vec<CString>,
+    symlinks: Vec<(CString, CString)>,
+    binds: Vec<Bind>,
+    uid_map: Vec<u8>,
+    gid_map: Vec<u8>,
+    rlimits: Vec<(libc::__rlimit_resource_t, libc::rlim_t)>,
+}
+
+fn c_path(path: &Path) -> Result<CString, ErrorCode> {
+    CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| ErrorCode::SetupFailure(format!("{} contains a nul byte", path.display())))
+}
+
+fn io_err(err: io::Error) -> ErrorCode {
+    ErrorCode::SetupFailure(err.to_string())
+}
+
+// Where `forge` is found on the PATH, both as the PATH sees it and resolved
+fn forge_dirs() -> Vec<PathBuf> {
+    let mut dirs = vec![];
+    for dir in env::split_paths(&env::var_os("PATH").unwrap_or_default()) {
+        let forge = dir.join("forge");
+        if forge.is_file() {
+            dirs.push(dir);
+            if let Some(parent) = fs::canonicalize(&forge)
+                .ok()
+                .and_then(|path| path.parent().map(Path::to_path_buf))
+            {
+                dirs.push(parent);
+            }
+            break;
+        }
+    }
+    dirs
+}
+
+fn locked_flags(path: &Path) -> Result<libc::c_ulong, ErrorCode> {
+    let c_path = c_path(path)?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(io_err(io::Error::last_os_error()));
+    }
+
+    let mut flags = 0;
+    for (st_flag, ms_flag) in [
+        (libc::ST_NOSUID, libc::MS_NOSUID),
+        (libc::ST_NODEV, libc::MS_NODEV),
+        (libc::ST_NOEXEC, libc::MS_NOEXEC),
+        (libc::ST_NOATIME, libc::MS_NOATIME),
+        (libc::ST_NODIRATIME, libc::MS_NODIRATIME),
+        (libc::ST_RELATIME, libc::MS_RELATIME),
+    ] {
+        if stat.f_flag & st_flag != 0 {
+            flags |= ms_flag;
+        }
+    }
+    Ok(flags)
+}
+
+impl Plan {
+    fn new(config: &SandboxConfig, root: &Path, scratch: &Path) -> Result<Plan, ErrorCode> {
+        let cwd = env::current_dir().map_err(io_err)?;
+        let root = cwd.join(root);
+        fs::create_dir_all(&root).map_err(io_err)?;
+
+        let mut sources: Vec<(PathBuf, bool)> = vec![];
+        sources.extend(SYSTEM_PATHS.iter().map(|path| (PathBuf::from(path), false)));
+        sources.extend(forge_dirs().into_iter().map(|path| (path, false)));
+        sources.push((config.solc_dir.clone(), false));
+        sources.extend(PROJECT_PATHS.iter().map(|path| (cwd.join(path), false)));
+        sources.push((cwd.join(scratch), true));
+
+        let mut plan = Plan {
+            root: c_path(&root)?,
+            cwd: c_path(&cwd)?,
+            proc_dir: c_path(&root.join("proc"))?,
+            dirs: vec![],
+            symlinks: vec![],
+            binds: vec![],
+            uid_map: format!("{} {} 1", SANDBOX_ID, unsafe { libc::getuid() }).into_bytes(),
+            gid_map: format!("{} {} 1", SANDBOX_ID, unsafe { libc::getgid() }).into_bytes(),
+            rlimits: vec![
+                (libc::RLIMIT_AS, config.memory_mb.saturating_mul(1 << 20)),
+                (libc::RLIMIT_NPROC, config.max_processes),
+                (
+                    libc::RLIMIT_FSIZE,
+                    config.file_size_mb.saturating_mul(1 << 20),
+                ),
+            ],
+        };
+        plan.add_dir(&root, Path::new("/proc"))?;
+        plan.add_dir(&root, Path::new("/tmp"))?;
+
+        let mut bound: Vec<PathBuf> = vec![];
+        for (src, writable) in sources {
+            let Ok(meta) = fs::symlink_metadata(&src) else {
+                continue;
+            };
+            // Already visible through a mount of one of its parents
+            if bound.iter().any(|parent| src.starts_with(parent)) {
+                continue;
+            }
+            let dst = root.join(src.strip_prefix("/").unwrap_or(&src));
+            if let Some(parent) = src.parent() {
+                plan.add_dir(&root, parent)?;
+            }
+
+            if meta.file_type().is_symlink() {
+                let target = fs::read_link(&src).map_err(io_err)?;
+                plan.symlinks.push((c_path(&target)?, c_path(&dst)?));
+                continue;
+            }
+            let is_dir = meta.is_dir();
+            if is_dir {
+                plan.add_dir(&root, &src)?;
+            }
+            plan.binds.push(Bind {
+                src: c_path(&src)?,
+                dst: c_path(&dst)?,
+                is_dir,
+                writable,
+                locked_flags: locked_flags(&src)?,
+            });
+            bound.push(src);
+        }
+        Ok(plan)
+    }
+
+    // Queues `path` and its parents to be created inside the new root
+    fn add_dir(&mut self, root: &Path, path: &Path) -> Result<(), ErrorCode> {
+        let mut dir = root.to_path_buf();
+        for component in path.strip_prefix("/").unwrap_or(path).components() {
+            dir.push(component);
+            let c_dir = c_path(&dir)?;
+            if !self.dirs.contains(&c_dir) {
+                self.dirs.push(c_dir);
+            }
+        }
+        Ok(())
+    }
+
+    /* enter
+
+       @dev Runs in the child between fork and exec: only raw syscalls on the data
+            prepared by `Plan::new`
+
+    */
+    fn enter(&self) -> io::Result<()> {
+        unsafe {
+            check(libc::unshare(
+                libc::CLONE_NEWUSER | libc::CLONE_NEWNS | libc::CLONE_NEWPID | libc::CLONE_NEWNET,
+            ))?;
+            write_file(c"/proc/self/setgroups", b"deny")?;
+            write_file(c"/proc/self/uid_map", &self.uid_map)?;
+            write_file(c"/proc/self/gid_map", &self.gid_map)?;
+
+            // Only the children of this process enter the new PID namespace, forge has
+            // to be one of them. This process stays behind and passes its status on
+            let pid = check(libc::fork())?;
+            if pid > 0 {
+                wait_and_exit(pid);
+            }
+
+            check(libc::mount(
+                std::ptr::null(),
+                c"/".as_ptr(),
+                std::ptr::null(),
+                libc::MS_REC | libc::MS_PRIVATE,
+                std::ptr::null(),
+            ))?;
+            check(libc::mount(
+                c"tmpfs".as_ptr(),
+                self.root.as_ptr(),
+                c"tmpfs".as_ptr(),
+                libc::MS_NOSUID | libc::MS_NODEV,
+                c"mode=755".as_ptr().cast(),
+            ))?;
+            for dir in &self.dirs {
+                if libc::mkdir(dir.as_ptr(), 0o755) != 0
+                    && io::Error::last_os_error().raw_os_error() != Some(libc::EEXIST)
+                {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            for (target, link) in &self.symlinks {
+                check(libc::symlink(target.as_ptr(), link.as_ptr()))?;
+            }
+            for bind in &self.binds {
+                self.mount_bind(bind)?;
+            }
+            check(libc::mount(
+                c"proc".as_ptr(),
+                self.proc_dir.as_ptr(),
+                c"proc".as_ptr(),
+                libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC,
+                std::ptr::null(),
+            ))?;
+
+            check(libc::chdir(self.root.as_ptr()))?;
+            check(libc::syscall(libc::SYS_pivot_root, c".".as_ptr(), c".".as_ptr()) as i32)?;
+            check(libc::umount2(c".".as_ptr(), libc::MNT_DETACH))?;
+            check(libc::chdir(self.cwd.as_ptr()))?;
+
+            for (resource, limit) in &self.rlimits {
+                let rlimit = libc::rlimit {
+                    rlim_cur: *limit,
+                    rlim_max: *limit,
+                };
+                check(libc::setrlimit(*resource, &rlimit))?;
+            }
+        }
+        Ok(())
+    }
+
+    unsafe fn mount_bind(&self, bind: &Bind) -> io::Result<()> {
+        if !bind.is_dir {
+            let fd = check(libc::open(
+                bind.dst.as_ptr(),
+                libc::O_CREAT | libc::O_WRONLY | libc::O_CLOEXEC,
+                0o644,
+            ))?;
+            libc::close(fd);
+        }
+        check(libc::mount(
+            bind.src.as_ptr(),
+            bind.dst.as_ptr(),
+            std::ptr::null(),
+            libc::MS_BIND | libc::MS_REC,
+            std::ptr::null(),
+        ))?;
+        if !bind.writable {
+            check(libc::mount(
+                std::ptr::null(),
+                bind.dst.as_ptr(),
+                std::ptr::null(),
+                libc::MS_BIND | libc::MS_REMOUNT | libc::MS_RDONLY | bind.locked_flags,
+                std::ptr::null(),
+            ))?;
+        }
+        Ok(())
+    }
+}
+
+fn check(res: libc::c_int) -> io::Result<libc::c_int> {
+    if res < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(res)
+    }
+}
+
+unsafe fn write_file(path: &std::ffi::CStr, content: &[u8]) -> io::Result<()> {
+    let fd = check(libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC))?;
+    let written = libc::write(fd, content.as_ptr().cast(), content.len());
+    libc::close(fd);
+    if written != content.len() as isize {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// Exits like the sandboxed child did, so a kill by SIGXCPU still reads as one
+unsafe fn wait_and_exit(pid: libc::pid_t) -> ! {
+    // The pipe std reports exec errors through must not be held open by this process
+    if libc::syscall(libc::SYS_close_range, 3, libc::c_uint::MAX, 0) != 0 {
+        for fd in 3..4096 {
+            libc::close(fd);
+        }
+    }
+
+    let mut status = 0;
+    while libc::waitpid(pid, &mut status, 0) < 0 {
+        if io::Error::last_os_error().raw_os_error() != Some(libc::EINTR) {
+            libc::_exit(1);
+        }
+    }
+    if libc::WIFSIGNALED(status) {
+        let signal = libc::WTERMSIG(status);
+        libc::signal(signal, libc::SIG_DFL);
+        libc::kill(libc::getpid(), signal);
+        libc::_exit(128 + signal);
+    }
+    libc::_exit(libc::WEXITSTATUS(status));
+}
+
+/* confine
+
+   @dev Makes `command` run inside the sandbox. `root` is an empty directory the new
+        root is mounted on, `scratch` the directory of the job, the only one forge can
+        write to. Both are relative to the working directory
+
+*/
+pub fn confine(
+    command: &mut Command,
+    config: &SandboxConfig,
+    root: &Path,
+    scratch: &Path,
+) -> Result<(), ErrorCode> {
+    let plan = Plan::new(config, root, scratch)?;
+    unsafe {
+        command.pre_exec(move || plan.enter());
+    }
+    Ok(())
+}
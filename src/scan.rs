@@ -0,0 +1,361 @@
+use regex::Regex;
+use serde::Serialize;
+
+/* DEFAULT_FORBIDDEN
+
+   @dev Cheatcodes reaching outside the EVM, refused unless the question sets its own
+        list. `assembly`, `selfdestruct` and `delegatecall` can be forbidden per question
+
+*/
+pub const DEFAULT_FORBIDDEN: [&str; 12] = [
+    "vm.ffi",
+    "vm.readFile",
+    "vm.readFileBinary",
+    "vm.readLine",
+    "vm.writeFile",
+    "vm.writeFileBinary",
+    "vm.writeLine",
+    "vm.removeFile",
+    "vm.setEnv",
+    "vm.createFork",
+    "vm.createSelectFork",
+    "vm.rpc",
+];
+
+const CONSTRUCTS: [&str; 3] = ["assembly", "selfdestruct", "delegatecall"];
+
+// Reported for raw calls into the cheatcode contract, which bypass the cheatcode rules
+const CHEATCODE_ADDRESS_RULE: &str = "cheatcode address";
+// address(uint160(uint256(keccak256("hevm cheat code"))))
+const CHEATCODE_ADDRESS: &str = "0x7109709ECfa91a80626fF3989D68f67F5b1DD12D";
+
+#[derive(Debug)]
+pub enum ErrorCode {
+    UnknownRule(String),
+}
+
+impl ErrorCode {
+    pub fn get_err_msg(&self) -> String {
+        match self {
+            ErrorCode::UnknownRule(rule) => format!(
+                "Unknown forbidden rule {}, expected vm.<cheatcode> or one of {}",
+                rule,
+                CONSTRUCTS.join(", ")
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Violation {
+    pub path: String,
+    pub line: usize,
+    pub rule: String,
+    pub snippet: String,
+}
+
+// `re` matches the code, `signature` the string literals, where a cheatcode signature
+// is spelled out for `abi.encodeWithSignature`
+struct Rule {
+    name: String,
+    re: Regex,
+    signature: Option<Regex>,
+}
+
+fn rule(name: &str) -> Result<Rule, ErrorCode> {
+    let (pattern, signature) = match name.strip_prefix("vm.") {
+        // The cheatcode contract may be bound to any name, and the cheatcode reached
+        // through call options or a function reference, match any member access
+        Some(cheatcode)
+            if !cheatcode.is_empty() && cheatcode.chars().all(char::is_alphanumeric) =>
+        {
+            (
+                format!(r"\.\s*{}\b", cheatcode),
+                Some(format!(r#"["']\s*{}\s*\("#, cheatcode)),
+            )
+        }
+        Some(_) => return Err(ErrorCode::UnknownRule(name.to_string())),
+        None if CONSTRUCTS.contains(&name) => (format!(r"\b{}\b", name), None),
+        None => return Err(ErrorCode::UnknownRule(name.to_string())),
+    };
+    Ok(Rule {
+        name: name.to_string(),
+        re: Regex::new(&pattern).unwrap(),
+        signature: signature.map(|signature| Regex::new(&signature).unwrap()),
+    })
+}
+
+// Fails on the first rule that is neither a cheatcode nor a known construct
+pub fn check_rules(names: &[String]) -> Result<(), ErrorCode> {
+    names.iter().try_for_each(|name| rule(name).map(|_| ()))
+}
+
+/* strip_comments_and_strings
+
+   @dev Blanks out comments and the content of string literals so they never match.
+        Every byte keeps its offset and newlines are kept, so lines and positions
+        found in the result are those of the source
+
+*/
+pub fn strip_comments_and_strings(source: &str) -> String {
+    blank_out(source, true)
+}
+
+// Blanks out the comments only, string literals are kept
+fn strip_comments(source: &str) -> String {
+    blank_out(source, false)
+}
+
+fn blank_out(source: &str, strings: bool) -> String {
+    let mut out = String::with_capacity(source.len());
+    let blank = |out: &mut String, c: char| {
+        if c == '\n' {
+            out.push('\n');
+        } else {
+            out.extend(std::iter::repeat_n(' ', c.len_utf8()));
+        }
+    };
+    let mut chars = source.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '/' if chars.peek() == Some(&'/') => {
+                blank(&mut out, c);
+                while let Some(&next) = chars.peek() {
+                    if next == '\n' {
+                        break;
+                    }
+                    blank(&mut out, next);
+                    chars.next();
+                }
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                out.push_str("  ");
+                let mut prev = ' ';
+                for next in chars.by_ref() {
+                    blank(&mut out, next);
+                    if prev == '*' && next == '/' {
+                        break;
+                    }
+                    prev = next;
+                }
+            }
+            '"' | '\'' => {
+                out.push(c);
+                let mut escaped = false;
+                for next in chars.by_ref() {
+                    if !escaped && next == c {
+                        out.push(c);
+                        break;
+                    }
+                    if strings {
+                        blank(&mut out, next);
+                    } else {
+                        out.push(next);
+                    }
+                    escaped = !escaped && next == '\\';
+                }
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/* cheatcode_address_uses
+
+   @dev Offsets of what reaches the cheatcode contract without naming a cheatcode: the
+        address of `vm` or of anything else typed `Vm`, `VM_ADDRESS` and `HEVM_ADDRESS`
+        outside a `Vm(...)` cast, the address itself and the seed it is derived from.
+        `code` has comments and strings blanked out, `text` only the comments
+
+*/
+fn cheatcode_address_uses(code: &str, text: &str) -> Vec<usize> {
+    let declared = Regex::new(
+        r"\bVm\s+(?:(?:constant|immutable|internal|private|public)\s+)*([A-Za-z_$][\w$]*)",
+    )
+    .unwrap();
+    let mut names = vec![String::from("vm")];
+    names.extend(declared.captures_iter(code).map(|c| regex::escape(&c[1])));
+    let address_of =
+        Regex::new(&format!(r"\baddress\s*\(\s*(?:{})\s*\)", names.join("|"))).unwrap();
+    let constants = Regex::new(r"\b(?:VM_ADDRESS|HEVM_ADDRESS)\b").unwrap();
+    let literal = Regex::new(&format!("(?i){}", CHEATCODE_ADDRESS)).unwrap();
+    let seed = Regex::new(r"hevm cheat code").unwrap();
+
+    // `Vm(VM_ADDRESS)` binds the cheatcodes to a name, calls through it are matched
+    let is_cast = |start: usize| {
+        let before = code[..start].trim_end();
+        before.strip_suffix('(').is_some_and(|before| {
+            let before = before.trim_end();
+            before.ends_with("Vm")
+                && !before[..before.len() - 2]
+                    .ends_with(|c: char| c.is_alphanumeric() || c == '_' || c == '$')
+        })
+    };
+    let mut found: Vec<usize> = address_of.find_iter(code).map(|m| m.start()).collect();
+    found.extend(
+        constants
+            .find_iter(code)
+            .filter(|m| !is_cast(m.start()))
+            .map(|m| m.start()),
+    );
+    found.extend(literal.find_iter(code).map(|m| m.start()));
+    found.extend(seed.find_iter(text).map(|m| m.start()));
+    found
+}
+
+/* find_violations
+
+   @dev Every use of a forbidden cheatcode or construct in `files`, given as path and
+        content, with the line it starts on. The whole file is matched, a call split
+        over lines is found as well. With any cheatcode forbidden, cheatcode signatures
+        in strings and raw calls into the cheatcode contract are refused too, they
+        would reach the same cheatcodes
+
+*/
+pub fn find_violations<'a>(
+    files: impl Iterator<Item = (&'a str, &'a str)>,
+    forbidden: &[String],
+) -> Result<Vec<Violation>, ErrorCode> {
+    let rules = forbidden
+        .iter()
+        .map(|name| rule(name))
+        .collect::<Result<Vec<_>, _>>()?;
+    let forbids_cheatcodes = rules.iter().any(|rule| rule.signature.is_some());
+
+    let mut violations: Vec<Violation> = vec![];
+    for (path, content) in files {
+        let code = strip_comments_and_strings(content);
+        let text = strip_comments(content);
+        let mut found: Vec<(usize, &str)> = vec![];
+        for rule in &rules {
+            found.extend(
+                rule.re
+                    .find_iter(&code)
+                    .map(|m| (m.start(), rule.name.as_str())),
+            );
+            if let Some(signature) = &rule.signature {
+                found.extend(
+                    signature
+                        .find_iter(&text)
+                        .map(|m| (m.start(), rule.name.as_str())),
+                );
+            }
+        }
+        if forbids_cheatcodes {
+            let uses = cheatcode_address_uses(&code, &text);
+            found.extend(
+                uses.into_iter()
+                    .map(|start| (start, CHEATCODE_ADDRESS_RULE)),
+            );
+        }
+        found.sort();
+
+        let lines: Vec<&str> = content.lines().collect();
+        for (start, rule) in found {
+            let line = code[..start].matches('\n').count() + 1;
+            // A rule is reported once per line
+            let reported = violations
+                .iter()
+                .any(|v| v.path == path && v.line == line && v.rule == rule);
+            if !reported {
+                violations.push(Violation {
+                    path: path.to_string(),
+                    line,
+                    rule: rule.to_string(),
+                    snippet: lines.get(line - 1).unwrap_or(&"").trim().to_string(),
+                });
+            }
+        }
+    }
+    Ok(violations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules_hit(source: &str) -> Vec<(usize, String)> {
+        let forbidden: Vec<String> = DEFAULT_FORBIDDEN.iter().map(|r| r.to_string()).collect();
+        find_violations([("test/A.t.sol", source)].into_iter(), &forbidden)
+            .unwrap()
+            .into_iter()
+            .map(|v| (v.line, v.rule))
+            .collect()
+    }
+
+    #[test]
+    fn calls_split_over_lines_are_found() {
+        let source = "contract T {\n    function testA() public {\n        vm.ffi\n            (cmd);\n        vm\n        .readFile(path);\n    }\n}\n";
+        assert_eq!(
+            rules_hit(source),
+            vec![(3, "vm.ffi".to_string()), (6, "vm.readFile".to_string())]
+        );
+    }
+
+    #[test]
+    fn call_options_and_references_are_found() {
+        let options = "function testA() public {\n    vm.ffi{gas: 1}(cmd);\n    vm.readFileBinary(path);\n}\n";
+        assert_eq!(
+            rules_hit(options),
+            vec![
+                (2, "vm.ffi".to_string()),
+                (3, "vm.readFileBinary".to_string())
+            ]
+        );
+
+        let reference = "function testA() public {\n    function(string[] memory) external returns (bytes memory) f = vm.ffi;\n    f(cmd);\n}\n";
+        assert_eq!(rules_hit(reference), vec![(2, "vm.ffi".to_string())]);
+    }
+
+    #[test]
+    fn comments_and_strings_do_not_match() {
+        let source = "// vm.ffi(cmd)\n/* vm.ffi(\n cmd) */\nstring s = \"vm.ffi(cmd)\";\nstring t = \"ffi\";\nVm constant cheats = Vm(VM_ADDRESS);\n";
+        assert!(rules_hit(source).is_empty());
+    }
+
+    #[test]
+    fn cheatcode_signatures_in_strings_are_found() {
+        let source = "function testA() public {\n    (bool ok, ) = target.call(\n        abi.encodeWithSignature(\"ffi(string[])\", cmd)\n    );\n}\n";
+        assert_eq!(rules_hit(source), vec![(3, "vm.ffi".to_string())]);
+    }
+
+    #[test]
+    fn raw_calls_into_the_cheatcode_contract_are_found() {
+        let through_vm = "address(vm).call(abi.encodeWithSelector(0x89160467, cmd));";
+        assert_eq!(
+            rules_hit(through_vm),
+            vec![(1, CHEATCODE_ADDRESS_RULE.to_string())]
+        );
+
+        let rebound = "Vm constant cheats = Vm(VM_ADDRESS);\nfunction testA() public {\n    address(cheats).call(data);\n}\n";
+        assert_eq!(
+            rules_hit(rebound),
+            vec![(3, CHEATCODE_ADDRESS_RULE.to_string())]
+        );
+
+        let constant = "VM_ADDRESS.call(data);\naddress(0x7109709ecfa91a80626ff3989d68f67f5b1dd12d).call(data);\naddress(uint160(uint256(keccak256(\"hevm cheat code\"))));\n";
+        assert_eq!(
+            rules_hit(constant),
+            vec![
+                (1, CHEATCODE_ADDRESS_RULE.to_string()),
+                (2, CHEATCODE_ADDRESS_RULE.to_string()),
+                (3, CHEATCODE_ADDRESS_RULE.to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn the_cheatcode_address_is_allowed_without_cheatcode_rules() {
+        let forbidden = vec![String::from("assembly")];
+        let files = [("test/A.t.sol", "address(vm).call(data);\nassembly {}\n")];
+        let violations = find_violations(files.into_iter(), &forbidden).unwrap();
+        assert_eq!(violations.len(), 1);
+        assert_eq!(
+            (violations[0].line, violations[0].rule.as_str()),
+            (2, "assembly")
+        );
+        assert_eq!(violations[0].snippet, "assembly {}");
+    }
+}
@@ -1,18 +1,23 @@
+use std::collections::HashMap;
 use std::io::Empty;
+use std::io::Read;
 use std::ops::{Deref, Div};
-use std::path::Path;
-use std::process::Command;
-use std::time::SystemTime;
+use std::path::{Component, Path, PathBuf};
+use std::process::{Child, Command, Output, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::Arc;
+use std::time::{Instant, SystemTime};
 use std::{default, env, fs, io};
 use std::{thread, time::Duration};
 
 use std::os::unix::fs::symlink;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
 
-use chrono::format;
+use chrono::Local;
 use clap::{error, Error};
 use log::{error, info, trace};
 use log4rs::encode::json;
-use redis::{Commands, Connection, RedisError, Script, Value};
+use redis::{Commands, Value};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, value};
 
@@ -20,6 +25,24 @@ use ansi_term::Colour::{Black, Blue, Cyan, Green, Purple, Red, White, Yellow};
 use ansi_term::{Color, Style};
 use regex::Regex;
 
+use crate::coverage;
+use crate::diagnostics::{self, Diagnostic};
+use crate::gas;
+use crate::mutation::{self, Mutant, MutantPool, MutantTask, Outcome};
+use crate::protocol::{
+    cancel_key, grader_result_key, job_events_channel, job_record_key, request_key, response_key,
+    JOB_RECORD_TTL_SECS, RESPONSE_TTL_SECS,
+};
+use crate::question::{self, CoverageTargets, MutationSettings, Question, QuestionBank};
+use crate::queue::memory::MemoryQueue;
+use crate::queue::result::RedisResultSink;
+use crate::queue::{self, list, Delivery, JobQueue, QueueBackend, ResultSink};
+use crate::rubric;
+use crate::sandbox::{self, SandboxConfig};
+use crate::scan;
+use crate::solidity;
+use crate::tiers;
+
 /* WORKER_TERMINAL_COLORS
 
    @dev Colors used to distinguish different threads
@@ -49,6 +72,91 @@ const WORKER_TERMINAL_COLORS: [Color; 20] = [
     Color::RGB(91, 174, 35),
 ];
 
+/* HEARTBEAT_TTL_SECS
+
+   @dev A server is considered dead once its heartbeat key expires, the jobs left in
+        its processing lists are then requeued by the next server that starts up
+
+*/
+const HEARTBEAT_TTL_SECS: usize = 30;
+const HEARTBEAT_INTERVAL_SECS: u64 = 10;
+
+// Limits of a single submission, checked before anything is written
+const MAX_SUBMITTED_FILES: usize = 64;
+const MAX_SUBMISSION_BYTES: usize = 1024 * 1024;
+const SUBMISSION_DIRS: [&str; 2] = ["contracts", "test"];
+
+// How often a running forge command checks whether its job got cancelled
+const CANCEL_CHECK_INTERVAL_MILLIS: u64 = 200;
+
+// How often a worker renews its claim on the stream entry it is judging
+pub const CLAIM_RENEW_SECS: u64 = 30;
+
+/* RetryPolicy
+
+   @dev How often a job that failed for infrastructure reasons is retried before it
+        is moved into the dead-letter list, the delay doubles with every attempt
+
+*/
+#[derive(Debug, Clone, Default)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub backoff_secs: u64,
+}
+
+impl RetryPolicy {
+    fn backoff(&self, attempts: u32) -> u64 {
+        self.backoff_secs
+            .saturating_mul(1 << attempts.saturating_sub(1).min(16))
+    }
+}
+
+/* TimeLimits
+
+   @dev Wall-clock limits of `forge build` and `forge test`. A job may ask for other
+        limits through `buildTimeLimit` and `testTimeLimit`, never above `max_secs`.
+        The CPU time of every forge process is capped as well, at the wall-clock limit
+        times the number of cores
+
+*/
+#[derive(Debug, Clone)]
+pub struct TimeLimits {
+    pub build_secs: u64,
+    pub test_secs: u64,
+    pub max_secs: u64,
+}
+
+impl Default for TimeLimits {
+    fn default() -> Self {
+        TimeLimits {
+            build_secs: 60,
+            test_secs: 120,
+            max_secs: 600,
+        }
+    }
+}
+
+// How the workers of a server treat their jobs, shared by all of them
+#[derive(Debug, Clone, Default)]
+pub struct WorkerSettings {
+    pub retry_policy: RetryPolicy,
+    pub time_limits: TimeLimits,
+    pub sandbox: SandboxConfig,
+    pub question_bank: Option<QuestionBank>,
+}
+
+impl TimeLimits {
+    fn build_limit(&self, job: &JobMessage) -> Duration {
+        let secs = job.build_time_limit.unwrap_or(self.build_secs);
+        Duration::from_secs(secs.min(self.max_secs))
+    }
+
+    fn test_limit(&self, job: &JobMessage) -> Duration {
+        let secs = job.test_time_limit.unwrap_or(self.test_secs);
+        Duration::from_secs(secs.min(self.max_secs))
+    }
+}
+
 macro_rules! color_log {
   ($color:expr, info, $($msg:tt)*) => {
     info!("{}", $color.paint(format!($($msg)*)));
@@ -75,49 +183,64 @@ pub enum ErrorCode {
     ResultJsonReadFailure(String),
     UpdateCacheOutPathNotExist(),
     EmptyFile,
+    WorkDirWriteFailure(String),
+    Cancelled,
+    TimeLimitExceeded(String),
+    ForgeCrashed(String),
+    SandboxFailure(String),
+    InvalidSubmission(String),
+    InvalidTests(String),
 }
 
 impl ErrorCode {
-    fn get_err_msg(&self) -> String {
+    pub fn get_err_msg(&self) -> String {
         match self {
             ErrorCode::RedisConnectErr(err) => {
-                return String::from(format!("Redis connect exception: {}", err));
-            }
-            ErrorCode::DataError(err) => {
-                return String::from(format!("Data err {}", err));
-            }
-            ErrorCode::DataNotJson(err) => {
-                return String::from(format!("Data not json {}", err));
+                String::from(format!("Redis connect exception: {}", err))
             }
+            ErrorCode::DataError(err) => String::from(format!("Data err {}", err)),
+            ErrorCode::DataNotJson(err) => String::from(format!("Data not json {}", err)),
             ErrorCode::DataNotString(data) => {
-                return String::from(format!("Data not string type, {}", data));
-            }
-            ErrorCode::NoNewMessage => {
-                return String::from("No new message.");
-            }
-            ErrorCode::ReadDirErr(data) => {
-                return String::from(format!("Data not string type, {}", data));
-            }
-            ErrorCode::ForgeBuildFailure(data) => {
-                return String::from(format!("{}", data));
+                String::from(format!("Data not string type, {}", data))
             }
-            ErrorCode::ForgeTestFailure(data) => {
-                return String::from(format!("{}", data));
+            ErrorCode::NoNewMessage => String::from("No new message."),
+            ErrorCode::ReadDirErr(data) => String::from(format!("Data not string type, {}", data)),
+            ErrorCode::ForgeBuildFailure(data) => String::from(format!("{}", data)),
+            ErrorCode::ForgeTestFailure(data) => String::from(format!("{}", data)),
+            ErrorCode::ForgeCompileFailure(data) => String::from(format!("{}", data)),
+            ErrorCode::ResultJsonReadFailure(data) => String::from(format!("{}", data)),
+            ErrorCode::EmptyFile => String::from("Empty file"),
+            ErrorCode::WorkDirWriteFailure(data) => {
+                format!("Write work directory failed: {}", data)
             }
-            ErrorCode::ForgeCompileFailure(data) => {
-                return String::from(format!("{}", data));
+            ErrorCode::Cancelled => String::from("Cancelled"),
+            ErrorCode::TimeLimitExceeded(data) => {
+                format!("Time limit exceeded: {}", data)
             }
-            ErrorCode::ResultJsonReadFailure(data) => {
-                return String::from(format!("{}", data));
+            ErrorCode::ForgeCrashed(data) => {
+                format!("Forge crashed: {}", data)
             }
-            ErrorCode::EmptyFile => {
-                return String::from("Empty file");
-            }
-            _ => {
-                return String::new();
+            ErrorCode::SandboxFailure(data) => data.to_string(),
+            ErrorCode::InvalidSubmission(data) => {
+                format!("Invalid submission: {}", data)
             }
+            ErrorCode::InvalidTests(data) => data.to_string(),
+            _ => String::new(),
         }
     }
+
+    // Infrastructure failures are worth another attempt, anything caused by the
+    // submission itself (compile errors, bad json) would fail the same way again
+    fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            ErrorCode::RedisConnectErr(_)
+                | ErrorCode::ForgeBuildFailure(_)
+                | ErrorCode::ForgeTestFailure(_)
+                | ErrorCode::WorkDirWriteFailure(_)
+                | ErrorCode::SandboxFailure(_)
+        )
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -136,7 +259,7 @@ struct PathWithContent {
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-struct JobMessage {
+pub(crate) struct JobMessage {
     #[serde(rename = "questionNo")]
     question_no: String,
 
@@ -144,13 +267,24 @@ struct JobMessage {
     solc_version: String,
 
     #[serde(rename = "judgeJobId")]
-    judge_job_id: String,
+    pub(crate) judge_job_id: String,
 
-    #[serde(rename = "jobKey")]
-    job_key: String,
+    // Unused since the request and response keys derive from `judgeJobId`
+    #[serde(rename = "jobKey", default)]
+    pub(crate) job_key: String,
 
     #[serde(rename = "pathWithContent")]
     path_with_content: Vec<PathWithContent>,
+
+    #[serde(rename = "attempts", default)]
+    attempts: u32,
+
+    // Secs, the server limits apply when omitted
+    #[serde(rename = "buildTimeLimit", default)]
+    build_time_limit: Option<u64>,
+
+    #[serde(rename = "testTimeLimit", default)]
+    test_time_limit: Option<u64>,
 }
 
 impl MyError {
@@ -174,6 +308,94 @@ fn get_value_str(v: Value) -> Result<String, ErrorCode> {
     }
 }
 
+/* submitted_path
+
+   @dev Normalizes a path of `pathWithContent`: relative, inside `contracts/` or
+        `test/`, no `.` or `..` segments and nothing but plain file names
+
+*/
+fn submitted_path(path: &str) -> Result<PathBuf, String> {
+    if path.contains('\\') || path.contains('\0') {
+        return Err(format!("{}: unexpected character", path));
+    }
+    let mut normalized = PathBuf::new();
+    for component in Path::new(path).components() {
+        match component {
+            Component::Normal(name) => normalized.push(name),
+            _ => {
+                return Err(format!(
+                    "{}: only relative paths without . or .. are allowed",
+                    path
+                ))
+            }
+        }
+    }
+
+    let mut components = normalized.components();
+    let top_dir = components.next().and_then(|dir| dir.as_os_str().to_str());
+    if !top_dir.is_some_and(|dir| SUBMISSION_DIRS.contains(&dir)) || components.next().is_none() {
+        return Err(format!(
+            "{}: files go into {}",
+            path,
+            SUBMISSION_DIRS.join("/ or ") + "/"
+        ));
+    }
+    Ok(normalized)
+}
+
+/* validate_submission
+
+   @dev Checks a job before a single file of it is written: the question number is a
+        plain directory name, every path passes `submitted_path` and the submission stays
+        within the file count and size limits
+
+*/
+pub(crate) fn validate_submission(job: &JobMessage) -> Result<(), ErrorCode> {
+    let mut question_dir = Path::new(&job.question_no).components();
+    if !matches!(question_dir.next(), Some(Component::Normal(_))) || question_dir.next().is_some() {
+        return Err(ErrorCode::InvalidSubmission(format!(
+            "{}: not a question number",
+            job.question_no
+        )));
+    }
+
+    if job.path_with_content.len() > MAX_SUBMITTED_FILES {
+        return Err(ErrorCode::InvalidSubmission(format!(
+            "{} files, at most {} are allowed",
+            job.path_with_content.len(),
+            MAX_SUBMITTED_FILES
+        )));
+    }
+    let total_bytes: usize = job.path_with_content.iter().map(|f| f.content.len()).sum();
+    if total_bytes > MAX_SUBMISSION_BYTES {
+        return Err(ErrorCode::InvalidSubmission(format!(
+            "{} bytes, at most {} are allowed",
+            total_bytes, MAX_SUBMISSION_BYTES
+        )));
+    }
+
+    let mut seen = vec![];
+    for file in &job.path_with_content {
+        let path = submitted_path(&file.path).map_err(ErrorCode::InvalidSubmission)?;
+        if seen.contains(&path) {
+            return Err(ErrorCode::InvalidSubmission(format!(
+                "{}: submitted twice",
+                file.path
+            )));
+        }
+        seen.push(path);
+    }
+    Ok(())
+}
+
+// Paths of the files the student submitted, the bank adds the official tests
+fn submitted_paths(job: &JobMessage) -> Vec<String> {
+    job.path_with_content
+        .iter()
+        .map(|file| file.path.clone())
+        .collect()
+}
+
 fn create_files_as_job_message(
     job: &JobMessage,
     worker_num: i8,
@@ -193,282 +415,920 @@ fn create_files_as_job_message(
         // let _ = fs::write(output_path.join("output.json"), json.to_string());
         return Err(ErrorCode::EmptyFile);
     }
+    validate_submission(job)?;
     for j in &job.path_with_content {
         let path = Path::new(&worker_dir)
             .join(format!("{:02}", worker_num))
             .join(&job.question_no)
-            .join(&j.path);
+            .join(submitted_path(&j.path).map_err(ErrorCode::InvalidSubmission)?);
         // if !path.is_file() {
-        let rIndex = path.as_os_str().to_str().unwrap().rfind('/').unwrap();
-        let path_sub_str = &path.as_os_str().to_str().unwrap()[0..rIndex];
-        if Path::new(path_sub_str).exists() {
-        } else {
-            fs::create_dir_all(path_sub_str).unwrap();
+        let path_sub = path.parent().unwrap_or(&base_path);
+        if path_sub.exists() {
+        } else if let Err(err) = fs::create_dir_all(path_sub) {
+            return Err(ErrorCode::WorkDirWriteFailure(err.to_string()));
+        }
+        if let Err(err) = fs::write(path.clone(), &j.content) {
+            return Err(ErrorCode::WorkDirWriteFailure(err.to_string()));
         }
-        fs::write(path.clone(), &j.content).unwrap();
         // }
         // info!("{}", color.paint(format!("{}", path.as_os_str().to_str().unwrap())));
     }
     Ok(())
 }
 
-fn get_job_message_by_redis_value(rv: redis::Value) -> Result<JobMessage, ErrorCode> {
-    match rv {
-        Value::Bulk(data) => {
-            let list_name = data.get(0).unwrap();
-            let data_str = data.get(1).unwrap();
-
-            let data = get_value_str(data_str.clone());
-            if data.is_err() {
-                let err =
-                    ErrorCode::DataError(format!("Data err({})", data.unwrap_err().get_err_msg()));
-                return Err(err);
-            } else {
-            }
-
-            let data = data.unwrap();
-            let jobTransferRes: Result<JobMessage, serde_json::Error> =
-                serde_json::from_str(data.as_str());
-            if jobTransferRes.is_err() {
-                let err = ErrorCode::DataNotJson(format!(
-                    "Data err({})",
-                    jobTransferRes.unwrap_err().to_string()
-                ));
-                return Err(err);
-            } else {
-            }
-
-            let job = jobTransferRes.unwrap();
-            return Ok(job);
-        }
-        Value::Nil => {
-            return Err(ErrorCode::NoNewMessage);
-        }
-        _ => {
-            return Err(ErrorCode::DataError(format!("Data err(Unknown)")));
-        }
-    }
+pub(crate) fn get_job_message(data: &str) -> Result<JobMessage, ErrorCode> {
+    let job = serde_json::from_str(data)
+        .map_err(|err| ErrorCode::DataNotJson(format!("Data err({})", err)))?;
+    Ok(job)
 }
 
-fn worker_thread(
-    num: i8,
-    redis_host: &str,
-    redis_prefix: &str,
-    worker_dir: &str,
-    redis_list_name: &str,
-) -> Result<(), ErrorCode> {
-    let color = WORKER_TERMINAL_COLORS[num as usize];
+/* Worker
 
-    let redis_client_result = redis::Client::open(redis_host);
+   @dev Everything a worker thread needs to judge jobs, the queue and the result sink
+        decide where the jobs come from and where their results go
 
-    let base_path = Path::new(worker_dir).join(format!("{:02}", num).as_str());
+*/
+pub struct Worker {
+    pub num: i8,
+    pub worker_dir: String,
+    pub redis_prefix: String,
+    pub retry_policy: RetryPolicy,
+    pub time_limits: TimeLimits,
+    pub sandbox: SandboxConfig,
+    // None trusts the tests of the submission, as `judger local` does
+    pub question_bank: Option<QuestionBank>,
+    pub queue: Box<dyn JobQueue>,
+    pub sink: Box<dyn ResultSink>,
+    // Mutants of the jobs of the server, shared by its workers
+    pub mutants: MutantPool,
+    // The delivery being judged and when its claim was last renewed
+    pub claim: Option<(Delivery, Instant)>,
+}
 
-    if redis_client_result.is_err() {
-        let redis_err = redis_client_result.as_ref().unwrap_err();
-        let err = ErrorCode::RedisConnectErr(redis_err.to_string());
-        color_log!(color, error, "[Thread {}:] {}", num, err.get_err_msg());
-        return Err(err);
-    } else {
+impl Worker {
+    // `{worker_dir}/{num}`, the sandbox and the mutants of the worker live there
+    fn dir(&self) -> PathBuf {
+        Path::new(&self.worker_dir).join(format!("{:02}", self.num))
     }
 
-    let redis_client = redis_client_result.unwrap();
-
-    let conn_res = redis_client.get_connection();
-    if conn_res.is_err() {
-        let redis_err = conn_res.as_ref().err().unwrap();
-        let err = ErrorCode::RedisConnectErr(redis_err.to_string());
-        color_log!(color, error, "[Thread {}:] {}", num, err.get_err_msg());
-        return Err(err);
+    // The foundry project the worker judges the jobs of a question in
+    fn job_dir(&self, job: &JobMessage) -> PathBuf {
+        self.dir().join(&job.question_no)
     }
+}
 
-    let mut conn = conn_res.unwrap();
+fn worker_thread(worker: &mut Worker) -> Result<(), ErrorCode> {
+    let num = worker.num;
+    let color = WORKER_TERMINAL_COLORS[num as usize];
 
     loop {
         // sleep 20ms
         thread::sleep(Duration::from_millis(20));
-        // println!("{}:requests", redis_prefix);
-        let x: Result<Value, RedisError> = conn.blpop(format!("{}:requests", redis_prefix), 1);
-
-        if x.is_err() {
-            color_log!(
-                color,
-                trace,
-                "[Thread {}:] {}",
-                num,
-                x.unwrap_err().to_string()
-            );
+        if let Err(err) = worker.queue.requeue_due(Local::now().timestamp()) {
+            color_log!(color, trace, "[Thread {}:] {}", num, err.get_err_msg());
+        }
+        // Mutants of running jobs come before new jobs
+        if let Some(task) = worker.mutants.take() {
+            run_mutant(worker, task);
             continue;
-        } else {
         }
-
-        let job_res = get_job_message_by_redis_value(x.unwrap());
-        if job_res.is_err() {
-            let err = job_res.unwrap_err();
-            match err {
-                ErrorCode::NoNewMessage => {
-                    color_log!(color, trace, "[Thread {}:] {}", num, err.get_err_msg());
-                }
-                _ => {
-                    color_log!(color, error, "[Thread {}:] {}", num, err.get_err_msg());
+        let x = worker.queue.fetch();
+
+        let delivery = match x {
+            Ok(None) => {
+                color_log!(
+                    color,
+                    trace,
+                    "[Thread {}:] {}",
+                    num,
+                    ErrorCode::NoNewMessage.get_err_msg()
+                );
+                continue;
+            }
+            Ok(Some(delivery)) => delivery,
+            Err(err) if err.is_connection_dropped() => {
+                color_log!(color, error, "[Thread {}:] {}", num, err.get_err_msg());
+                worker.queue.reconnect();
+                // Whatever this worker held when the connection dropped gets another attempt
+                let held = worker.queue.held().unwrap_or_default();
+                for delivery in held {
+                    let err = ErrorCode::RedisConnectErr(err.get_err_msg());
+                    if let Err(err) = retry_or_dead_letter(worker, &delivery, &err) {
+                        color_log!(color, error, "[Thread {}:] {}", num, err.get_err_msg());
+                    }
                 }
+                continue;
             }
-            continue;
+            Err(err) => {
+                color_log!(color, trace, "[Thread {}:] {}", num, err.get_err_msg());
+                continue;
+            }
+        };
+
+        process_job(worker, delivery);
+    }
+}
+
+fn process_job(worker: &mut Worker, delivery: Delivery) {
+    worker.claim = Some((delivery.clone(), Instant::now()));
+    judge_delivery(worker, delivery);
+    worker.claim = None;
+}
+
+/* renew_claim
+
+   @dev A job runs for as long as its build, tests, gas, coverage and mutants take,
+        so rather than bounding that the worker keeps renewing its claim while it
+        judges, an entry only goes idle once its worker is gone
+
+*/
+fn renew_claim(worker: &mut Worker) {
+    let Some((delivery, renewed_at)) = worker.claim.as_mut() else {
+        return;
+    };
+    if renewed_at.elapsed() < Duration::from_secs(CLAIM_RENEW_SECS) {
+        return;
+    }
+    *renewed_at = Instant::now();
+    if let Err(err) = worker.queue.renew(delivery) {
+        log::warn!(
+            "[Thread {}:] Renewing claim failed {}",
+            worker.num,
+            err.get_err_msg()
+        );
+    }
+}
+
+fn judge_delivery(worker: &mut Worker, delivery: Delivery) {
+    let num = worker.num;
+    let color = WORKER_TERMINAL_COLORS[num as usize];
+    let worker_dir = worker.worker_dir.clone();
+    let redis_prefix = worker.redis_prefix.clone();
+
+    let mut job = match get_job_message(&delivery.raw) {
+        Ok(job) => job,
+        Err(err) => {
+            // Malformed jobs can never succeed, drop them instead of retrying forever
+            color_log!(color, error, "[Thread {}:] {}", num, err.get_err_msg());
+            let _ = worker.queue.ack(&delivery);
+            return;
         }
-        let job = job_res.unwrap();
-        let start_time = SystemTime::now();
-        color_log!(color, trace, "[Thread {}:] Received job: {:?}", num, &job);
+    };
+    let start_time = SystemTime::now();
+    let cancel_key = cancel_key(&redis_prefix, &job.judge_job_id);
+    if worker.sink.is_cancelled(&cancel_key).unwrap_or(false) {
         color_log!(
             color,
-            trace,
-            "[Thread {}:] Received job: {}, start creating files...",
+            info,
+            "[Thread {}:] Job {} was cancelled while queued, dropped",
             num,
-            job.question_no
+            &job.judge_job_id
         );
+        finish_cancelled(worker, &delivery, &job);
+        return;
+    }
+    record_job(worker, &job.judge_job_id, "running", None);
+    report(
+        worker,
+        &job.judge_job_id,
+        "received",
+        json!({ "questionNo": &job.question_no, "attempts": job.attempts }),
+    );
+    color_log!(color, trace, "[Thread {}:] Received job: {:?}", num, &job);
+    color_log!(
+        color,
+        trace,
+        "[Thread {}:] Received job: {}, start creating files...",
+        num,
+        job.question_no
+    );
 
-        let request_key = format!("{}:{}:{}", &redis_prefix, &job.job_key, "request");
-        let request_v: Result<redis::Value, RedisError> = conn.get(&request_key);
-        if request_v.is_err() {
-            let mut json = json!({});
-            json["jobId"] = json!(&job.judge_job_id);
-            json["code"] = json!(3);
-            json["msg"] = json!("Timeout");
-            let _: Result<redis::Value, redis::RedisError> = conn
-                .lpush::<String, String, redis::Value>(
-                    format!("{}:responses", redis_prefix),
-                    json.to_string(),
-                );
-            continue;
-        } else {
+    let request_key = request_key(&redis_prefix, &job.judge_job_id);
+    let response_key = response_key(&redis_prefix, &job.judge_job_id);
+    if worker.sink.is_expected(&request_key).is_err() {
+        let mut json = json!({});
+        json["jobId"] = json!(&job.judge_job_id);
+        json["code"] = json!(3);
+        json["msg"] = json!("Timeout");
+        let _ = worker.sink.publish(
+            &request_key,
+            &response_key,
+            &json.to_string(),
+            RESPONSE_TTL_SECS,
+        );
+        let _ = worker.queue.ack(&delivery);
+        return;
+    }
+
+    if let Err(err) = validate_submission(&job) {
+        color_log!(
+            color,
+            error,
+            "[Thread {}:] Job {} rejected: {}",
+            num,
+            &job.judge_job_id,
+            err.get_err_msg()
+        );
+        finish_rejected(worker, &delivery, &job, &err);
+        return;
+    }
+
+    // The tests of a question always come from the bank, never from the submission
+    let question = match worker
+        .question_bank
+        .as_ref()
+        .map(|bank| bank.find(&job.question_no))
+    {
+        Some(Ok(question)) => Some(question),
+        Some(Err(err)) => {
+            let err = ErrorCode::InvalidSubmission(err.get_err_msg());
+            color_log!(color, error, "[Thread {}:] {}", num, err.get_err_msg());
+            finish_rejected(worker, &delivery, &job, &err);
+            return;
+        }
+        None => None,
+    };
+    if let Some(question) = &question {
+        // Questions grading tests take nothing else, the contracts are the reference ones
+        let grades_tests = question.grades_tests();
+        job.path_with_content
+            .retain(|file| Path::new(&file.path).starts_with("test") == grades_tests);
+        let sources = job
+            .path_with_content
+            .iter()
+            .map(|file| file.content.as_str());
+        if let Err(err) = question.check_submission(&job.solc_version, sources) {
+            let err = ErrorCode::InvalidSubmission(err.get_err_msg());
+            color_log!(color, error, "[Thread {}:] {}", num, err.get_err_msg());
+            finish_rejected(worker, &delivery, &job, &err);
+            return;
         }
+    }
 
-        //  Create files as the path
-        let res = create_files_as_job_message(&job, num, worker_dir);
-        if let Err(ErrorCode::EmptyFile) = res {
+    // Nothing the submission wrote may reach outside the EVM or use what the question forbids
+    let forbidden = question.as_ref().map_or_else(
+        || {
+            scan::DEFAULT_FORBIDDEN
+                .iter()
+                .map(|rule| rule.to_string())
+                .collect()
+        },
+        |question| question.meta.forbidden(),
+    );
+    let files = job
+        .path_with_content
+        .iter()
+        .map(|file| (file.path.as_str(), file.content.as_str()));
+    match scan::find_violations(files, &forbidden) {
+        Ok(violations) if violations.is_empty() => {}
+        Ok(violations) => {
             color_log!(
                 color,
                 error,
-                "[Thread {}:] No files created, check {}",
+                "[Thread {}:] Job {} uses {} forbidden construct(s)",
                 num,
-                &job.question_no
+                &job.judge_job_id,
+                violations.len()
             );
+            finish_forbidden(worker, &delivery, &job, &violations);
+            return;
         }
+        Err(err) => {
+            let err = ErrorCode::InvalidSubmission(err.get_err_msg());
+            color_log!(color, error, "[Thread {}:] {}", num, err.get_err_msg());
+            finish_rejected(worker, &delivery, &job, &err);
+            return;
+        }
+    }
 
-        // Start forge build
-
+    //  Create files as the path
+    let workspace = worker.job_dir(&job);
+    if question.as_ref().is_some_and(Question::grades_tests) {
+        // The tests of an earlier job would count as the student's
+        let _ = fs::remove_dir_all(workspace.join("test"));
+    }
+    let mut res = create_files_as_job_message(&job, num, &worker_dir);
+    if let (Ok(()), Some(question)) = (&res, &question) {
+        res = question
+            .overlay(&workspace)
+            .map_err(|err| ErrorCode::WorkDirWriteFailure(err.get_err_msg()));
+    }
+    if let Err(ErrorCode::EmptyFile) = res {
         color_log!(
             color,
-            trace,
-            "[Thread {}:] Creating files complete: {}, start testing...",
+            error,
+            "[Thread {}:] No files created, check {}",
             num,
             &job.question_no
         );
-        let _ = collect_output_from_test_scripts(
-            Path::new("tmp/worker")
-                .join(format!("{:02}", num))
-                .join(&job.question_no)
-                .as_path(),
-        );
+    }
+    if let Err(err) = res.as_ref() {
+        if err.is_retryable() {
+            color_log!(color, error, "[Thread {}:] {}", num, err.get_err_msg());
+            if let Err(err) = retry_or_dead_letter(worker, &delivery, err) {
+                color_log!(color, error, "[Thread {}:] {}", num, err.get_err_msg());
+            }
+            return;
+        }
+    }
+    report(
+        worker,
+        &job.judge_job_id,
+        "files_written",
+        json!({ "files": job.path_with_content.len() }),
+    );
 
-        let forge_test_res = run_forge_test(&job, num);
+    // Start forge build
 
-        let output_path = Path::new("tmp/worker")
-            .join(format!("{:02}", num))
-            .join(&job.question_no)
-            .join("output")
-            .join("output.json");
-        if forge_test_res.is_err() {
-            match forge_test_res.err().unwrap() {
-                ErrorCode::ForgeBuildFailure(data) => {
-                    color_log!(
-                        color,
-                        error,
-                        "[Thread {}:] Forge bulild run failed, result saved in {}",
-                        num,
-                        output_path.to_str().unwrap()
-                    );
-                }
-                ErrorCode::ForgeTestFailure(data) => {
-                    color_log!(
-                        color,
-                        error,
-                        "[Thread {}:] Forge test run failed, result saved in {}",
-                        num,
-                        output_path.to_str().unwrap()
-                    );
-                }
-                ErrorCode::ForgeCompileFailure(data) => {
-                    color_log!(
-                        color,
-                        error,
-                        "[Thread {}:] Forge compile failed, result saved in {}",
-                        num,
-                        output_path.to_str().unwrap()
-                    );
-                }
-                ErrorCode::EmptyFile => {
-                    color_log!(
-                        color,
-                        error,
-                        "[Thread {}:] Forge test failed, result saved in {}",
-                        num,
-                        output_path.to_str().unwrap()
-                    );
-                }
-                _ => {}
+    color_log!(
+        color,
+        trace,
+        "[Thread {}:] Creating files complete: {}, start testing...",
+        num,
+        &job.question_no
+    );
+    let job_path = worker.job_dir(&job);
+    let collected = if question.as_ref().is_some_and(Question::grades_tests) {
+        // The student's tests carry no scores, their coverage is scored
+        start_output(&job_path)
+    } else {
+        collect_output_from_test_scripts(&job_path)
+    };
+    if let Err(err) = collected {
+        color_log!(color, error, "[Thread {}:] {}", num, err.get_err_msg());
+        let _ = clean_contracts_and_test_dir(worker, &job);
+        finish_invalid_tests(worker, &delivery, &job, &err);
+        return;
+    }
+    if let Some(question) = &question {
+        apply_question_scores(worker, question, &job);
+    }
+
+    let forge_test_res = run_forge_test(&job, worker, question.as_ref());
+    if let Err(ErrorCode::Cancelled) = forge_test_res {
+        color_log!(
+            color,
+            info,
+            "[Thread {}:] Job {} was cancelled, forge killed",
+            num,
+            &job.judge_job_id
+        );
+        let _ = clean_contracts_and_test_dir(worker, &job);
+        finish_cancelled(worker, &delivery, &job);
+        return;
+    }
+    if let Err(err) = forge_test_res.as_ref() {
+        if err.is_retryable() {
+            color_log!(color, error, "[Thread {}:] {}", num, err.get_err_msg());
+            if let Err(err) = retry_or_dead_letter(worker, &delivery, err) {
+                color_log!(color, error, "[Thread {}:] {}", num, err.get_err_msg());
             }
-        } else {
-            let cost_time = SystemTime::now().duration_since(start_time).unwrap();
-            color_log!(
-                color,
-                info,
-                "[Thread {}:] Test complete in {:.2}s, result saved in {}. Start cleanning...",
-                num,
-                cost_time.as_millis() as f64 / 1000.0,
-                output_path.to_str().unwrap()
-            );
+            return;
         }
+    }
 
-        let request_key = format!("{}:{}:{}", &redis_prefix, &job.job_key, "request");
-        color_log!(color, info, "[Thread {}:] {}", num, &request_key);
-
+    let output_path = worker.job_dir(&job).join("output").join("output.json");
+    if forge_test_res.is_err() {
+        match forge_test_res.err().unwrap() {
+            ErrorCode::ForgeBuildFailure(_) => {
+                color_log!(
+                    color,
+                    error,
+                    "[Thread {}:] Forge bulild run failed, result saved in {}",
+                    num,
+                    output_path.to_str().unwrap()
+                );
+            }
+            ErrorCode::ForgeTestFailure(_) => {
+                color_log!(
+                    color,
+                    error,
+                    "[Thread {}:] Forge test run failed, result saved in {}",
+                    num,
+                    output_path.to_str().unwrap()
+                );
+            }
+            ErrorCode::ForgeCompileFailure(_) => {
+                color_log!(
+                    color,
+                    error,
+                    "[Thread {}:] Forge compile failed, result saved in {}",
+                    num,
+                    output_path.to_str().unwrap()
+                );
+            }
+            ErrorCode::EmptyFile => {
+                color_log!(
+                    color,
+                    error,
+                    "[Thread {}:] Forge test failed, result saved in {}",
+                    num,
+                    output_path.to_str().unwrap()
+                );
+            }
+            ErrorCode::TimeLimitExceeded(data) | ErrorCode::ForgeCrashed(data) => {
+                color_log!(
+                    color,
+                    error,
+                    "[Thread {}:] {}, result saved in {}",
+                    num,
+                    data,
+                    output_path.to_str().unwrap()
+                );
+            }
+            _ => {}
+        }
+    } else {
+        let cost_time = SystemTime::now().duration_since(start_time).unwrap();
         color_log!(
             color,
             info,
-            "[Thread {}:] Output path {}",
+            "[Thread {}:] Test complete in {:.2}s, result saved in {}. Start cleanning...",
             num,
-            &output_path.as_os_str().to_str().unwrap()
+            cost_time.as_millis() as f64 / 1000.0,
+            output_path.to_str().unwrap()
         );
-        let response_key = format!("{}:{}", &redis_prefix, "responses");
-        // color_log!(color, error, "{}", output_path.as_os_str().to_str().unwrap());
+    }
 
-        let mut json = json!({});
-        json["jobId"] = json!(&job.judge_job_id);
-        json["code"] = json!(3);
-        json["msg"] = json!("Timeout");
+    color_log!(color, info, "[Thread {}:] {}", num, &request_key);
 
-        let _ = write_response_if_request_exist(
-            &request_key,
-            &response_key,
-            &fs::read_to_string(output_path).unwrap(),
-            &json.to_string(),
-            &mut conn,
+    color_log!(
+        color,
+        info,
+        "[Thread {}:] Output path {}",
+        num,
+        &output_path.as_os_str().to_str().unwrap()
+    );
+    // color_log!(color, error, "{}", output_path.as_os_str().to_str().unwrap());
+
+    // Students get the redacted result, graders look the full one up
+    let full_output = fs::read_to_string(output_path).unwrap();
+    let output = match serde_json::from_str::<serde_json::Value>(&full_output) {
+        Ok(full) => tiers::student_view(&full, &submitted_paths(&job)).to_string(),
+        Err(_) => full_output.clone(),
+    };
+    let grader_key = grader_result_key(&worker.redis_prefix, &job.judge_job_id);
+    if let Err(err) = worker
+        .sink
+        .record(&grader_key, &full_output, JOB_RECORD_TTL_SECS)
+    {
+        color_log!(color, error, "[Thread {}:] {}", num, err.get_err_msg());
+    }
+    if let Err(err) = worker
+        .sink
+        .publish(&request_key, &response_key, &output, RESPONSE_TTL_SECS)
+    {
+        let err = ErrorCode::RedisConnectErr(err.get_err_msg());
+        color_log!(color, error, "[Thread {}:] {}", num, err.get_err_msg());
+        worker.sink.reconnect();
+        worker.queue.reconnect();
+        if let Err(err) = retry_or_dead_letter(worker, &delivery, &err) {
+            color_log!(color, error, "[Thread {}:] {}", num, err.get_err_msg());
+        }
+        return;
+    }
+    record_job(worker, &job.judge_job_id, "done", Some(&output));
+    report(
+        worker,
+        &job.judge_job_id,
+        "done",
+        json!({ "result": serde_json::from_str::<serde_json::Value>(&output).unwrap_or(json!(output)) }),
+    );
+    if let Err(err) = worker.queue.ack(&delivery) {
+        color_log!(color, error, "[Thread {}:] {}", num, err.get_err_msg());
+    }
+    // let _ = clean_contracts_and_test_dir(worker, &job);
+
+    // update cache
+    let _ = commit_update_cache_task(
+        &job,
+        &worker_dir,
+        worker.job_dir(&job).as_path(),
+        job.solc_version.as_str(),
+    );
+}
+
+// Answers a cancelled job with code 5 and takes it off the worker
+fn finish_cancelled(worker: &mut Worker, delivery: &Delivery, job: &JobMessage) {
+    let mut response = json!({});
+    response["info"] = json!("Cancelled");
+    response["code"] = json!(5);
+    response["jobId"] = json!(&job.judge_job_id);
+    response["msg"] = json!("Cancelled");
+    finish_unjudged(worker, delivery, job, "cancelled", &response);
+}
+
+// Answers a job rejected before anything was written with code 7
+fn finish_rejected(worker: &mut Worker, delivery: &Delivery, job: &JobMessage, err: &ErrorCode) {
+    let mut response = json!({});
+    response["info"] = json!("Invalid submission");
+    response["code"] = json!(7);
+    response["jobId"] = json!(&job.judge_job_id);
+    response["msg"] = json!(err.get_err_msg());
+    finish_unjudged(worker, delivery, job, "done", &response);
+}
+
+// Answers a job using a forbidden cheatcode or construct with code 8, listing every use
+fn finish_forbidden(
+    worker: &mut Worker,
+    delivery: &Delivery,
+    job: &JobMessage,
+    violations: &[scan::Violation],
+) {
+    let msg = violations
+        .iter()
+        .map(|v| format!("{}:{}: {} is forbidden", v.path, v.line, v.rule))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let mut response = json!({});
+    response["info"] = json!("Forbidden construct");
+    response["code"] = json!(8);
+    response["jobId"] = json!(&job.judge_job_id);
+    response["msg"] = json!(msg);
+    response["violations"] = json!(violations);
+    finish_unjudged(worker, delivery, job, "done", &response);
+}
+
+// Answers a job whose tests cannot be collected, missing or wrongly annotated, with code 9
+fn finish_invalid_tests(
+    worker: &mut Worker,
+    delivery: &Delivery,
+    job: &JobMessage,
+    err: &ErrorCode,
+) {
+    let mut response = json!({});
+    response["info"] = json!("Invalid tests");
+    response["code"] = json!(9);
+    response["jobId"] = json!(&job.judge_job_id);
+    response["msg"] = json!(err.get_err_msg());
+    finish_unjudged(worker, delivery, job, "done", &response);
+}
+
+// Publishes `response` as the final result of a job forge never judged
+fn finish_unjudged(
+    worker: &mut Worker,
+    delivery: &Delivery,
+    job: &JobMessage,
+    status: &str,
+    response: &serde_json::Value,
+) {
+    let request_key = request_key(&worker.redis_prefix, &job.judge_job_id);
+    let response_key = response_key(&worker.redis_prefix, &job.judge_job_id);
+    let _ = worker.sink.publish(
+        &request_key,
+        &response_key,
+        &response.to_string(),
+        RESPONSE_TTL_SECS,
+    );
+    record_job(
+        worker,
+        &job.judge_job_id,
+        status,
+        Some(&response.to_string()),
+    );
+    report(
+        worker,
+        &job.judge_job_id,
+        status,
+        json!({ "result": response }),
+    );
+    if let Err(err) = worker.queue.ack(delivery) {
+        error!("{}", err.get_err_msg());
+    }
+}
+
+/* cancel_job
+
+   @dev Flags the job as cancelled: a queued job is dropped once a worker pops it, a
+        running one has its forge process killed and its workspace cleaned
+
+*/
+pub fn cancel_job(
+    sink: &mut dyn ResultSink,
+    redis_prefix: &str,
+    judge_job_id: &str,
+) -> Result<(), queue::ErrorCode> {
+    sink.cancel(&cancel_key(redis_prefix, judge_job_id), JOB_RECORD_TTL_SECS)
+}
+
+fn worker_id(server_id: &str, num: i8) -> String {
+    format!("{}:{:02}", server_id, num)
+}
+
+/* job_record
+
+   @dev The status record of a job: `queued`, `running`, `retrying`, `done` or
+        `cancelled`, the response the client received is attached once the job is
+        done or cancelled
+
+*/
+pub fn job_record(judge_job_id: &str, status: &str, result: Option<&str>) -> String {
+    let mut record = json!({});
+    record["jobId"] = json!(judge_job_id);
+    record["status"] = json!(status);
+    record["result"] = match result {
+        Some(result) => serde_json::from_str(result).unwrap_or_else(|_| json!(result)),
+        None => serde_json::Value::Null,
+    };
+    record["updatedAt"] = json!(Local::now().to_rfc3339());
+    record.to_string()
+}
+
+/* report
+
+   @dev Publishes a progress event of the job on `{prefix}:events:{judgeJobId}`. The
+        phases are received, files_written, build_started, build_finished, test_started,
+        test (one per test), scored, retrying, cancelled and done. Progress is best effort, a
+        failed publish never fails the job
+
+*/
+fn report(worker: &mut Worker, judge_job_id: &str, phase: &str, mut event: serde_json::Value) {
+    event["jobId"] = json!(judge_job_id);
+    event["phase"] = json!(phase);
+    event["at"] = json!(Local::now().to_rfc3339());
+
+    let channel = job_events_channel(&worker.redis_prefix, judge_job_id);
+    if let Err(err) = worker.sink.notify(&channel, &event.to_string()) {
+        trace!(
+            "Report {} of job {} failed: {}",
+            phase,
+            judge_job_id,
+            err.get_err_msg()
         );
-        // let _ = clean_contracts_and_test_dir(&job, num);
-
-        // update cache
-        let _ = commit_update_cache_task(
-            &job,
-            worker_dir,
-            Path::new("tmp/worker")
-                .join(format!("{:02}", num))
-                .join(&job.question_no)
-                .as_path(),
-            job.solc_version.as_str(),
+    }
+}
+
+/* follow_job
+
+   @dev Hands `on_event` the current status record of the job as a `status` event,
+        then every progress event until the job is done, `on_event` returns false or,
+        between events, `is_wanted` does. Returns false when there is no record of the
+        job, nothing is followed then
+
+*/
+pub fn follow_job(
+    redis_host: &str,
+    redis_prefix: &str,
+    judge_job_id: &str,
+    mut on_event: impl FnMut(&str) -> bool,
+    is_wanted: impl FnMut() -> bool,
+) -> Result<bool, queue::ErrorCode> {
+    let record_key = job_record_key(redis_prefix, judge_job_id);
+    let channel = job_events_channel(redis_prefix, judge_job_id);
+    let mut found = true;
+    let mut lookup_err = None;
+
+    let on_message = |sink: &mut RedisResultSink, message: Option<&str>| {
+        let event = match message {
+            Some(message) => message.to_string(),
+            None => match sink.lookup(&record_key) {
+                Ok(Some(record)) => {
+                    let mut event: serde_json::Value =
+                        serde_json::from_str(&record).unwrap_or(json!({}));
+                    event["phase"] = json!("status");
+                    event.to_string()
+                }
+                Ok(None) => {
+                    found = false;
+                    return false;
+                }
+                Err(err) => {
+                    lookup_err = Some(err);
+                    return false;
+                }
+            },
+        };
+        if !on_event(&event) {
+            return false;
+        }
+        let event: serde_json::Value = serde_json::from_str(&event).unwrap_or_default();
+        let phase = event["phase"].as_str().unwrap_or("");
+        let status = event["status"].as_str().unwrap_or("");
+        !is_final_status(phase) && !is_final_status(status)
+    };
+    RedisResultSink::new(redis_host)?.follow(&channel, on_message, is_wanted)?;
+    match lookup_err {
+        Some(err) => Err(err),
+        None => Ok(found),
+    }
+}
+
+// No update follows a final status
+pub fn is_final_status(status: &str) -> bool {
+    status == "done" || status == "cancelled"
+}
+
+fn record_job(worker: &mut Worker, judge_job_id: &str, status: &str, result: Option<&str>) {
+    let record_key = job_record_key(&worker.redis_prefix, judge_job_id);
+    let record = job_record(judge_job_id, status, result);
+    if let Err(err) = worker
+        .sink
+        .record(&record_key, &record, JOB_RECORD_TTL_SECS)
+    {
+        error!("Record job {} failed: {}", judge_job_id, err.get_err_msg());
+    }
+}
+
+/* retry_or_dead_letter
+
+   @dev Takes a job that failed for infrastructure reasons off the worker and either
+        schedules it for another attempt after the backoff, or moves it into the
+        dead-letter list with the last error and answers the client
+
+*/
+fn retry_or_dead_letter(
+    worker: &mut Worker,
+    delivery: &Delivery,
+    err: &ErrorCode,
+) -> Result<(), queue::ErrorCode> {
+    let mut job: JobMessage = match serde_json::from_str(&delivery.raw) {
+        Ok(job) => job,
+        Err(_) => return worker.queue.ack(delivery),
+    };
+    job.attempts += 1;
+
+    let retry_policy = &worker.retry_policy;
+    if job.attempts <= retry_policy.max_retries {
+        let due = Local::now().timestamp() + retry_policy.backoff(job.attempts) as i64;
+        worker
+            .queue
+            .retry_at(delivery, &json!(job).to_string(), due)?;
+        info!(
+            "Job {} scheduled for retry {}/{} at {}",
+            job.judge_job_id, job.attempts, retry_policy.max_retries, due
         );
+        record_job(worker, &job.judge_job_id, "retrying", None);
+        report(
+            worker,
+            &job.judge_job_id,
+            "retrying",
+            json!({ "attempts": job.attempts, "dueAt": due, "error": err.get_err_msg() }),
+        );
+        return Ok(());
     }
+
+    let mut dead = json!({});
+    dead["job"] = json!(job);
+    dead["error"] = json!(err.get_err_msg());
+    dead["attempts"] = json!(job.attempts);
+    dead["failedAt"] = json!(Local::now().to_rfc3339());
+
+    let mut response = json!({});
+    response["info"] = json!("Judge failed");
+    response["code"] = json!(4);
+    response["jobId"] = json!(&job.judge_job_id);
+    response["msg"] = json!(err.get_err_msg());
+
+    worker.queue.dead_letter(delivery, &dead.to_string())?;
+    error!(
+        "Job {} dead-lettered after {} attempts: {}",
+        job.judge_job_id,
+        job.attempts,
+        err.get_err_msg()
+    );
+
+    let request_key = request_key(&worker.redis_prefix, &job.judge_job_id);
+    let response_key = response_key(&worker.redis_prefix, &job.judge_job_id);
+    let _ = worker.sink.publish(
+        &request_key,
+        &response_key,
+        &response.to_string(),
+        RESPONSE_TTL_SECS,
+    );
+    record_job(
+        worker,
+        &job.judge_job_id,
+        "done",
+        Some(&response.to_string()),
+    );
+    report(
+        worker,
+        &job.judge_job_id,
+        "done",
+        json!({ "result": response }),
+    );
     Ok(())
 }
 
+/* replay_dead_letters
+
+   @dev Moves dead-lettered jobs back onto the request queue with a fresh retry budget,
+        only the given job when `job_id` is set
+
+*/
+pub fn replay_dead_letters(
+    redis_host: &str,
+    redis_prefix: &str,
+    backend: &QueueBackend,
+    job_id: Option<&str>,
+) -> Result<usize, queue::ErrorCode> {
+    let mut queue = backend.open(redis_host, redis_prefix, "dead-letter")?;
+
+    let mut replayed = 0;
+    for entry in queue.dead_letters()? {
+        let dead: serde_json::Value = match serde_json::from_str(&entry) {
+            Ok(dead) => dead,
+            Err(_) => continue,
+        };
+        if job_id.is_some() && dead["job"]["judgeJobId"].as_str() != job_id {
+            continue;
+        }
+
+        let mut job = dead["job"].clone();
+        job["attempts"] = json!(0);
+        queue.replay(&entry, &job.to_string())?;
+        replayed += 1;
+    }
+    Ok(replayed)
+}
+
+pub fn list_dead_letters(
+    redis_host: &str,
+    redis_prefix: &str,
+    backend: &QueueBackend,
+) -> Result<Vec<String>, queue::ErrorCode> {
+    backend
+        .open(redis_host, redis_prefix, "dead-letter")?
+        .dead_letters()
+}
+
+fn heartbeat_thread(redis_host: &str, redis_prefix: &str, server_id: &str) {
+    let key = list::heartbeat_key(redis_prefix, server_id);
+    loop {
+        let res = redis::Client::open(redis_host)
+            .and_then(|client| client.get_connection())
+            .and_then(|mut conn| {
+                conn.set_ex::<&str, i64, ()>(&key, Local::now().timestamp(), HEARTBEAT_TTL_SECS)
+            });
+        if let Err(err) = res {
+            error!("Heartbeat failed: {}", err);
+        }
+        thread::sleep(Duration::from_secs(HEARTBEAT_INTERVAL_SECS));
+    }
+}
+
+/* judge_locally
+
+   @dev Runs a single job in this process through the in-memory queue, no Redis needed.
+        Without a question bank the tests of the job are trusted
+
+*/
+pub fn judge_locally(
+    raw_job: &str,
+    worker_dir: &str,
+    question_bank: Option<QuestionBank>,
+) -> Result<String, ErrorCode> {
+    let job = get_job_message(raw_job)?;
+    let queue = MemoryQueue::new();
+    let mut sink = queue.result_sink();
+    let mut worker = Worker {
+        num: 0,
+        worker_dir: worker_dir.to_string(),
+        redis_prefix: String::from("local"),
+        retry_policy: RetryPolicy {
+            max_retries: 0,
+            backoff_secs: 0,
+        },
+        time_limits: TimeLimits::default(),
+        sandbox: SandboxConfig::default(),
+        question_bank,
+        queue: Box::new(queue.for_worker(&worker_id("local", 0))),
+        sink: Box::new(queue.result_sink()),
+        mutants: MutantPool::default(),
+        claim: None,
+    };
+
+    let request_key = request_key(&worker.redis_prefix, &job.judge_job_id);
+    let response_key = response_key(&worker.redis_prefix, &job.judge_job_id);
+    let to_err = |err: queue::ErrorCode| ErrorCode::DataError(err.get_err_msg());
+
+    sink.expect(&request_key, RESPONSE_TTL_SECS)
+        .map_err(to_err)?;
+    worker.queue.submit(raw_job).map_err(to_err)?;
+    if let Some(delivery) = worker.queue.fetch().map_err(to_err)? {
+        process_job(&mut worker, delivery);
+    }
+    let response = sink
+        .take(&request_key, &response_key)
+        .map_err(to_err)?
+        .ok_or(ErrorCode::NoNewMessage)?;
+    // Judging locally is for the authors, they get hidden tests in full
+    let grader_key = grader_result_key(&worker.redis_prefix, &job.judge_job_id);
+    Ok(sink
+        .lookup(&grader_key)
+        .map_err(to_err)?
+        .unwrap_or(response))
+}
+
+pub fn default_server_id() -> String {
+    env::var("HOSTNAME")
+        .ok()
+        .or_else(|| fs::read_to_string("/etc/hostname").ok())
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .unwrap_or_else(|| String::from("judger"))
+}
+
 fn commit_update_cache_task(
     job: &JobMessage,
     worker_dir: &str,
@@ -499,40 +1359,8 @@ fn commit_update_cache_task(
     Ok(())
 }
 
-fn write_response_if_request_exist(
-    request_key: &str,
-    response_key: &str,
-    response: &str,
-    timeout_response: &str,
-    conn: &mut redis::Connection,
-) -> Result<(), ErrorCode> {
-    let script = Script::new(
-        "
-    if redis.call('exists', KEYS[1]) == 1 then
-        redis.call('del', KEYS[1])
-        redis.call('lpush', KEYS[2], ARGV[1])
-        return 1
-    else
-        redis.call('lpush', KEYS[2], ARGV[2])
-        return 0
-    end
-  ",
-    );
-
-    let res = script
-        .key(request_key)
-        .key(response_key)
-        .arg(response)
-        .arg(timeout_response)
-        .invoke::<i32>(conn);
-    if res.is_err() {
-        println!("{}", res.unwrap_err());
-    }
-    Ok(())
-}
-
-fn clean_contracts_and_test_dir(job: &JobMessage, worker_num: i8) -> Result<(), ErrorCode> {
-    let base_path = Path::new("tmp/worker").join(format!("{:02}", worker_num));
+fn clean_contracts_and_test_dir(worker: &Worker, job: &JobMessage) -> Result<(), ErrorCode> {
+    let base_path = worker.job_dir(job);
 
     let _ = fs::remove_dir_all(base_path.join("contracts"));
     let _ = fs::remove_dir_all(base_path.join("test"));
@@ -540,10 +1368,153 @@ fn clean_contracts_and_test_dir(job: &JobMessage, worker_num: i8) -> Result<(),
     Ok(())
 }
 
-fn run_forge_test(job: &JobMessage, worker_num: i8) -> Result<String, ErrorCode> {
-    let base_path = Path::new("tmp/worker")
-        .join(format!("{:02}", worker_num))
-        .join(&job.question_no);
+enum ForgeRunError {
+    Spawn(io::Error),
+    Cancelled,
+    TimeLimit,
+    // Killed by the signal, not by the worker nor the CPU limit
+    Crashed(i32),
+}
+
+fn read_in_background<R: Read + Send + 'static>(pipe: Option<R>) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut buf);
+        }
+        buf
+    })
+}
+
+// forge spawns solc and its own workers, they all go down with it
+fn kill_process_group(child: &mut Child) {
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+    let _ = child.wait();
+}
+
+/* run_forge
+
+   @dev Collects the output of a forge command like `Command::output`, but kills the
+        process group of the child as soon as the job gets cancelled or `limit` elapsed.
+        The pipes are drained in the background so a chatty forge never blocks on a full
+        pipe. Only the deadline and SIGXCPU are a time limit, forge dying of any other
+        signal crashed
+
+*/
+fn run_forge(
+    worker: &mut Worker,
+    judge_job_id: &str,
+    command: &mut Command,
+    limit: Duration,
+) -> Result<Output, ForgeRunError> {
+    let cores = thread::available_parallelism().map_or(1, |n| n.get()) as u64;
+    let cpu_secs = limit.as_secs().max(1).saturating_mul(cores) as libc::rlim_t;
+    command.process_group(0);
+    unsafe {
+        command.pre_exec(move || {
+            // SIGXCPU at the soft limit, whatever ignores it is killed at the deadline
+            let mut rlimit = libc::rlimit {
+                rlim_cur: 0,
+                rlim_max: 0,
+            };
+            if libc::getrlimit(libc::RLIMIT_CPU, &mut rlimit) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            rlimit.rlim_cur = cpu_secs.min(rlimit.rlim_max);
+            if libc::setrlimit(libc::RLIMIT_CPU, &rlimit) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(ForgeRunError::Spawn)?;
+    let stdout = read_in_background(child.stdout.take());
+    let stderr = read_in_background(child.stderr.take());
+
+    let start_time = SystemTime::now();
+    let cancel_key = cancel_key(&worker.redis_prefix, judge_job_id);
+    let status = loop {
+        if let Some(status) = child.try_wait().map_err(ForgeRunError::Spawn)? {
+            break status;
+        }
+        if worker.sink.is_cancelled(&cancel_key).unwrap_or(false) {
+            kill_process_group(&mut child);
+            return Err(ForgeRunError::Cancelled);
+        }
+        if start_time.elapsed().unwrap_or_default() >= limit {
+            kill_process_group(&mut child);
+            return Err(ForgeRunError::TimeLimit);
+        }
+        renew_claim(worker);
+        thread::sleep(Duration::from_millis(CANCEL_CHECK_INTERVAL_MILLIS));
+    };
+    match status.signal() {
+        Some(libc::SIGXCPU) => return Err(ForgeRunError::TimeLimit),
+        Some(signal) => return Err(ForgeRunError::Crashed(signal)),
+        None => {}
+    }
+
+    Ok(Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
+
+// A forge command for the job in `base_path`, confined when the sandbox is enabled
+fn forge_command(worker: &Worker, base_path: &Path) -> Result<Command, ErrorCode> {
+    let mut command = Command::new("forge");
+    if worker.sandbox.enabled {
+        let root = worker.dir().join(".sandbox");
+        sandbox::confine(&mut command, &worker.sandbox, &root, base_path)
+            .map_err(|err| ErrorCode::SandboxFailure(err.get_err_msg()))?;
+    }
+    Ok(command)
+}
+
+// Answers a job whose forge run got killed with code 6
+fn time_limit_exceeded(
+    job: &JobMessage,
+    output_path: &Path,
+    step: &str,
+    limit: Duration,
+) -> ErrorCode {
+    let msg = format!("forge {} exceeded {}s", step, limit.as_secs());
+    let mut json = json!({});
+    json["info"] = json!("Time limit exceeded");
+    json["code"] = json!(6);
+    json["jobId"] = json!(&job.judge_job_id);
+    json["msg"] = json!(&msg);
+    let _ = fs::write(output_path.join("output.json"), json.to_string());
+    ErrorCode::TimeLimitExceeded(msg)
+}
+
+// Answers a job whose forge run died of a signal with code 10
+fn forge_crashed(job: &JobMessage, output_path: &Path, step: &str, signal: i32) -> ErrorCode {
+    let msg = format!("forge {} was killed by signal {}", step, signal);
+    let mut json = json!({});
+    json["info"] = json!("Forge crashed");
+    json["code"] = json!(10);
+    json["jobId"] = json!(&job.judge_job_id);
+    json["msg"] = json!(&msg);
+    let _ = fs::write(output_path.join("output.json"), json.to_string());
+    ErrorCode::ForgeCrashed(msg)
+}
+
+fn run_forge_test(
+    job: &JobMessage,
+    worker: &mut Worker,
+    question: Option<&Question>,
+) -> Result<String, ErrorCode> {
+    let worker_num = worker.num;
+    let base_path = worker.job_dir(job);
     let cache_path = base_path.join("cache");
 
     let contracts_path = base_path.join("contracts");
@@ -649,61 +1620,139 @@ fn run_forge_test(job: &JobMessage, worker_num: i8) -> Result<String, ErrorCode>
         &job.solc_version,
     );
 
-    let res = Command::new("forge")
-        .args([
-            "build",
-            "--contracts",
-            base_path.as_os_str().to_str().unwrap(),
-            "--cache-path",
-            cache_path.as_os_str().to_str().unwrap(),
-            "--out",
-            out_path.as_os_str().to_str().unwrap(),
-            "--use",
-            &job.solc_version,
-        ])
-        .output();
-
-    if res.is_err() {
-        // error!("{}", res.as_ref().unwrap_err().to_string());
-        let mut json = json!({});
-        json["info"] = json!("Forge build failed");
-        json["code"] = json!(1);
-        json["jobId"] = json!(&job.judge_job_id);
-        json["msg"] = json!(res.as_ref().unwrap_err().to_string());
-        let _ = fs::write(output_path.join("output.json"), json.to_string());
-        return Err(ErrorCode::ForgeBuildFailure(res.unwrap_err().to_string()));
+    report(worker, &job.judge_job_id, "build_started", json!({}));
+    let build_limit = worker.time_limits.build_limit(job);
+    let mut command = forge_command(worker, &base_path)?;
+    command.args([
+        "build",
+        "--contracts",
+        base_path.as_os_str().to_str().unwrap(),
+        "--cache-path",
+        cache_path.as_os_str().to_str().unwrap(),
+        "--out",
+        out_path.as_os_str().to_str().unwrap(),
+        "--use",
+        &job.solc_version,
+        "--json",
+    ]);
+    let res = match run_forge(worker, &job.judge_job_id, &mut command, build_limit) {
+        Err(ForgeRunError::Cancelled) => return Err(ErrorCode::Cancelled),
+        Err(killed @ (ForgeRunError::TimeLimit | ForgeRunError::Crashed(_))) => {
+            let err = match killed {
+                ForgeRunError::Crashed(signal) => forge_crashed(job, &output_path, "build", signal),
+                _ => time_limit_exceeded(job, &output_path, "build", build_limit),
+            };
+            report(
+                worker,
+                &job.judge_job_id,
+                "build_finished",
+                json!({ "success": false, "diagnostics": [Diagnostic::from_output(&err.get_err_msg())] }),
+            );
+            return Err(err);
+        }
+        Err(ForgeRunError::Spawn(err)) => Err(err),
+        Ok(output) => Ok(output),
+    };
+
+    let build_output = match res {
+        Ok(output) => output,
+        Err(err) => {
+            let msg = err.to_string();
+            let mut json = json!({});
+            json["info"] = json!("Forge build failed");
+            json["code"] = json!(1);
+            json["jobId"] = json!(&job.judge_job_id);
+            json["msg"] = json!(&msg);
+            let _ = fs::write(output_path.join("output.json"), json.to_string());
+            report(
+                worker,
+                &job.judge_job_id,
+                "build_finished",
+                json!({ "success": false, "diagnostics": [Diagnostic::from_output(&msg)] }),
+            );
+            return Err(ErrorCode::ForgeBuildFailure(msg));
+        }
+    };
+    let stdout = String::from_utf8_lossy(&build_output.stdout);
+    let mut diagnostics = diagnostics::parse(&stdout, &base_path).unwrap_or_default();
+    let has_errors = diagnostics.iter().any(Diagnostic::is_error);
+    if !build_output.status.success() && !has_errors {
+        // forge failed before solc reported anything, keep what it printed
+        let re = Regex::new(r"\x1b\[[\d;]+m").unwrap();
+        let stderr = String::from_utf8_lossy(&build_output.stderr);
+        let printed = re.replace_all(&stderr, "").trim().to_string();
+        let printed = if printed.is_empty() {
+            stdout.trim().to_string()
+        } else {
+            printed
+        };
+        diagnostics.push(Diagnostic::from_output(&printed));
     }
-    let stderr = String::from_utf8(res.unwrap().stderr).unwrap();
-    if stderr.len() > 0 {
+    if !build_output.status.success() || has_errors {
         // Compile failure
-        let re = Regex::new(r"\x1b\[[\d;]+m").unwrap();
-        let result = re.replace_all(&stderr.as_str(), "").to_string();
+        let result = diagnostics
+            .iter()
+            .filter(|d| d.is_error())
+            .map(|d| d.formatted_message.trim_end())
+            .collect::<Vec<_>>()
+            .join("\n\n");
 
         let mut json = json!({});
         json["info"] = json!("Compile failed");
         json["code"] = json!(1);
         json["jobId"] = json!(&job.judge_job_id);
         json["msg"] = json!(result.as_str());
+        json["diagnostics"] = json!(&diagnostics);
         let _ = fs::write(output_path.join("output.json"), json.to_string());
+        report(
+            worker,
+            &job.judge_job_id,
+            "build_finished",
+            json!({ "success": false, "diagnostics": tiers::student_diagnostics(&json["diagnostics"], &submitted_paths(job)) }),
+        );
         return Err(ErrorCode::ForgeCompileFailure(result));
     }
+    report(
+        worker,
+        &job.judge_job_id,
+        "build_finished",
+        json!({ "success": true, "diagnostics": tiers::student_diagnostics(&json!(&diagnostics), &submitted_paths(job)) }),
+    );
+    if let Some(question) = question.filter(|question| question.grades_tests()) {
+        return grade_tests(job, worker, question, &diagnostics);
+    }
 
-    let res = Command::new("forge")
-        .args([
-            "test",
-            "--contracts",
-            base_path.as_os_str().to_str().unwrap(),
-            "--cache-path",
-            cache_path.as_os_str().to_str().unwrap(),
-            "--out",
-            out_path.as_os_str().to_str().unwrap(),
-            "--json",
-            "--use",
-            &job.solc_version,
-            "--offline",
-            "--allow-failure",
-        ])
-        .output();
+    report(worker, &job.judge_job_id, "test_started", json!({}));
+    let test_limit = worker.time_limits.test_limit(job);
+
+    let test_args = [
+        "test",
+        "--contracts",
+        base_path.as_os_str().to_str().unwrap(),
+        "--cache-path",
+        cache_path.as_os_str().to_str().unwrap(),
+        "--out",
+        out_path.as_os_str().to_str().unwrap(),
+        "--json",
+        "--use",
+        &job.solc_version,
+        "--offline",
+        "--allow-failure",
+    ];
+    let fuzz_env = question::fuzz_env(question);
+    let mut command = forge_command(worker, &base_path)?;
+    command.args(test_args).envs(fuzz_env.clone());
+    let res = match run_forge(worker, &job.judge_job_id, &mut command, test_limit) {
+        Err(ForgeRunError::Cancelled) => return Err(ErrorCode::Cancelled),
+        Err(ForgeRunError::TimeLimit) => {
+            return Err(time_limit_exceeded(job, &output_path, "test", test_limit))
+        }
+        Err(ForgeRunError::Crashed(signal)) => {
+            return Err(forge_crashed(job, &output_path, "test", signal))
+        }
+        Err(ForgeRunError::Spawn(err)) => Err(err),
+        Ok(output) => Ok(output),
+    };
 
     color_log!(
         WORKER_TERMINAL_COLORS[worker_num as usize],
@@ -763,104 +1812,586 @@ fn run_forge_test(job: &JobMessage, worker_num: i8) -> Result<String, ErrorCode>
     }
     let mut output = output_res.unwrap();
 
-    let mut total_score = 0;
-    let mut get_score = 0;
+    let cases = match_test_results(
+        output["questions"].as_array().unwrap(),
+        &test_out_json,
+        &base_path,
+    );
+    for (q, case) in output["questions"]
+        .as_array_mut()
+        .unwrap()
+        .iter_mut()
+        .zip(cases)
+    {
+        let Some(case) = case.filter(|case| case["status"].as_str().is_some()) else {
+            // forge did not run this question, it stays failed
+            continue;
+        };
+        q["Passed"] = json!(case["status"].as_str().unwrap() != "Failure");
+        add_test_details(q, case);
+    }
+
+    let (mut total_score, mut get_score, breakdown) =
+        rubric::apply(output["questions"].as_array_mut().unwrap());
+
+    // `--json` prints either the test results or the gas report, budgets need a second run
+    let budgets = question.map_or(&[][..], |question| &question.meta.gas[..]);
+    if !budgets.is_empty() {
+        let mut command = forge_command(worker, &base_path)?;
+        command
+            .args(test_args)
+            .arg("--gas-report")
+            .envs(fuzz_env.clone());
+        let report = match run_forge(worker, &job.judge_job_id, &mut command, test_limit) {
+            Err(ForgeRunError::Cancelled) => return Err(ErrorCode::Cancelled),
+            Err(ForgeRunError::TimeLimit) => {
+                return Err(time_limit_exceeded(job, &output_path, "test", test_limit))
+            }
+            Err(ForgeRunError::Crashed(signal)) => {
+                return Err(forge_crashed(job, &output_path, "test", signal))
+            }
+            Err(ForgeRunError::Spawn(err)) => {
+                return Err(ErrorCode::ForgeTestFailure(err.to_string()))
+            }
+            Ok(report) => gas::parse_report(&String::from_utf8_lossy(&report.stdout)),
+        };
+        let (gas_total, gas_earned, results) = gas::score_budgets(budgets, &report);
+        total_score += gas_total;
+        get_score += gas_earned;
+        output["gasBudgets"] = results;
+    }
 
-    for (k, v) in test_out_json.as_object().unwrap() {
-        let obj = v.as_object().unwrap();
-        let test_results = obj.get("test_results").unwrap();
-        for q in output["questions"].as_array_mut().unwrap() {
-            let case = &test_results[format!("{}{}", q["Func"].as_str().unwrap(), "()")];
+    // forge only reports the results once the whole run finished
+    for q in output["questions"].as_array().unwrap() {
+        // Only the tests forge ran carry its details, hidden ones are not told
+        if q.get("Reason").is_some() && tiers::is_public(q) {
+            report(
+                worker,
+                &job.judge_job_id,
+                "test",
+                json!({ "contract": format!("{}:{}", q["File"].as_str().unwrap_or(""), q["Contract"].as_str().unwrap_or("")), "func": &q["Func"], "passed": &q["Passed"], "score": &q["Score"], "earned": &q["Earned"], "reason": &q["Reason"], "gas": &q["Gas"] }),
+            );
+        }
+    }
+    output["total_score"] = rubric::score_json(total_score);
+    output["get_score"] = rubric::score_json(get_score);
+    output["rubric"] = breakdown;
+    let view = tiers::student_view(&output, &submitted_paths(job));
+    report(
+        worker,
+        &job.judge_job_id,
+        "scored",
+        json!({ "total_score": &view["total_score"], "get_score": &view["get_score"], "rubric": &view["rubric"], "hiddenTests": &view["hiddenTests"] }),
+    );
+    output["info"] = json!("Complete");
+    output["code"] = json!(0);
+    output["msg"] = json!("Complete");
+    output["jobId"] = json!(&job.judge_job_id);
+    output["diagnostics"] = json!(&diagnostics);
+    output["fuzzSeed"] =
+        json!(question.map_or(question::DEFAULT_FUZZ_SEED, |q| q.meta.fuzz.seed()));
 
-            // info!("{:#}", case);
+    let _ = fs::write(output_path.join("output.json"), output.to_string());
 
-            q["Score"] = json!(q["Score"].as_str().unwrap_or("0").parse::<i64>().unwrap());
+    Ok((output.to_string()))
+}
 
-            total_score += q["Score"].as_i64().unwrap();
+/* grade_tests
 
-            if case["status"].as_str().is_none() {
-                // This question is not collected in the reg match phase, pass
-                continue;
-            }
+   @dev Judges the tests a student wrote for a question with `coverage` or `mutation`,
+        against the reference solution in place of the contracts. The scores of both
+        add up when a question sets both
 
-            if case["status"].as_str().unwrap() == "Failure" {
-                q["Passed"] = json!(false);
-            } else {
-                q["Passed"] = json!(true);
-                get_score += q["Score"].as_i64().unwrap();
-            }
-        }
+*/
+fn grade_tests(
+    job: &JobMessage,
+    worker: &mut Worker,
+    question: &Question,
+    diagnostics: &[Diagnostic],
+) -> Result<String, ErrorCode> {
+    let output_path = worker.job_dir(job).join("output");
+    let mut output: serde_json::Value = fs::read_to_string(output_path.join("output.json"))
+        .ok()
+        .and_then(|raw| serde_json::from_str(&raw).ok())
+        .unwrap_or_else(|| json!({}));
+
+    report(worker, &job.judge_job_id, "test_started", json!({}));
+    let fuzz_env = question::fuzz_env(Some(question));
+    let (mut total_score, mut get_score) = (0.0, 0.0);
+    let mut scored = json!({});
+    if let Some(targets) = &question.meta.coverage {
+        let (total, earned, coverage) = run_forge_coverage(job, worker, targets, &fuzz_env)?;
+        total_score += total;
+        get_score += earned;
+        scored["coverage"] =
+            json!({ "lines": &coverage["lines"], "branches": &coverage["branches"] });
+        output["coverage"] = coverage;
+    }
+    if let Some(settings) = &question.meta.mutation {
+        let (total, earned, mutation) = run_mutation(job, worker, question, settings, &fuzz_env)?;
+        total_score += total;
+        get_score += earned;
+        scored["mutation"] = json!({ "total": &mutation["total"], "killed": &mutation["killed"], "percent": &mutation["percent"] });
+        output["mutation"] = mutation;
     }
 
-    output["total_score"] = json!(total_score);
-    output["get_score"] = json!(get_score);
+    output["total_score"] = rubric::score_json(total_score);
+    output["get_score"] = rubric::score_json(get_score);
+    scored["total_score"] = output["total_score"].clone();
+    scored["get_score"] = output["get_score"].clone();
+    report(worker, &job.judge_job_id, "scored", scored);
     output["info"] = json!("Complete");
     output["code"] = json!(0);
     output["msg"] = json!("Complete");
     output["jobId"] = json!(&job.judge_job_id);
+    output["diagnostics"] = json!(diagnostics);
+    output["fuzzSeed"] = json!(question.meta.fuzz.seed());
 
     let _ = fs::write(output_path.join("output.json"), output.to_string());
+    Ok(output.to_string())
+}
 
-    Ok((output.to_string()))
+// The forge arguments building and testing the job in `base_path`, after the command
+fn project_args(solc_version: &str, base_path: &Path) -> Vec<String> {
+    let path = |path: PathBuf| path.to_string_lossy().into_owned();
+    vec![
+        String::from("--contracts"),
+        path(base_path.to_path_buf()),
+        String::from("--cache-path"),
+        path(base_path.join("cache")),
+        String::from("--out"),
+        path(base_path.join("out")),
+        String::from("--use"),
+        solc_version.to_string(),
+        String::from("--offline"),
+        String::from("--allow-failure"),
+    ]
+}
+
+/* run_forge_coverage
+
+   @dev Runs the student's tests against the reference solution with `forge coverage`
+        and scores the lines and branches of `contracts/` they cover. Failing tests do not
+        stop the run, what they covered up to the failure counts
+
+*/
+fn run_forge_coverage(
+    job: &JobMessage,
+    worker: &mut Worker,
+    targets: &CoverageTargets,
+    fuzz_env: &[(&'static str, String)],
+) -> Result<(f64, f64, serde_json::Value), ErrorCode> {
+    let base_path = worker.job_dir(job);
+    let output_path = base_path.join("output");
+    let report_path = output_path.join("lcov.info");
+    let _ = fs::remove_file(&report_path);
+
+    let test_limit = worker.time_limits.test_limit(job);
+    let mut command = forge_command(worker, &base_path)?;
+    command
+        .arg("coverage")
+        .args(project_args(&job.solc_version, &base_path))
+        .args(["--report", "lcov", "--report-file"])
+        .arg(&report_path)
+        .envs(fuzz_env.iter().cloned());
+    let coverage_output = match run_forge(worker, &job.judge_job_id, &mut command, test_limit) {
+        Err(ForgeRunError::Cancelled) => return Err(ErrorCode::Cancelled),
+        Err(ForgeRunError::TimeLimit) => {
+            return Err(time_limit_exceeded(
+                job,
+                &output_path,
+                "coverage",
+                test_limit,
+            ))
+        }
+        Err(ForgeRunError::Crashed(signal)) => {
+            return Err(forge_crashed(job, &output_path, "coverage", signal))
+        }
+        Err(ForgeRunError::Spawn(err)) => return Err(ErrorCode::ForgeTestFailure(err.to_string())),
+        Ok(output) => output,
+    };
+
+    let Ok(lcov) = fs::read_to_string(&report_path) else {
+        let re = Regex::new(r"\x1b\[[\d;]+m").unwrap();
+        let stderr = String::from_utf8_lossy(&coverage_output.stderr);
+        let mut json = json!({});
+        json["info"] = json!("Coverage report missing");
+        json["code"] = json!(2);
+        json["jobId"] = json!(&job.judge_job_id);
+        json["msg"] = json!(re.replace_all(&stderr, "").trim());
+        let _ = fs::write(output_path.join("output.json"), json.to_string());
+        return Err(ErrorCode::EmptyFile);
+    };
+    let files = coverage::parse_lcov(&lcov, &base_path);
+    Ok(coverage::score(targets, &files))
+}
+
+// `path:Contract.signature` of every test case of a `forge test --json` output and whether it failed
+fn test_outcomes(test_out: &serde_json::Value, base_path: &Path) -> Vec<(String, bool)> {
+    let mut outcomes = vec![];
+    for (key, suite) in test_out.as_object().into_iter().flatten() {
+        let suite_name = match key.rsplit_once(':') {
+            Some((path, contract)) => {
+                format!(
+                    "{}:{}",
+                    relative_to_job(Path::new(path), base_path),
+                    contract
+                )
+            }
+            None => key.to_string(),
+        };
+        for (signature, case) in suite["test_results"].as_object().into_iter().flatten() {
+            let failed = case["status"].as_str() == Some("Failure");
+            outcomes.push((format!("{}.{}", suite_name, signature), failed));
+        }
+    }
+    outcomes.sort();
+    outcomes
+}
+
+/* run_mutation
+
+   @dev Runs the student's tests against every mutant of the reference solution and
+        scores the share they kill. The tests first run once against the reference
+        itself, those failing there kill nothing. The mutants go to the pool of the
+        server and run on whichever worker is idle, this one included. A mutant that
+        does not report back by the deadline, or whose task was dropped, fails the
+        job so it is retried
+
+*/
+fn run_mutation(
+    job: &JobMessage,
+    worker: &mut Worker,
+    question: &Question,
+    settings: &MutationSettings,
+    fuzz_env: &[(&'static str, String)],
+) -> Result<(f64, f64, serde_json::Value), ErrorCode> {
+    let base_path = worker.job_dir(job);
+    let output_path = base_path.join("output");
+    let test_limit = worker.time_limits.test_limit(job);
+
+    let mut command = forge_command(worker, &base_path)?;
+    command
+        .arg("test")
+        .args(project_args(&job.solc_version, &base_path))
+        .arg("--json")
+        .envs(fuzz_env.iter().cloned());
+    let baseline = match run_forge(worker, &job.judge_job_id, &mut command, test_limit) {
+        Err(ForgeRunError::Cancelled) => return Err(ErrorCode::Cancelled),
+        Err(ForgeRunError::TimeLimit) => {
+            return Err(time_limit_exceeded(job, &output_path, "test", test_limit))
+        }
+        Err(ForgeRunError::Crashed(signal)) => {
+            return Err(forge_crashed(job, &output_path, "test", signal))
+        }
+        Err(ForgeRunError::Spawn(err)) => return Err(ErrorCode::ForgeTestFailure(err.to_string())),
+        Ok(output) => output,
+    };
+    let Ok(baseline) = serde_json::from_slice::<serde_json::Value>(&baseline.stdout) else {
+        let mut json = json!({});
+        json["info"] = json!("Result not json");
+        json["code"] = json!(2);
+        json["jobId"] = json!(&job.judge_job_id);
+        json["msg"] = json!("No files");
+        let _ = fs::write(output_path.join("output.json"), json.to_string());
+        return Err(ErrorCode::EmptyFile);
+    };
+    let baseline_failures: Vec<String> = test_outcomes(&baseline, &base_path)
+        .into_iter()
+        .filter(|(_, failed)| *failed)
+        .map(|(test, _)| test)
+        .collect();
+
+    let mutants = question.mutants();
+    let (sender, results) = mpsc::channel();
+    let baseline_failures = Arc::new(baseline_failures);
+    worker.mutants.push(mutants.iter().map(|mutant| MutantTask {
+        judge_job_id: job.judge_job_id.clone(),
+        workspace: base_path.clone(),
+        solc_version: job.solc_version.clone(),
+        limit: test_limit,
+        mutant: mutant.clone(),
+        baseline_failures: baseline_failures.clone(),
+        fuzz_env: fuzz_env.to_vec(),
+        results: sender.clone(),
+    }));
+    drop(sender);
+
+    // Long enough for every mutant to run after the other at the highest test limit,
+    // plus one for the mutants of other jobs ahead in the pool
+    let deadline = Instant::now()
+        + Duration::from_secs(worker.time_limits.max_secs) * (mutants.len() as u32 + 1);
+    let outcomes = await_outcomes(worker, &job.judge_job_id, &mutants, results, deadline)?;
+    if outcomes
+        .values()
+        .any(|outcome| matches!(outcome, Outcome::Cancelled))
+    {
+        return Err(ErrorCode::Cancelled);
+    }
+    // A mutant the worker could not run says nothing about the tests
+    if let Some((id, Outcome::Failed(err))) = outcomes
+        .iter()
+        .find(|(_, outcome)| matches!(outcome, Outcome::Failed(_)))
+    {
+        return Err(ErrorCode::ForgeTestFailure(format!(
+            "mutant {}: {}",
+            id, err
+        )));
+    }
+
+    Ok(mutation::score(
+        settings,
+        &mutants,
+        &outcomes,
+        &baseline_failures,
+    ))
+}
+
+/* await_outcomes
+
+   @dev Collects what happened to every mutant of a job, running mutants from the pool
+        while it waits. Fails once the deadline passes or every task is gone with some
+        mutants not reported back, their workers died or dropped them
+
+*/
+fn await_outcomes(
+    worker: &mut Worker,
+    judge_job_id: &str,
+    mutants: &[Mutant],
+    results: Receiver<(usize, Outcome)>,
+    deadline: Instant,
+) -> Result<HashMap<usize, Outcome>, ErrorCode> {
+    let cancel_key = cancel_key(&worker.redis_prefix, judge_job_id);
+    let mut outcomes: HashMap<usize, Outcome> = HashMap::new();
+    while outcomes.len() < mutants.len() && Instant::now() < deadline {
+        if worker.sink.is_cancelled(&cancel_key).unwrap_or(false) {
+            worker.mutants.drop_job(judge_job_id);
+            return Err(ErrorCode::Cancelled);
+        }
+        renew_claim(worker);
+        if let Some(task) = worker.mutants.take() {
+            run_mutant(worker, task);
+        } else {
+            match results.recv_timeout(Duration::from_millis(CANCEL_CHECK_INTERVAL_MILLIS)) {
+                Ok((id, outcome)) => {
+                    outcomes.insert(id, outcome);
+                }
+                // Every task of the job ran or was dropped, nothing else is coming
+                Err(RecvTimeoutError::Disconnected) => break,
+                Err(RecvTimeoutError::Timeout) => {}
+            }
+        }
+        while let Ok((id, outcome)) = results.try_recv() {
+            outcomes.insert(id, outcome);
+        }
+    }
+    let missing: Vec<String> = mutants
+        .iter()
+        .filter(|mutant| !outcomes.contains_key(&mutant.id))
+        .map(|mutant| mutant.id.to_string())
+        .collect();
+    if !missing.is_empty() {
+        worker.mutants.drop_job(judge_job_id);
+        return Err(ErrorCode::ForgeTestFailure(format!(
+            "mutants {} never reported back",
+            missing.join(", ")
+        )));
+    }
+    Ok(outcomes)
+}
+
+/* run_mutant
+
+   @dev Runs the tests of a job against one mutant, in a copy of the job directory of
+        this worker, and sends back what they did to it. A test the reference solution
+        passed failing against the mutant kills it
+
+*/
+fn run_mutant(worker: &mut Worker, task: MutantTask) {
+    let workspace = worker.dir().join(".mutant");
+    let _ = fs::remove_dir_all(&workspace);
+    let outcome = match mutate_workspace(&task, &workspace) {
+        Ok(()) => mutant_outcome(worker, &task, &workspace),
+        Err(err) => Outcome::Failed(err),
+    };
+    let _ = task.results.send((task.mutant.id, outcome));
+}
+
+// Copies the job directory into `workspace` with the mutant applied
+fn mutate_workspace(task: &MutantTask, workspace: &Path) -> Result<(), String> {
+    for dir in ["contracts", "test", "fixtures"] {
+        if task.workspace.join(dir).is_dir() {
+            copy_dir_all(task.workspace.join(dir), workspace.join(dir))
+                .map_err(|err| format!("copy {}: {}", dir, err))?;
+        }
+    }
+    let file = workspace.join(&task.mutant.file);
+    let source =
+        fs::read_to_string(&file).map_err(|err| format!("{}: {}", task.mutant.file, err))?;
+    let mutated = task
+        .mutant
+        .apply(&source)
+        .ok_or(format!("{} no longer matches the mutant", task.mutant.file))?;
+    fs::write(&file, mutated).map_err(|err| format!("{}: {}", task.mutant.file, err))
+}
+
+fn mutant_outcome(worker: &mut Worker, task: &MutantTask, workspace: &Path) -> Outcome {
+    let mut command = match forge_command(worker, workspace) {
+        Ok(command) => command,
+        Err(err) => return Outcome::Failed(err.get_err_msg()),
+    };
+    command
+        .arg("test")
+        .args(project_args(&task.solc_version, workspace))
+        .arg("--json")
+        .envs(task.fuzz_env.iter().cloned());
+    let output = match run_forge(worker, &task.judge_job_id, &mut command, task.limit) {
+        Err(ForgeRunError::Cancelled) => return Outcome::Cancelled,
+        Err(ForgeRunError::TimeLimit) => return Outcome::TimedOut,
+        Err(ForgeRunError::Crashed(signal)) => {
+            return Outcome::Killed(format!("forge killed by signal {}", signal))
+        }
+        Err(ForgeRunError::Spawn(err)) => return Outcome::Failed(err.to_string()),
+        Ok(output) => output,
+    };
+    // forge prints no json when the mutant does not compile
+    let Ok(test_out) = serde_json::from_slice::<serde_json::Value>(&output.stdout) else {
+        return Outcome::Stillborn;
+    };
+    test_outcomes(&test_out, workspace)
+        .into_iter()
+        .find(|(test, failed)| *failed && !task.baseline_failures.contains(test))
+        .map_or(Outcome::Survived, |(test, _)| Outcome::Killed(test))
+}
+
+/* add_test_details
+
+   @dev Copies what forge reports about a test case into its question: the revert
+        `Reason`, the `Logs`, the fuzz or invariant `Counterexample`, the `Kind` of
+        test with its runs and gas, the `Gas` it used (the mean one for fuzz tests)
+        and its `DurationMs`
+
+*/
+fn add_test_details(q: &mut serde_json::Value, case: &serde_json::Value) {
+    let kind = &case["kind"];
+    // Unit tests are `Standard` on older forge releases
+    let gas = kind["Unit"]["gas"]
+        .as_u64()
+        .or_else(|| kind["Standard"].as_u64())
+        .or_else(|| kind["Fuzz"]["mean_gas"].as_u64());
+    let duration = &case["duration"];
+    let duration_ms = match (duration["secs"].as_u64(), duration["nanos"].as_u64()) {
+        (Some(secs), Some(nanos)) => Some(secs * 1000 + nanos / 1_000_000),
+        _ => None,
+    };
+
+    q["Reason"] = case["reason"].clone();
+    q["Logs"] = match case["decoded_logs"].as_array() {
+        Some(logs) => json!(logs),
+        None => json!([]),
+    };
+    q["Counterexample"] = case["counterexample"].clone();
+    q["Kind"] = kind.clone();
+    q["Gas"] = json!(gas);
+    q["DurationMs"] = json!(duration_ms);
+}
+
+// `path` relative to the job directory `base_path`, forge names files from the project root
+fn relative_to_job(path: &Path, base_path: &Path) -> String {
+    path.strip_prefix(base_path)
+        .ok()
+        .or_else(|| path.strip_prefix(base_path.canonicalize().ok()?).ok())
+        .unwrap_or(path)
+        .to_string_lossy()
+        .into_owned()
+}
+
+/* match_test_results
+
+   @dev The forge result of every question, `None` for those forge did not run. Suites
+        are keyed `path:Contract` and cases by signature, so test functions sharing a
+        name in several contracts or files are told apart
+
+*/
+fn match_test_results<'a>(
+    questions: &[serde_json::Value],
+    test_out: &'a serde_json::Value,
+    base_path: &Path,
+) -> Vec<Option<&'a serde_json::Value>> {
+    let mut suites = HashMap::new();
+    for (key, suite) in test_out.as_object().into_iter().flatten() {
+        if let Some((path, contract)) = key.rsplit_once(':') {
+            let path = relative_to_job(Path::new(path), base_path);
+            suites.insert(format!("{}:{}", path, contract), &suite["test_results"]);
+        }
+    }
+
+    questions
+        .iter()
+        .map(|q| {
+            let suite = format!("{}:{}", q["File"].as_str()?, q["Contract"].as_str()?);
+            suites.get(&suite)?.get(q["Signature"].as_str()?)
+        })
+        .collect()
 }
 
 fn clean_project(p: &str) {
     let _ = fs::remove_dir_all(Path::new(p));
 }
 
+// Scores of `question.json` win over the annotations of the tests
+fn apply_question_scores(worker: &Worker, question: &Question, job: &JobMessage) {
+    let output_path = worker.job_dir(job).join("output").join("output.json");
+    let Ok(raw) = fs::read_to_string(&output_path) else {
+        return;
+    };
+    if let Ok(mut output) = serde_json::from_str::<serde_json::Value>(&raw) {
+        question.apply_scores(&mut output);
+        let _ = fs::write(&output_path, output.to_string());
+    }
+}
+
+// An empty result for jobs without official tests to collect
+fn start_output(basepath: &Path) -> Result<(), ErrorCode> {
+    let output = basepath.join("output");
+    let _ = fs::remove_dir_all(&output);
+    fs::create_dir_all(&output)
+        .and_then(|_| fs::write(output.join("output.json"), json!({}).to_string()))
+        .map_err(|err| ErrorCode::WorkDirWriteFailure(err.to_string()))
+}
+
 fn collect_output_from_test_scripts(basepath: &Path) -> Result<(), ErrorCode> {
-    let test_dir = Path::new(basepath).join("test");
-    let contract_dir = Path::new(basepath).join("contracts");
     let output = Path::new(basepath).join("output");
 
     let _ = fs::remove_dir_all(output.clone());
     let res = fs::create_dir_all(output.clone());
 
-    let test_dir_entries_res = fs::read_dir(test_dir);
-    if test_dir_entries_res.is_err() {
-        return Err(ErrorCode::ReadDirErr(format!(
-            "Read directory error({})",
-            test_dir_entries_res.unwrap_err().to_string()
-        )));
-    }
-    let test_dir_entries = test_dir_entries_res.unwrap();
+    let tests = match solidity::collect_tests(basepath) {
+        Ok(tests) => tests,
+        Err(solidity::ErrorCode::ReadFailure(err)) => {
+            return Err(ErrorCode::ReadDirErr(format!(
+                "Read directory error({})",
+                err
+            )));
+        }
+        Err(err) => return Err(ErrorCode::InvalidTests(err.get_err_msg())),
+    };
 
     let mut raw_infos: Vec<serde_json::Value> = vec![];
-
-    for entry in test_dir_entries {
-        let entry_dir = entry.unwrap();
-        let path = entry_dir.path();
-        let file_content = fs::read(&path).unwrap();
-        let file_content = String::from_utf8_lossy(&file_content).into_owned();
-        // println!("{}", file_content);
-
-        let re =
-            Regex::new(r"function\s+(test[^\(\s]+)\s*\([^)]*\)[^\{]*?\{\s*(\/\*\*[^\\]*?\*\/)?")
-                .unwrap();
-
-        let res: Vec<&str> = re.find_iter(&file_content).map(|m| m.as_str()).collect();
-
-        for s in res {
-            let attributes_re = Regex::new(r"@.*[\s]*:[\s]*[^\n]*").unwrap();
-            let res: Vec<&str> = attributes_re.find_iter(&s).map(|m| m.as_str()).collect();
-            let mut res_json = json!({});
-            for attr in res {
-                let split: Vec<&str> = attr[1..].split(":").collect();
-                let attribute_name = split[0].trim();
-                let attribute_value = split[1].trim();
-                res_json[attribute_name] = json!(attribute_value);
-            }
-            let function_name_re = Regex::new(r"function\s(test[^\(]*)").unwrap();
-            let mut function_name: Option<String> = None;
-            for (_, [function_name_captured]) in
-                function_name_re.captures_iter(&s).map(|s| s.extract())
-            {
-                function_name = Some(function_name_captured.to_string());
-            }
-            res_json["Func"] = json!(function_name.unwrap());
-            res_json["Passed"] = json!(false);
-            raw_infos.push(res_json);
+    for test in tests {
+        let mut res_json = json!({});
+        for (key, value) in &test.tags {
+            res_json[key] = json!(value);
+        }
+        res_json["Func"] = json!(test.name);
+        res_json["File"] = json!(test.file);
+        res_json["Contract"] = json!(test.contract);
+        res_json["Signature"] = json!(test.signature);
+        if res_json.get("Visibility").is_none() {
+            res_json["Visibility"] = json!("public");
         }
+        res_json["Passed"] = json!(false);
+        raw_infos.push(res_json);
     }
 
     let mut test_res_json = json!({});
@@ -1019,29 +2550,90 @@ pub fn start(
     redis_host: &str,
     redis_prefix: &str,
     worker_dir: &str,
-    redis_list_name: &str,
+    server_id: &str,
+    settings: &WorkerSettings,
+    backend: &QueueBackend,
 ) {
     log4rs::init_file("log4rs.yaml", Default::default()).unwrap();
     // print!("{:?}", res.unwrap_err());
     info!("Start cleanning..");
     clean_project(worker_dir);
+
+    // Stream entries of dead workers are reclaimed through XAUTOCLAIM instead
+    match redis::Client::open(redis_host).and_then(|client| client.get_connection()) {
+        Ok(mut conn) if matches!(backend, QueueBackend::List { .. }) => {
+            let requests_key = backend.requests_key(redis_prefix);
+            match list::requeue_orphaned_jobs(&mut conn, redis_prefix, &requests_key, server_id) {
+                Ok(requeued) => info!("Requeued {} orphaned jobs", requeued),
+                Err(err) => error!("Requeue orphaned jobs failed: {}", err.get_err_msg()),
+            }
+            for i in 0..thread_num {
+                let worker_id = worker_id(server_id, i as i8);
+                if let Err(err) = list::register_worker(&mut conn, redis_prefix, &worker_id) {
+                    error!(
+                        "Register worker {} failed: {}",
+                        worker_id,
+                        err.get_err_msg()
+                    );
+                }
+            }
+        }
+        Ok(_) => {}
+        Err(err) => error!(
+            "{}",
+            ErrorCode::RedisConnectErr(err.to_string()).get_err_msg()
+        ),
+    }
+
+    let heartbeat_redis_host = redis_host.to_string();
+    let heartbeat_redis_prefix = redis_prefix.to_string();
+    let heartbeat_server_id = server_id.to_string();
+    thread::spawn(move || {
+        heartbeat_thread(
+            heartbeat_redis_host.as_str(),
+            heartbeat_redis_prefix.as_str(),
+            heartbeat_server_id.as_str(),
+        )
+    });
+
     // let _ = init_cache_file();
+    let mutants = MutantPool::default();
     let mut handles = vec![];
     for i in 0..thread_num {
         info!("Starting thread {}", i);
         let redis_host_str = redis_host.to_string();
         let redis_prefix_str = redis_prefix.to_string();
         let worker_dir_str = worker_dir.to_string();
-        let redis_list_name_str = redis_list_name.to_string();
+        let worker_id = worker_id(server_id, i as i8);
+        let settings = settings.clone();
+        let backend = backend.clone();
+        let mutants = mutants.clone();
 
         let handle = thread::spawn(move || {
-            let res = worker_thread(
-                i as i8,
-                redis_host_str.as_str(),
-                redis_prefix_str.as_str(),
-                worker_dir_str.as_str(),
-                redis_list_name_str.as_str(),
-            );
+            let queue = backend.open(&redis_host_str, &redis_prefix_str, &worker_id);
+            let sink = RedisResultSink::new(&redis_host_str);
+            let (queue, sink) = match (queue, sink) {
+                (Ok(queue), Ok(sink)) => (queue, sink),
+                (Err(err), _) | (_, Err(err)) => {
+                    log::error!("{:?}", err.get_err_msg());
+                    return;
+                }
+            };
+
+            let mut worker = Worker {
+                num: i as i8,
+                worker_dir: worker_dir_str,
+                redis_prefix: redis_prefix_str,
+                retry_policy: settings.retry_policy,
+                time_limits: settings.time_limits,
+                sandbox: settings.sandbox,
+                question_bank: settings.question_bank,
+                queue,
+                sink: Box::new(sink),
+                mutants,
+                claim: None,
+            };
+            let res = worker_thread(&mut worker);
             if res.is_err() {
                 log::error!("{:?}", res.unwrap_err().get_err_msg());
             } else {
@@ -1055,3 +2647,423 @@ pub fn start(
         handle.join().unwrap();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_results_are_matched_by_contract_and_signature() {
+        let base_path = env::temp_dir().join(format!("judger-match-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&base_path);
+        fs::create_dir_all(base_path.join("test")).unwrap();
+        fs::write(
+            base_path.join("test").join("Vault.t.sol"),
+            r#"
+abstract contract OwnerBase {
+    function testOwner() public {
+        /**
+         * @Score: 3
+         */
+    }
+}
+
+contract DepositTest {
+    function testDeposit() public {
+        /**
+         * @Score: 10
+         */
+    }
+}
+
+contract WithdrawTest is OwnerBase {
+    function testDeposit() public {
+        /**
+         * @Score: 20
+         */
+    }
+
+    function testFuzzWithdraw(uint amount, address to) public {
+        /**
+         * @Score: 5
+         */
+    }
+}
+"#,
+        )
+        .unwrap();
+        fs::write(
+            base_path.join("test").join("Owner.t.sol"),
+            "import {OwnerBase} from \"./Vault.t.sol\";\ncontract OwnerTest is OwnerBase {}\n",
+        )
+        .unwrap();
+        collect_output_from_test_scripts(&base_path).unwrap();
+        let output: serde_json::Value = serde_json::from_str(
+            &fs::read_to_string(base_path.join("output").join("output.json")).unwrap(),
+        )
+        .unwrap();
+
+        let file = |file: &str, contract: &str| {
+            format!(
+                "{}:{}",
+                base_path.join("test").join(file).display(),
+                contract
+            )
+        };
+        let test_out = json!({
+            file("Owner.t.sol", "OwnerTest"): {
+                "test_results": { "testOwner()": { "status": "Success" } }
+            },
+            file("Vault.t.sol", "DepositTest"): {
+                "test_results": { "testDeposit()": { "status": "Success" } }
+            },
+            file("Vault.t.sol", "WithdrawTest"): {
+                "test_results": {
+                    "testDeposit()": { "status": "Failure" },
+                    "testOwner()": { "status": "Failure" },
+                    "testFuzzWithdraw(uint256,address)": { "status": "Success" }
+                }
+            }
+        });
+
+        let questions = output["questions"].as_array().unwrap();
+        let cases = match_test_results(questions, &test_out, &base_path);
+        let results: Vec<(&str, &str, &str, &str)> = questions
+            .iter()
+            .zip(&cases)
+            .map(|(q, case)| {
+                (
+                    q["File"].as_str().unwrap(),
+                    q["Contract"].as_str().unwrap(),
+                    q["Signature"].as_str().unwrap(),
+                    case.unwrap()["status"].as_str().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            results,
+            vec![
+                ("test/Owner.t.sol", "OwnerTest", "testOwner()", "Success"),
+                (
+                    "test/Vault.t.sol",
+                    "DepositTest",
+                    "testDeposit()",
+                    "Success"
+                ),
+                (
+                    "test/Vault.t.sol",
+                    "WithdrawTest",
+                    "testDeposit()",
+                    "Failure"
+                ),
+                (
+                    "test/Vault.t.sol",
+                    "WithdrawTest",
+                    "testFuzzWithdraw(uint256,address)",
+                    "Success"
+                ),
+                ("test/Vault.t.sol", "WithdrawTest", "testOwner()", "Failure"),
+            ]
+        );
+        // The abstract base is never run on its own
+        assert!(questions
+            .iter()
+            .all(|q| q["Contract"] != json!("OwnerBase")));
+
+        let _ = fs::remove_dir_all(&base_path);
+    }
+
+    // A worker judging from an in-memory queue, and the queue jobs are submitted to
+    fn memory_worker(max_retries: u32) -> (Worker, MemoryQueue) {
+        let queue = MemoryQueue::new();
+        let worker = Worker {
+            num: 0,
+            worker_dir: env::temp_dir()
+                .join(format!("judger-flow-{}", std::process::id()))
+                .to_string_lossy()
+                .into_owned(),
+            redis_prefix: String::from("test"),
+            retry_policy: RetryPolicy {
+                max_retries,
+                backoff_secs: 0,
+            },
+            time_limits: TimeLimits::default(),
+            sandbox: SandboxConfig::default(),
+            question_bank: None,
+            queue: Box::new(queue.for_worker("test:00")),
+            sink: Box::new(queue.result_sink()),
+            mutants: MutantPool::default(),
+            claim: None,
+        };
+        (worker, queue)
+    }
+
+    // Submits a job of one file as a client would and returns its raw message
+    fn submit(queue: &MemoryQueue, job_id: &str, path: &str, content: &str) -> String {
+        let raw = json!({
+            "questionNo": "q1",
+            "solcVersion": "0.8.20",
+            "judgeJobId": job_id,
+            "pathWithContent": [{ "path": path, "content": content }],
+        })
+        .to_string();
+        let mut sink = queue.result_sink();
+        sink.expect(&request_key("test", job_id), RESPONSE_TTL_SECS)
+            .unwrap();
+        queue.for_worker("client").submit(&raw).unwrap();
+        raw
+    }
+
+    fn response(queue: &MemoryQueue, job_id: &str) -> Option<serde_json::Value> {
+        let response = queue
+            .result_sink()
+            .take(&request_key("test", job_id), &response_key("test", job_id))
+            .unwrap()?;
+        Some(serde_json::from_str(&response).unwrap())
+    }
+
+    fn status(queue: &MemoryQueue, job_id: &str) -> serde_json::Value {
+        let record = queue
+            .result_sink()
+            .lookup(&job_record_key("test", job_id))
+            .unwrap()
+            .unwrap();
+        serde_json::from_str::<serde_json::Value>(&record).unwrap()["status"].clone()
+    }
+
+    fn judge_next(worker: &mut Worker) {
+        let delivery = worker.queue.fetch().unwrap().unwrap();
+        process_job(worker, delivery);
+    }
+
+    #[test]
+    fn jobs_cancelled_while_queued_are_never_judged() {
+        let (mut worker, queue) = memory_worker(0);
+        submit(&queue, "job-1", "contracts/A.sol", "contract A {}");
+        cancel_job(&mut queue.result_sink(), "test", "job-1").unwrap();
+
+        judge_next(&mut worker);
+        assert_eq!(response(&queue, "job-1").unwrap()["code"], json!(5));
+        assert_eq!(status(&queue, "job-1"), json!("cancelled"));
+        assert!(worker.queue.held().unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejected_submissions_are_answered_without_forge() {
+        let (mut worker, queue) = memory_worker(0);
+        submit(&queue, "job-1", "../A.sol", "contract A {}");
+        submit(
+            &queue,
+            "job-2",
+            "test/A.t.sol",
+            "contract T { function testA() public { vm.ffi(new string[](0)); } }",
+        );
+
+        judge_next(&mut worker);
+        judge_next(&mut worker);
+        assert_eq!(response(&queue, "job-1").unwrap()["code"], json!(7));
+        let forbidden = response(&queue, "job-2").unwrap();
+        assert_eq!(forbidden["code"], json!(8));
+        assert_eq!(forbidden["violations"][0]["rule"], json!("vm.ffi"));
+        assert_eq!(status(&queue, "job-2"), json!("done"));
+        assert!(worker.queue.held().unwrap().is_empty());
+    }
+
+    #[test]
+    fn failed_jobs_are_retried_then_dead_lettered() {
+        let (mut worker, queue) = memory_worker(1);
+        submit(&queue, "job-1", "contracts/A.sol", "contract A {}");
+        let err = ErrorCode::ForgeTestFailure(String::from("forge crashed"));
+
+        let delivery = worker.queue.fetch().unwrap().unwrap();
+        retry_or_dead_letter(&mut worker, &delivery, &err).unwrap();
+        assert_eq!(status(&queue, "job-1"), json!("retrying"));
+        assert!(response(&queue, "job-1").is_none());
+        assert!(worker.queue.held().unwrap().is_empty());
+
+        worker.queue.requeue_due(i64::MAX).unwrap();
+        let delivery = worker.queue.fetch().unwrap().unwrap();
+        assert_eq!(get_job_message(&delivery.raw).unwrap().attempts, 1);
+        retry_or_dead_letter(&mut worker, &delivery, &err).unwrap();
+        let response = response(&queue, "job-1").unwrap();
+        assert_eq!(response["code"], json!(4));
+        assert_eq!(response["msg"], json!("forge crashed"));
+
+        let dead = worker.queue.dead_letters().unwrap();
+        let dead: serde_json::Value = serde_json::from_str(&dead[0]).unwrap();
+        assert_eq!(dead["attempts"], json!(2));
+        assert_eq!(dead["job"]["judgeJobId"], json!("job-1"));
+    }
+
+    #[test]
+    fn jobs_are_judged_in_the_worker_dir() {
+        let (mut worker, _) = memory_worker(0);
+        worker.worker_dir = env::temp_dir()
+            .join(format!("judger-dir-{}", std::process::id()))
+            .to_string_lossy()
+            .into_owned();
+        let job = job_with_files("q1", &[("contracts/A.sol", "contract A {}")]);
+        let job_dir = Path::new(&worker.worker_dir).join("00").join("q1");
+        assert_eq!(worker.job_dir(&job), job_dir);
+
+        create_files_as_job_message(&job, worker.num, &worker.worker_dir).unwrap();
+        assert!(job_dir.join("contracts").join("A.sol").exists());
+        clean_contracts_and_test_dir(&worker, &job).unwrap();
+        let cleaned = !job_dir.join("contracts").exists();
+        fs::remove_dir_all(&worker.worker_dir).unwrap();
+        assert!(cleaned);
+    }
+
+    fn job_with_files(question_no: &str, files: &[(&str, &str)]) -> JobMessage {
+        let files: Vec<serde_json::Value> = files
+            .iter()
+            .map(|(path, content)| json!({ "path": path, "content": content }))
+            .collect();
+        let raw = json!({
+            "questionNo": question_no,
+            "solcVersion": "0.8.20",
+            "judgeJobId": "job",
+            "pathWithContent": files,
+        });
+        get_job_message(&raw.to_string()).unwrap()
+    }
+
+    #[test]
+    fn submitted_paths_stay_inside_contracts_and_test() {
+        assert_eq!(
+            submitted_path("contracts/Vault.sol"),
+            Ok(PathBuf::from("contracts/Vault.sol"))
+        );
+        assert_eq!(
+            submitted_path("test/unit//Vault.t.sol"),
+            Ok(PathBuf::from("test/unit/Vault.t.sol"))
+        );
+        for path in [
+            "../Vault.sol",
+            "/etc/passwd",
+            "./contracts/Vault.sol",
+            "contracts/../../Vault.sol",
+            "contracts\\Vault.sol",
+            "contracts/Vault\0.sol",
+            "lib/forge-std/src/Test.sol",
+            "foundry.toml",
+            "contracts",
+        ] {
+            assert!(submitted_path(path).is_err(), "{} was accepted", path);
+        }
+    }
+
+    #[test]
+    fn submissions_are_validated_before_anything_is_written() {
+        let valid = job_with_files("q1", &[("contracts/A.sol", "contract A {}")]);
+        assert!(validate_submission(&valid).is_ok());
+
+        for question_no in ["../q1", "q1/q2", "", "/q1"] {
+            let job = job_with_files(question_no, &[("contracts/A.sol", "")]);
+            assert!(
+                validate_submission(&job).is_err(),
+                "{} was accepted",
+                question_no
+            );
+        }
+
+        let twice = job_with_files("q1", &[("contracts/A.sol", ""), ("contracts//A.sol", "")]);
+        let err = validate_submission(&twice).unwrap_err().get_err_msg();
+        assert!(
+            err.ends_with("contracts//A.sol: submitted twice"),
+            "{}",
+            err
+        );
+
+        let paths: Vec<String> = (0..=MAX_SUBMITTED_FILES)
+            .map(|i| format!("contracts/A{}.sol", i))
+            .collect();
+        let files: Vec<(&str, &str)> = paths.iter().map(|path| (path.as_str(), "")).collect();
+        assert!(validate_submission(&job_with_files("q1", &files)).is_err());
+
+        let content = "a".repeat(MAX_SUBMISSION_BYTES + 1);
+        let large = job_with_files("q1", &[("contracts/A.sol", &content)]);
+        assert!(validate_submission(&large).is_err());
+    }
+
+    #[test]
+    fn claims_are_renewed_once_the_interval_passed() {
+        let (mut worker, queue) = memory_worker(0);
+        submit(&queue, "renew", "contracts/A.sol", "contract A {}");
+        let delivery = worker.queue.fetch().unwrap().unwrap();
+
+        let fresh = Instant::now();
+        worker.claim = Some((delivery.clone(), fresh));
+        renew_claim(&mut worker);
+        assert_eq!(worker.claim.as_ref().unwrap().1, fresh);
+
+        let Some(stale) = fresh.checked_sub(Duration::from_secs(CLAIM_RENEW_SECS)) else {
+            return;
+        };
+        worker.claim = Some((delivery, stale));
+        renew_claim(&mut worker);
+        assert!(worker.claim.as_ref().unwrap().1 >= fresh);
+    }
+
+    fn mutant(id: usize) -> Mutant {
+        Mutant {
+            id,
+            file: String::from("contracts/A.sol"),
+            line: 1,
+            operator: String::from("operator-swap"),
+            original: String::from("+"),
+            replacement: String::from("-"),
+            start: 0,
+            end: 1,
+        }
+    }
+
+    #[test]
+    fn outcomes_are_collected_for_every_mutant() {
+        let (mut worker, _queue) = memory_worker(0);
+        let (sender, results) = mpsc::channel();
+        sender.send((1, Outcome::Survived)).unwrap();
+        sender
+            .send((2, Outcome::Killed(String::from("test_a"))))
+            .unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let outcomes = await_outcomes(
+            &mut worker,
+            "job",
+            &[mutant(1), mutant(2)],
+            results,
+            deadline,
+        )
+        .unwrap();
+        assert_eq!(outcomes.len(), 2);
+        drop(sender);
+    }
+
+    #[test]
+    fn mutants_not_reported_back_fail_the_job() {
+        let (mut worker, _queue) = memory_worker(0);
+
+        // Their tasks are gone, nothing will report the second mutant
+        let (sender, results) = mpsc::channel();
+        sender.send((1, Outcome::Survived)).unwrap();
+        drop(sender);
+        let deadline = Instant::now() + Duration::from_secs(60);
+        let err = await_outcomes(
+            &mut worker,
+            "job",
+            &[mutant(1), mutant(2)],
+            results,
+            deadline,
+        )
+        .unwrap_err();
+        assert!(err.get_err_msg().contains("mutants 2 never reported back"));
+
+        // A task still held somewhere but past the deadline
+        let (sender, results) = mpsc::channel::<(usize, Outcome)>();
+        let deadline = Instant::now();
+        let err = await_outcomes(&mut worker, "job", &[mutant(1)], results, deadline).unwrap_err();
+        assert!(err.is_retryable());
+        drop(sender);
+    }
+}
@@ -0,0 +1,899 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use crate::rubric;
+use crate::tiers;
+
+// Tags of standard NatSpec, allowed on tests but not collected
+const NATSPEC_TAGS: [&str; 7] = [
+    "title",
+    "author",
+    "notice",
+    "dev",
+    "param",
+    "return",
+    "inheritdoc",
+];
+const VISIBILITIES: [&str; 4] = ["public", "external", "internal", "private"];
+
+#[derive(Debug)]
+pub enum ErrorCode {
+    ReadFailure(String),
+    InvalidTests(Vec<String>),
+}
+
+impl ErrorCode {
+    pub fn get_err_msg(&self) -> String {
+        match self {
+            ErrorCode::ReadFailure(err) => format!("Read tests failed: {}", err),
+            ErrorCode::InvalidTests(errors) => format!("Invalid tests:\n{}", errors.join("\n")),
+        }
+    }
+}
+
+/* TestFunction
+
+   @dev A test function forge runs, `file` relative to the judged directory, `tags` the
+        annotations of the test in the order written, keyed `Score` for both
+        `@custom:score 10` NatSpec and the `@Score: 10` comment in the function body
+
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestFunction {
+    pub file: String,
+    pub contract: String,
+    pub name: String,
+    pub signature: String,
+    pub visibility: String,
+    pub line: usize,
+    pub tags: Vec<(String, String)>,
+}
+
+/* Contract
+
+   @dev A contract of a test file with the test functions it declares itself. `bases`
+        are the contracts it inherits from as listed after `is`, `runs_tests` is false
+        for abstract contracts, libraries and interfaces, forge never runs them
+
+*/
+#[derive(Debug, Clone, PartialEq)]
+struct Contract {
+    file: String,
+    name: String,
+    runs_tests: bool,
+    bases: Vec<String>,
+    tests: Vec<TestFunction>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Literal(String),
+    Punct(char),
+    Doc(String),
+}
+
+#[derive(Debug)]
+struct Lexed {
+    token: Token,
+    line: usize,
+}
+
+/* lex
+
+   @dev Tokens of a Solidity source. Comments are dropped except NatSpec, `/** */` and
+        runs of `///` lines, which become a single `Doc`. Strings and numbers are only
+        kept as literals
+
+*/
+fn lex(source: &str) -> Result<Vec<Lexed>, String> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens: Vec<Lexed> = vec![];
+    let mut line = 1;
+    // Line of the last `///`, the next one on the line below continues it
+    let mut line_doc_end = 0;
+    let mut i = 0;
+
+    let peek = |i: usize| chars.get(i).copied().unwrap_or('\0');
+    while i < chars.len() {
+        let c = chars[i];
+        let start_line = line;
+        if c == '\n' {
+            line += 1;
+            i += 1;
+        } else if c.is_whitespace() {
+            i += 1;
+        } else if c == '/' && peek(i + 1) == '/' {
+            let end = (i..chars.len())
+                .find(|&j| chars[j] == '\n')
+                .unwrap_or(chars.len());
+            let is_doc = peek(i + 2) == '/' && peek(i + 3) != '/';
+            if is_doc {
+                let text: String = chars[i + 3..end].iter().collect();
+                match tokens.last_mut() {
+                    Some(Lexed {
+                        token: Token::Doc(doc),
+                        ..
+                    }) if line_doc_end + 1 == line => {
+                        doc.push('\n');
+                        doc.push_str(&text);
+                    }
+                    _ => tokens.push(Lexed {
+                        token: Token::Doc(text),
+                        line,
+                    }),
+                }
+                line_doc_end = line;
+            }
+            i = end;
+        } else if c == '/' && peek(i + 1) == '*' {
+            let end = (i + 2..chars.len().saturating_sub(1))
+                .find(|&j| chars[j] == '*' && chars[j + 1] == '/')
+                .ok_or(format!("line {}: unterminated comment", start_line))?;
+            let is_doc = peek(i + 2) == '*' && end > i + 2;
+            let text: String = chars[i + 2..end].iter().collect();
+            line += text.matches('\n').count();
+            if is_doc {
+                tokens.push(Lexed {
+                    token: Token::Doc(text[1..].to_string()),
+                    line: start_line,
+                });
+            }
+            i = end + 2;
+        } else if c == '"' || c == '\'' {
+            let mut j = i + 1;
+            while j < chars.len() && chars[j] != c && chars[j] != '\n' {
+                j += if chars[j] == '\\' { 2 } else { 1 };
+            }
+            if peek(j) != c {
+                return Err(format!("line {}: unterminated string", start_line));
+            }
+            tokens.push(Lexed {
+                token: Token::Literal(chars[i..=j].iter().collect()),
+                line,
+            });
+            i = j + 1;
+        } else if c.is_ascii_alphanumeric() || c == '_' || c == '$' {
+            let end = (i..chars.len())
+                .find(|&j| {
+                    !(chars[j].is_ascii_alphanumeric() || chars[j] == '_' || chars[j] == '$')
+                })
+                .unwrap_or(chars.len());
+            let word: String = chars[i..end].iter().collect();
+            let token = if c.is_ascii_digit() {
+                Token::Literal(word)
+            } else {
+                Token::Ident(word)
+            };
+            tokens.push(Lexed { token, line });
+            i = end;
+        } else {
+            tokens.push(Lexed {
+                token: Token::Punct(c),
+                line,
+            });
+            i += 1;
+        }
+    }
+    Ok(tokens)
+}
+
+fn camel_case(name: &str) -> String {
+    name.split(['-', '_'])
+        .map(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
+
+/* parse_tags
+
+   @dev Annotations of a doc comment: `@custom:score 10` is keyed `Score`, the older
+        `@Score: 10` keeps its name. Standard NatSpec tags are skipped, any other tag is
+        an error
+
+*/
+fn parse_tags(doc: &str) -> Result<Vec<(String, String)>, String> {
+    let mut blocks: Vec<String> = vec![];
+    for line in doc.lines() {
+        let line = line.trim().trim_start_matches('*').trim();
+        if line.starts_with('@') {
+            blocks.push(line.to_string());
+        } else if let Some(block) = blocks.last_mut() {
+            if !line.is_empty() {
+                block.push(' ');
+                block.push_str(line);
+            }
+        }
+    }
+
+    let mut tags = vec![];
+    for block in blocks {
+        let rest = &block[1..];
+        let word_end = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '-'))
+            .unwrap_or(rest.len());
+        let (word, rest) = rest.split_at(word_end);
+        let (key, value) = if let Some(custom) = rest.strip_prefix(':').filter(|_| word == "custom")
+        {
+            let name_end = custom.find(char::is_whitespace).unwrap_or(custom.len());
+            let (name, value) = custom.split_at(name_end);
+            (camel_case(name), value.trim())
+        } else if let Some(value) = rest.trim_start().strip_prefix(':') {
+            (word.to_string(), value.trim())
+        } else if NATSPEC_TAGS.contains(&word) {
+            continue;
+        } else {
+            return Err(format!("unknown tag @{}", word));
+        };
+
+        if key.is_empty() {
+            return Err(format!("{} has no name", block));
+        }
+        if value.is_empty() {
+            return Err(format!("{} has no value", block));
+        }
+        let is_points = ["Score", "Bonus", "Penalty"].contains(&key.as_str());
+        if is_points
+            && !value
+                .parse::<f64>()
+                .is_ok_and(|v| v.is_finite() && v >= 0.0)
+        {
+            return Err(format!(
+                "{} {} is not a number of points",
+                key.to_lowercase(),
+                value
+            ));
+        }
+        let is_gas = ["GasTarget", "GasCeiling"].contains(&key.as_str());
+        if is_gas && value.parse::<u64>().is_err() {
+            return Err(format!("{} {} is not an amount of gas", key, value));
+        }
+        if key == "GasPartial" && !value.parse::<f64>().is_ok_and(|v| (0.0..=1.0).contains(&v)) {
+            return Err(format!("GasPartial {} is not within 0 and 1", value));
+        }
+        if key == "Visibility" && !tiers::VISIBILITIES.contains(&value) {
+            return Err(format!(
+                "visibility {} is none of {}",
+                value,
+                tiers::VISIBILITIES.join(", ")
+            ));
+        }
+        if tags.iter().any(|(k, _)| k == &key) {
+            return Err(format!("{} is given twice", key));
+        }
+        tags.push((key, value.to_string()));
+    }
+
+    let gas = |key: &str| {
+        tags.iter()
+            .find(|(k, _)| k == key)
+            .and_then(|(_, v)| v.parse::<u64>().ok())
+    };
+    let has = |key: &str| tags.iter().any(|(k, _)| k == key);
+    if (has("GasCeiling") || has("GasPartial")) && !has("GasTarget") {
+        return Err(String::from("gas ceiling without a GasTarget"));
+    }
+    if let (Some(target), Some(ceiling)) = (gas("GasTarget"), gas("GasCeiling")) {
+        if ceiling < target {
+            return Err(format!("GasCeiling {} below GasTarget {}", ceiling, target));
+        }
+    }
+    Ok(tags)
+}
+
+// Canonical type of a parameter, `uint[] memory amounts` gives `uint256[]`
+fn param_type(tokens: &[&Token]) -> String {
+    let mut ty = String::new();
+    let mut iter = tokens.iter().peekable();
+    if let Some(Token::Ident(name)) = iter.next() {
+        ty.push_str(match name.as_str() {
+            "uint" => "uint256",
+            "int" => "int256",
+            "byte" => "bytes1",
+            name => name,
+        });
+    }
+    while let Some(token) = iter.next() {
+        match token {
+            Token::Punct('.') => {
+                if let Some(Token::Ident(name)) = iter.next() {
+                    ty.push('.');
+                    ty.push_str(name);
+                }
+            }
+            Token::Punct('[') => {
+                ty.push('[');
+                for token in iter.by_ref() {
+                    match token {
+                        Token::Punct(']') => break,
+                        Token::Literal(text) | Token::Ident(text) => ty.push_str(text),
+                        _ => {}
+                    }
+                }
+                ty.push(']');
+            }
+            _ => break,
+        }
+    }
+    ty
+}
+
+struct Parser<'a> {
+    tokens: &'a [Lexed],
+    pos: usize,
+    contracts: Vec<Contract>,
+    tests: Vec<TestFunction>,
+    errors: Vec<String>,
+}
+
+impl<'a> Parser<'a> {
+    fn token(&self, pos: usize) -> Option<&'a Token> {
+        self.tokens.get(pos).map(|lexed| &lexed.token)
+    }
+
+    fn line(&self, pos: usize) -> usize {
+        self.tokens
+            .get(pos)
+            .or(self.tokens.last())
+            .map_or(1, |lexed| lexed.line)
+    }
+
+    // Moves past the group opened at `pos`, nested groups included
+    fn skip_group(&mut self, open: char, close: char) -> Vec<&'a Token> {
+        let mut depth = 0;
+        let mut inner = vec![];
+        while let Some(token) = self.token(self.pos) {
+            self.pos += 1;
+            match token {
+                Token::Punct(c) if *c == open => depth += 1,
+                Token::Punct(c) if *c == close => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return inner;
+                    }
+                }
+                _ => {}
+            }
+            if depth > 0 && !(depth == 1 && token == &Token::Punct(open)) {
+                inner.push(token);
+            }
+        }
+        self.errors
+            .push(format!("line {}: unbalanced {}", self.line(self.pos), open));
+        inner
+    }
+
+    fn parse(&mut self) {
+        while let Some(token) = self.token(self.pos) {
+            match token {
+                Token::Ident(kind)
+                    if kind == "contract" || kind == "library" || kind == "interface" =>
+                {
+                    let is_abstract = self.pos.checked_sub(1).and_then(|pos| self.token(pos))
+                        == Some(&Token::Ident(String::from("abstract")));
+                    self.parse_contract(kind == "contract", is_abstract)
+                }
+                Token::Punct('{') => {
+                    self.skip_group('{', '}');
+                }
+                _ => self.pos += 1,
+            }
+        }
+    }
+
+    fn parse_contract(&mut self, is_contract: bool, is_abstract: bool) {
+        self.pos += 1;
+        let Some(Token::Ident(name)) = self.token(self.pos) else {
+            self.errors.push(format!(
+                "line {}: contract without a name",
+                self.line(self.pos)
+            ));
+            return;
+        };
+        // Bases up to the body, `Base(1)` and `Lib.Base` named by their last identifier
+        self.pos += 1;
+        let mut bases = vec![];
+        let mut base = None;
+        while let Some(token) = self.token(self.pos) {
+            match token {
+                Token::Punct('{') => break,
+                Token::Punct('(') => {
+                    self.skip_group('(', ')');
+                    continue;
+                }
+                Token::Punct(',') => bases.extend(base.take()),
+                Token::Ident(word) if word != "is" => base = Some(word.clone()),
+                _ => {}
+            }
+            self.pos += 1;
+        }
+        bases.extend(base);
+        self.pos += 1;
+        let first_test = self.tests.len();
+        while let Some(token) = self.token(self.pos) {
+            match token {
+                Token::Punct('}') => {
+                    self.pos += 1;
+                    let tests = self.tests.split_off(first_test);
+                    self.contracts.push(Contract {
+                        file: String::new(),
+                        name: name.clone(),
+                        runs_tests: is_contract && !is_abstract,
+                        bases,
+                        tests,
+                    });
+                    return;
+                }
+                Token::Punct('{') => {
+                    self.skip_group('{', '}');
+                }
+                Token::Ident(word) if word == "function" => {
+                    // Tests of an abstract contract run in the contracts inheriting them
+                    self.parse_function(name, is_contract)
+                }
+                _ => self.pos += 1,
+            }
+        }
+        self.errors.push(format!(
+            "line {}: contract {} is not closed",
+            self.line(self.pos),
+            name
+        ));
+    }
+
+    fn parse_function(&mut self, contract: &str, runs_tests: bool) {
+        let line = self.line(self.pos);
+        let natspec = match self.pos.checked_sub(1).and_then(|pos| self.token(pos)) {
+            Some(Token::Doc(doc)) => Some(doc),
+            _ => None,
+        };
+        self.pos += 1;
+        let name = match self.token(self.pos) {
+            Some(Token::Ident(name)) => {
+                self.pos += 1;
+                name.clone()
+            }
+            _ => String::new(),
+        };
+        if self.token(self.pos) != Some(&Token::Punct('(')) {
+            self.errors.push(format!(
+                "line {}: function {} has no parameters",
+                line, name
+            ));
+            return;
+        }
+        let params = self.skip_group('(', ')');
+
+        // Modifiers and returns up to the body, or `;` without one
+        let mut visibility = None;
+        let mut body_doc = None;
+        while let Some(token) = self.token(self.pos) {
+            match token {
+                Token::Punct('(') => {
+                    self.skip_group('(', ')');
+                }
+                Token::Punct(';') => {
+                    self.pos += 1;
+                    break;
+                }
+                Token::Punct('{') => {
+                    if let Some(Token::Doc(doc)) = self.token(self.pos + 1) {
+                        body_doc = Some(doc);
+                    }
+                    self.skip_group('{', '}');
+                    break;
+                }
+                Token::Ident(word) if VISIBILITIES.contains(&word.as_str()) => {
+                    visibility = Some(word.clone());
+                    self.pos += 1;
+                }
+                _ => self.pos += 1,
+            }
+        }
+
+        let mut tags: Vec<(String, String)> = vec![];
+        for doc in natspec.into_iter().chain(body_doc) {
+            match parse_tags(doc) {
+                Ok(parsed) => {
+                    for (key, value) in parsed {
+                        if tags.iter().any(|(k, _)| k == &key) {
+                            self.errors
+                                .push(format!("line {}: {}: {} is given twice", line, name, key));
+                        }
+                        tags.push((key, value));
+                    }
+                }
+                Err(err) => self
+                    .errors
+                    .push(format!("line {}: {}: {}", line, name, err)),
+            }
+        }
+
+        let is_test = name.starts_with("test") || name.starts_with("invariant");
+        let visibility = visibility.unwrap_or_else(|| "public".to_string());
+        let runs = runs_tests && is_test && (visibility == "public" || visibility == "external");
+        if !runs {
+            if tags.iter().any(|(key, _)| key == "Score") {
+                self.errors.push(format!(
+                    "line {}: {} is scored but forge never runs it as a test",
+                    line, name
+                ));
+            }
+            return;
+        }
+
+        let mut types = vec![];
+        for param in params.split(|token| token == &&Token::Punct(',')) {
+            if !param.is_empty() {
+                types.push(param_type(param));
+            }
+        }
+        self.tests.push(TestFunction {
+            file: String::new(),
+            contract: contract.to_string(),
+            signature: format!("{}({})", name, types.join(",")),
+            name,
+            visibility,
+            line,
+            tags,
+        });
+    }
+}
+
+// Contracts of a Solidity source, or the problems found, each prefixed with its line
+fn parse_contracts(source: &str) -> Result<Vec<Contract>, Vec<String>> {
+    let tokens = lex(source).map_err(|err| vec![err])?;
+    let mut parser = Parser {
+        tokens: &tokens,
+        pos: 0,
+        contracts: vec![],
+        tests: vec![],
+        errors: vec![],
+    };
+    parser.parse();
+    if parser.errors.is_empty() {
+        Ok(parser.contracts)
+    } else {
+        Err(parser.errors)
+    }
+}
+
+/* inherited_tests
+
+   @dev The tests forge runs: those of every contract it runs with the ones it inherits,
+        each named by the contract running it and its file, as forge names the suite.
+        Bases are looked up in the file of the contract first, those of no test file
+        like forge-std are skipped. A test redefined by a derived contract runs once,
+        the derived one. Also returns the scored tests nothing runs
+
+*/
+fn inherited_tests(contracts: &[Contract]) -> (Vec<TestFunction>, Vec<&TestFunction>) {
+    let find = |file: &str, name: &str| {
+        (0..contracts.len())
+            .filter(|&i| contracts[i].name == name)
+            .min_by_key(|&i| contracts[i].file != file)
+    };
+
+    let mut tests = vec![];
+    // Contract and position of every test declaration some contract runs
+    let mut run = HashSet::new();
+    for (index, contract) in contracts.iter().enumerate() {
+        if !contract.runs_tests {
+            continue;
+        }
+        // Most derived first, the last base listed being the most derived of them
+        let mut lineage: Vec<usize> = vec![];
+        let mut pending = vec![index];
+        while let Some(current) = pending.pop() {
+            if lineage.contains(&current) {
+                continue;
+            }
+            lineage.push(current);
+            let current = &contracts[current];
+            pending.extend(
+                current
+                    .bases
+                    .iter()
+                    .filter_map(|base| find(&current.file, base)),
+            );
+        }
+
+        let mut signatures = HashSet::new();
+        for &current in &lineage {
+            for (i, test) in contracts[current].tests.iter().enumerate() {
+                if signatures.insert(test.signature.as_str()) {
+                    run.insert((current, i));
+                    tests.push(TestFunction {
+                        file: contract.file.clone(),
+                        contract: contract.name.clone(),
+                        ..test.clone()
+                    });
+                }
+            }
+        }
+    }
+
+    let mut unrun = vec![];
+    for (current, contract) in contracts.iter().enumerate() {
+        for (i, test) in contract.tests.iter().enumerate() {
+            let scored = test.tags.iter().any(|(key, _)| key == "Score");
+            if scored && !run.contains(&(current, i)) {
+                unrun.push(test);
+            }
+        }
+    }
+    (tests, unrun)
+}
+
+fn list_sources(dir: &Path, files: &mut Vec<std::path::PathBuf>) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            list_sources(&path, files)?;
+        } else if path.extension().is_some_and(|ext| ext == "sol") {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/* collect_tests
+
+   @dev The test functions forge runs in the `.sol` files under `{base}/test`, in file
+        order, inherited ones included. Problems of all the files are reported together,
+        prefixed with `file:line`
+
+*/
+pub fn collect_tests(base: &Path) -> Result<Vec<TestFunction>, ErrorCode> {
+    let mut files = vec![];
+    list_sources(&base.join("test"), &mut files)
+        .map_err(|err| ErrorCode::ReadFailure(err.to_string()))?;
+    files.sort();
+
+    let mut contracts = vec![];
+    let mut errors = vec![];
+    for path in files {
+        let file = path
+            .strip_prefix(base)
+            .unwrap_or(&path)
+            .to_string_lossy()
+            .into_owned();
+        let source = fs::read(&path).map_err(|err| ErrorCode::ReadFailure(err.to_string()))?;
+        match parse_contracts(&String::from_utf8_lossy(&source)) {
+            Ok(parsed) => contracts.extend(parsed.into_iter().map(|contract| {
+                Contract {
+                    file: file.clone(),
+                    tests: contract
+                        .tests
+                        .into_iter()
+                        .map(|test| TestFunction {
+                            file: file.clone(),
+                            ..test
+                        })
+                        .collect(),
+                    ..contract
+                }
+            })),
+            Err(parsed) => errors.extend(
+                parsed
+                    .into_iter()
+                    .map(|err| format!("{}:{}", file, err.trim_start_matches("line "))),
+            ),
+        }
+    }
+    let (tests, unrun) = inherited_tests(&contracts);
+    for test in unrun {
+        errors.push(format!(
+            "{}:{}: {} is scored but no contract forge runs inherits it",
+            test.file, test.line, test.name
+        ));
+    }
+    // Prerequisites name a test function or a group
+    for test in &tests {
+        let requires = test.tags.iter().filter(|(key, _)| key == "Requires");
+        for name in requires.flat_map(|(_, value)| rubric::requirements(value)) {
+            let known = tests.iter().any(|other| {
+                rubric::names_test(
+                    &name,
+                    &other.file,
+                    &other.contract,
+                    &other.name,
+                    &other.signature,
+                ) || other
+                    .tags
+                    .iter()
+                    .any(|tag| tag.0 == "Group" && tag.1 == name)
+            });
+            if !known {
+                errors.push(format!(
+                    "{}:{}: {} requires {}, which is no test or group",
+                    test.file, test.line, test.name, name
+                ));
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(tests)
+    } else {
+        Err(ErrorCode::InvalidTests(errors))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(source: &str) -> Vec<Token> {
+        lex(source)
+            .unwrap()
+            .into_iter()
+            .map(|lexed| lexed.token)
+            .collect()
+    }
+
+    fn tags(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn only_natspec_comments_are_kept() {
+        let source = "// plain\n/// @custom:score 1\n/// @custom:group deposits\n/* block */\n/** @dev doc */\nx = \"a // b\";";
+        assert_eq!(
+            tokens(source),
+            vec![
+                Token::Doc(String::from(" @custom:score 1\n @custom:group deposits")),
+                Token::Doc(String::from(" @dev doc ")),
+                Token::Ident(String::from("x")),
+                Token::Punct('='),
+                Token::Literal(String::from("\"a // b\"")),
+                Token::Punct(';'),
+            ]
+        );
+    }
+
+    #[test]
+    fn lines_are_counted_through_comments() {
+        let lexed = lex("/* one\n two */\n/// three\n\nfour").unwrap();
+        let lines: Vec<usize> = lexed.iter().map(|lexed| lexed.line).collect();
+        assert_eq!(lines, vec![3, 5]);
+    }
+
+    #[test]
+    fn unterminated_comments_and_strings_are_errors() {
+        assert_eq!(
+            lex("a\n/* open").unwrap_err(),
+            "line 2: unterminated comment"
+        );
+        assert_eq!(
+            lex("\"open\n\"").unwrap_err(),
+            "line 1: unterminated string"
+        );
+    }
+
+    #[test]
+    fn tags_are_read_from_natspec_and_legacy_annotations() {
+        let doc = "\n * @notice Deposits are credited\n * @custom:score 10\n * @custom:requires testDeposit,\n *   testWithdraw\n * @Visibility: hidden\n ";
+        assert_eq!(
+            parse_tags(doc).unwrap(),
+            tags(&[
+                ("Score", "10"),
+                ("Requires", "testDeposit, testWithdraw"),
+                ("Visibility", "hidden"),
+            ])
+        );
+        assert_eq!(
+            parse_tags(" @custom:gas-target 5000\n @custom:gas-ceiling 9000").unwrap(),
+            tags(&[("GasTarget", "5000"), ("GasCeiling", "9000")])
+        );
+    }
+
+    #[test]
+    fn invalid_tags_are_errors() {
+        let err = |doc: &str| parse_tags(doc).unwrap_err();
+        assert_eq!(err(" @scroe 10"), "unknown tag @scroe");
+        assert_eq!(
+            err(" @custom:score -1"),
+            "score -1 is not a number of points"
+        );
+        assert_eq!(err(" @custom:score"), "@custom:score has no value");
+        assert_eq!(err(" @custom:score 1\n @Score: 2"), "Score is given twice");
+        assert_eq!(
+            err(" @custom:gas-ceiling 10"),
+            "gas ceiling without a GasTarget"
+        );
+        assert_eq!(
+            err(" @custom:gas-target 10\n @custom:gas-ceiling 5"),
+            "GasCeiling 5 below GasTarget 10"
+        );
+        assert!(err(" @custom:visibility private").starts_with("visibility private is none of"));
+    }
+
+    #[test]
+    fn test_functions_get_their_signature_and_tags() {
+        let source = r#"
+contract VaultTest {
+    /// @custom:score 5
+    function testFuzzDeposit(uint amount, address[] memory to, IERC20.Kind kind) public {
+        /** @custom:group deposits */
+    }
+
+    function testHelper() internal {}
+
+    function setUp() public {}
+}
+"#;
+        let contracts = parse_contracts(source).unwrap();
+        let (tests, unrun) = inherited_tests(&contracts);
+        assert!(unrun.is_empty());
+        assert_eq!(tests.len(), 1);
+        assert_eq!(tests[0].contract, "VaultTest");
+        assert_eq!(
+            tests[0].signature,
+            "testFuzzDeposit(uint256,address[],IERC20.Kind)"
+        );
+        assert_eq!(tests[0].line, 4);
+        assert_eq!(
+            tests[0].tags,
+            tags(&[("Score", "5"), ("Group", "deposits")])
+        );
+    }
+
+    #[test]
+    fn scored_functions_forge_never_runs_are_errors() {
+        let source = "library L {\n    /// @custom:score 5\n    function testA() public {}\n}\ncontract T {\n    /// @custom:score 5\n    function testB() internal {}\n}\n";
+        assert_eq!(
+            parse_contracts(source).unwrap_err(),
+            vec![
+                "line 3: testA is scored but forge never runs it as a test",
+                "line 7: testB is scored but forge never runs it as a test",
+            ]
+        );
+
+        let abstract_only = "abstract contract Base {\n    /// @custom:score 5\n    function testA() public {}\n}\n";
+        let contracts = parse_contracts(abstract_only).unwrap();
+        let (tests, unrun) = inherited_tests(&contracts);
+        assert!(tests.is_empty());
+        assert_eq!(unrun[0].name, "testA");
+    }
+
+    #[test]
+    fn derived_contracts_run_the_tests_they_inherit_once() {
+        let source = r#"
+abstract contract Base {
+    function testA() public virtual {}
+    function testB() public {}
+}
+abstract contract Middle is Base {
+    function testA() public virtual override {}
+}
+contract Derived is Test, Middle(1) {
+    function testC() public {}
+}
+"#;
+        let contracts = parse_contracts(source).unwrap();
+        assert_eq!(contracts[2].bases, vec!["Test", "Middle"]);
+        let (tests, _) = inherited_tests(&contracts);
+        let run: Vec<(&str, &str, usize)> = tests
+            .iter()
+            .map(|test| (test.contract.as_str(), test.name.as_str(), test.line))
+            .collect();
+        assert_eq!(
+            run,
+            vec![
+                ("Derived", "testC", 10),
+                ("Derived", "testA", 7),
+                ("Derived", "testB", 4)
+            ]
+        );
+    }
+}
@@ -0,0 +1,275 @@
+use regex::Regex;
+use serde_json::{json, Value};
+
+/* Tiers
+
+   @dev Who sees what of a test, set with `@custom:visibility`:
+
+        public  everything, the default
+        hidden  only whether it passed and its points, no name, reason or logs
+        secret  nothing but that it exists, it still counts in the score
+
+        Every test runs in the same forge run, only the result students get is redacted.
+        The full one stays for graders
+
+*/
+pub const VISIBILITIES: [&str; 3] = ["public", "hidden", "secret"];
+
+// What a hidden test keeps, secret ones keep their `Visibility` only
+const HIDDEN_KEEPS: [&str; 6] = [
+    "Visibility",
+    "Passed",
+    "Score",
+    "Earned",
+    "Counted",
+    "Group",
+];
+
+fn visibility(q: &Value) -> &str {
+    q["Visibility"].as_str().unwrap_or("public")
+}
+
+pub fn is_public(q: &Value) -> bool {
+    visibility(q) == "public"
+}
+
+// Tests are told apart by their file, contract and signature, names repeat across contracts
+fn test_key(q: &Value) -> [&str; 4] {
+    ["File", "Contract", "Func", "Signature"].map(|key| q[key].as_str().unwrap_or(""))
+}
+
+/* student_diagnostics
+
+   @dev The compiler diagnostics a student may see: those of the files `submitted`,
+        whose formatted message quotes no other file. solc quotes the source of every
+        file it reports on, the official tests included, so the others only go to
+        graders. Diagnostics without a file are forge's own output and dropped as well
+
+*/
+pub fn student_diagnostics(diagnostics: &Value, submitted: &[String]) -> Value {
+    let quoted_re = Regex::new(r"-->\s*([^\s:]+):").unwrap();
+    let is_submitted = |path: &str| submitted.iter().any(|file| file == path);
+    let kept: Vec<&Value> = diagnostics
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|d| {
+            let formatted = d["formattedMessage"].as_str().unwrap_or("");
+            d["path"].as_str().is_some_and(is_submitted)
+                && quoted_re
+                    .captures_iter(formatted)
+                    .all(|c| is_submitted(&c[1]))
+        })
+        .collect();
+    json!(kept)
+}
+
+/* student_view
+
+   @dev The result of a job as students get it: hidden and secret entries of
+        `questions` redacted, dropped from the groups of the `rubric` and the hidden ones
+        summed up in `hiddenTests`. Only the `diagnostics` of the `submitted` files are
+        kept, a failed build then tells the errors of those alone in `msg`
+
+*/
+pub fn student_view(output: &Value, submitted: &[String]) -> Value {
+    let mut view = output.clone();
+    if let Some(diagnostics) = output.get("diagnostics") {
+        let kept = student_diagnostics(diagnostics, submitted);
+        if output["code"] == 1 && kept != *diagnostics {
+            let errors: Vec<&str> = kept
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter(|d| d["severity"] == "error")
+                .filter_map(|d| d["formattedMessage"].as_str())
+                .map(str::trim_end)
+                .collect();
+            view["msg"] = if errors.is_empty() {
+                json!("Compile failed outside the submitted files")
+            } else {
+                json!(errors.join("\n\n"))
+            };
+        }
+        view["diagnostics"] = kept;
+    }
+    let Some(questions) = view.get_mut("questions").and_then(Value::as_array_mut) else {
+        return view;
+    };
+
+    let (mut hidden, mut hidden_passed) = (0, 0);
+    let mut redacted_tests = vec![];
+    for q in questions.iter_mut() {
+        let keeps: &[&str] = match visibility(q) {
+            "public" => continue,
+            "hidden" => {
+                hidden += 1;
+                if q["Passed"].as_bool().unwrap_or(false) {
+                    hidden_passed += 1;
+                }
+                &HIDDEN_KEEPS
+            }
+            _ => &["Visibility"],
+        };
+        redacted_tests.push(test_key(q).map(str::to_string));
+        let mut redacted = json!({});
+        for key in keeps {
+            if let Some(value) = q.get(*key) {
+                redacted[*key] = value.clone();
+            }
+        }
+        *q = redacted;
+    }
+
+    // Group members are listed by name only, they are listed again from the questions
+    let questions = output["questions"]
+        .as_array()
+        .map_or(&[][..], Vec::as_slice);
+    if let Some(groups) = view["rubric"]["groups"].as_array_mut() {
+        for group in groups {
+            let name = group["name"].as_str();
+            let tests: Vec<&Value> = questions
+                .iter()
+                .filter(|q| q["Group"].as_str() == name)
+                .filter(|q| !redacted_tests.contains(&test_key(q).map(str::to_string)))
+                .map(|q| &q["Func"])
+                .collect();
+            group["tests"] = json!(tests);
+        }
+    }
+    if hidden > 0 {
+        view["hiddenTests"] = json!({ "total": hidden, "passed": hidden_passed });
+    }
+    view
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diagnostics_of_official_tests_stay_with_graders() {
+        let submitted = vec![String::from("contracts/Vault.sol")];
+        let output = json!({
+            "info": "Compile failed",
+            "code": 1,
+            "msg": "TypeError: Member not found.\n --> test/Hidden.t.sol:9:5:\n  |\n9 | vault.secretCheck();\n\nWarning: Unused variable.\n --> contracts/Vault.sol:4:9:",
+            "diagnostics": [
+                {
+                    "severity": "error",
+                    "path": "test/Hidden.t.sol",
+                    "line": 9,
+                    "message": "Member not found.",
+                    "formattedMessage": "TypeError: Member not found.\n --> test/Hidden.t.sol:9:5:\n  |\n9 | vault.secretCheck();\n"
+                },
+                {
+                    "severity": "error",
+                    "path": "contracts/Vault.sol",
+                    "line": 3,
+                    "message": "Identifier already declared.",
+                    "formattedMessage": "DeclarationError: Identifier already declared.\n --> contracts/Vault.sol:3:5:\n\nNote: The previous declaration is here:\n --> test/Hidden.t.sol:2:1:\n"
+                },
+                {
+                    "severity": "warning",
+                    "path": "contracts/Vault.sol",
+                    "line": 4,
+                    "message": "Unused variable.",
+                    "formattedMessage": "Warning: Unused variable.\n --> contracts/Vault.sol:4:9:\n"
+                },
+                {
+                    "severity": "error",
+                    "message": "Error: test/Hidden.t.sol failed",
+                    "formattedMessage": "Error: test/Hidden.t.sol failed"
+                }
+            ]
+        });
+
+        let view = student_view(&output, &submitted);
+        let diagnostics = view["diagnostics"].as_array().unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0]["path"], "contracts/Vault.sol");
+        assert_eq!(diagnostics[0]["severity"], "warning");
+        assert_eq!(view["msg"], "Compile failed outside the submitted files");
+        assert!(!view.to_string().contains("Hidden"));
+        // Graders keep every diagnostic
+        assert_eq!(output["diagnostics"].as_array().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn errors_of_the_submission_are_kept_in_msg() {
+        let submitted = vec![String::from("contracts/Vault.sol")];
+        let output = json!({
+            "code": 1,
+            "msg": "everything",
+            "diagnostics": [
+                {
+                    "severity": "error",
+                    "path": "contracts/Vault.sol",
+                    "formattedMessage": "TypeError: Undeclared identifier.\n --> contracts/Vault.sol:4:9:\n"
+                },
+                {
+                    "severity": "error",
+                    "path": "test/Vault.t.sol",
+                    "formattedMessage": "TypeError: Wrong argument count.\n --> test/Vault.t.sol:7:9:\n"
+                }
+            ]
+        });
+
+        let view = student_view(&output, &submitted);
+        assert_eq!(
+            view["msg"],
+            "TypeError: Undeclared identifier.\n --> contracts/Vault.sol:4:9:"
+        );
+        assert_eq!(view["diagnostics"].as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn hidden_and_secret_tests_are_redacted() {
+        let output = json!({
+            "questions": [
+                { "Func": "testPublic", "Visibility": "public", "Passed": true, "Score": 1 },
+                { "Func": "testHidden", "Visibility": "hidden", "Passed": true, "Score": 2, "Reason": "x" },
+                { "Func": "testSecret", "Visibility": "secret", "Passed": false, "Score": 3 }
+            ],
+            "rubric": { "groups": [{ "name": null, "tests": ["testPublic", "testHidden", "testSecret"] }] }
+        });
+
+        let view = student_view(&output, &[]);
+        assert_eq!(view["questions"][0]["Func"], "testPublic");
+        assert_eq!(
+            view["questions"][1],
+            json!({ "Visibility": "hidden", "Passed": true, "Score": 2 })
+        );
+        assert_eq!(view["questions"][2], json!({ "Visibility": "secret" }));
+        assert_eq!(view["rubric"]["groups"][0]["tests"], json!(["testPublic"]));
+        assert_eq!(view["hiddenTests"], json!({ "total": 1, "passed": 1 }));
+        assert!(view.get("diagnostics").is_none());
+    }
+
+    #[test]
+    fn tests_sharing_a_name_are_redacted_apart() {
+        let test = |contract: &str, visibility: &str| {
+            json!({
+                "File": "test/Vault.t.sol",
+                "Contract": contract,
+                "Func": "testWithdraw",
+                "Signature": "testWithdraw()",
+                "Group": "withdraw",
+                "Visibility": visibility,
+                "Passed": true,
+            })
+        };
+        let output = json!({
+            "questions": [test("PublicTest", "public"), test("HiddenTest", "hidden")],
+            "rubric": { "groups": [{ "name": "withdraw", "tests": ["testWithdraw", "testWithdraw"] }] }
+        });
+
+        let view = student_view(&output, &[]);
+        assert_eq!(view["questions"][0]["Contract"], "PublicTest");
+        assert!(view["questions"][1].get("Contract").is_none());
+        assert_eq!(
+            view["rubric"]["groups"][0]["tests"],
+            json!(["testWithdraw"])
+        );
+    }
+}
@@ -1,6 +1,6 @@
-use serde::{Serialize, Deserialize};
+use serde::{Deserialize, Serialize};
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
-pub struct SubCommandParam{
+pub struct SubCommandParam {
     #[serde(rename = "NAME")]
     pub name: String,
     #[serde(rename = "SHORT_NAME")]
@@ -8,17 +8,17 @@ pub struct SubCommandParam{
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
-pub struct SubCommand{
+pub struct SubCommand {
     #[serde(rename = "COMMAND_NAME")]
     pub command_name: String,
     #[serde[rename = "ABOUT"]]
     pub about: String,
     #[serde[rename = "ARGS"]]
-    pub args: Vec<SubCommandParam>
+    pub args: Vec<SubCommandParam>,
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
-pub struct Conf{
+pub struct Conf {
     #[serde(rename = "COMMAND_NAME")]
     pub command_name: String,
 
@@ -38,5 +38,5 @@ pub struct Conf{
     pub arg_required_else_help: bool,
 
     #[serde[rename="SUB_COMMANDS"]]
-    pub sub_commands: Vec<SubCommand>
-}
\ No newline at end of file
+    pub sub_commands: Vec<SubCommand>,
+}
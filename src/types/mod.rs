@@ -1 +1 @@
-pub mod conf;
\ No newline at end of file
+pub mod conf;